@@ -0,0 +1,128 @@
+// ----------------------------------------------------------------------------
+// Aseprite export
+// ----------------------------------------------------------------------------
+//
+// Writes a single-frame RGBA `.aseprite` file: one layer per exported part, each with
+// one cel holding its baked pixels (zlib-compressed) at their position on the canvas.
+// Layout follows the published file format spec (aseprite/aseprite docs/ase-file-specs.md);
+// all numbers are little-endian.
+
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::RgbaImage;
+use std::io::Write;
+
+const FILE_MAGIC: u16 = 0xA5E0;
+const FRAME_MAGIC: u16 = 0xF1FA;
+const CHUNK_LAYER: u16 = 0x2004;
+const CHUNK_CEL: u16 = 0x2005;
+const CEL_COMPRESSED_IMAGE: u16 = 2;
+const FRAME_DURATION_MS: u16 = 100;
+
+/// One Aseprite layer: its name and, unless empty, a cel at `[x, y]`
+pub struct Layer {
+    pub name: String,
+    pub cel: Option<(RgbaImage, [u32; 2])>,
+}
+
+fn u16_field(value: u32, what: &str) -> Result<u16, String> {
+    u16::try_from(value)
+        .map_err(|_| format!("{} {} is too large for an Aseprite file", what, value))
+}
+
+fn i16_field(value: u32, what: &str) -> Result<i16, String> {
+    i16::try_from(value)
+        .map_err(|_| format!("{} {} is too large for an Aseprite file", what, value))
+}
+
+/// Append a chunk: size (including its 6-byte header), type, data
+fn push_chunk(frame: &mut Vec<u8>, chunk_type: u16, data: &[u8]) {
+    frame.extend_from_slice(&(data.len() as u32 + 6).to_le_bytes());
+    frame.extend_from_slice(&chunk_type.to_le_bytes());
+    frame.extend_from_slice(data);
+}
+
+fn layer_chunk(layer: &Layer) -> Result<Vec<u8>, String> {
+    let flags: u16 = 1 | 2; // visible, editable
+    let name = layer.name.as_bytes();
+    let mut data = Vec::new();
+    data.extend_from_slice(&flags.to_le_bytes());
+    data.extend_from_slice(&0u16.to_le_bytes()); // normal image layer
+    data.extend_from_slice(&0u16.to_le_bytes()); // child level
+    data.extend_from_slice(&[0; 4]); // default width/height, ignored
+    data.extend_from_slice(&0u16.to_le_bytes()); // blend mode: normal
+    data.push(255); // opacity
+    data.extend_from_slice(&[0; 3]);
+    data.extend_from_slice(&u16_field(name.len() as u32, "Layer name length")?.to_le_bytes());
+    data.extend_from_slice(name);
+    Ok(data)
+}
+
+fn cel_chunk(layer_index: u16, img: &RgbaImage, [x, y]: [u32; 2]) -> Result<Vec<u8>, String> {
+    let mut data = Vec::new();
+    data.extend_from_slice(&layer_index.to_le_bytes());
+    data.extend_from_slice(&i16_field(x, "Cel position")?.to_le_bytes());
+    data.extend_from_slice(&i16_field(y, "Cel position")?.to_le_bytes());
+    data.push(255); // opacity
+    data.extend_from_slice(&CEL_COMPRESSED_IMAGE.to_le_bytes());
+    data.extend_from_slice(&0i16.to_le_bytes()); // z-index
+    data.extend_from_slice(&[0; 5]);
+    data.extend_from_slice(&u16_field(img.width(), "Cel width")?.to_le_bytes());
+    data.extend_from_slice(&u16_field(img.height(), "Cel height")?.to_le_bytes());
+    let mut encoder = ZlibEncoder::new(data, Compression::default());
+    encoder
+        .write_all(img.as_raw())
+        .and_then(|()| encoder.finish())
+        .map_err(|e| format!("Failed to compress cel: {}", e))
+}
+
+/// Encode `layers` (bottom to top) on a `width` x `height` canvas
+pub fn encode(width: u32, height: u32, layers: &[Layer]) -> Result<Vec<u8>, String> {
+    let mut chunks = 0u32;
+    let mut frame_body = Vec::new();
+    for layer in layers {
+        push_chunk(&mut frame_body, CHUNK_LAYER, &layer_chunk(layer)?);
+        chunks += 1;
+    }
+    for (index, layer) in layers.iter().enumerate() {
+        if let Some((img, position)) = &layer.cel {
+            let index = u16_field(index as u32, "Layer count")?;
+            push_chunk(
+                &mut frame_body,
+                CHUNK_CEL,
+                &cel_chunk(index, img, *position)?,
+            );
+            chunks += 1;
+        }
+    }
+
+    let mut frame = Vec::new();
+    frame.extend_from_slice(&(frame_body.len() as u32 + 16).to_le_bytes());
+    frame.extend_from_slice(&FRAME_MAGIC.to_le_bytes());
+    frame.extend_from_slice(&u16::try_from(chunks).unwrap_or(0xFFFF).to_le_bytes());
+    frame.extend_from_slice(&FRAME_DURATION_MS.to_le_bytes());
+    frame.extend_from_slice(&[0; 2]);
+    frame.extend_from_slice(&chunks.to_le_bytes());
+    frame.extend_from_slice(&frame_body);
+
+    let mut file = Vec::with_capacity(128 + frame.len());
+    file.extend_from_slice(&(128 + frame.len() as u32).to_le_bytes());
+    file.extend_from_slice(&FILE_MAGIC.to_le_bytes());
+    file.extend_from_slice(&1u16.to_le_bytes()); // frames
+    file.extend_from_slice(&u16_field(width, "Canvas width")?.to_le_bytes());
+    file.extend_from_slice(&u16_field(height, "Canvas height")?.to_le_bytes());
+    file.extend_from_slice(&32u16.to_le_bytes()); // RGBA
+    file.extend_from_slice(&1u32.to_le_bytes()); // layer opacity is valid
+    file.extend_from_slice(&FRAME_DURATION_MS.to_le_bytes()); // deprecated speed
+    file.extend_from_slice(&[0; 8]);
+    file.push(0); // transparent palette index, unused in RGBA
+    file.extend_from_slice(&[0; 3]);
+    file.extend_from_slice(&0u16.to_le_bytes()); // palette colors
+    file.extend_from_slice(&[1, 1]); // pixel aspect ratio
+    file.extend_from_slice(&[0; 4]); // grid position
+    file.extend_from_slice(&16u16.to_le_bytes()); // grid width
+    file.extend_from_slice(&16u16.to_le_bytes()); // grid height
+    file.extend_from_slice(&[0; 84]);
+    file.extend_from_slice(&frame);
+    Ok(file)
+}
@@ -0,0 +1,127 @@
+// ----------------------------------------------------------------------------
+// Sprite atlas (TexturePacker "JSON (Hash)")
+// ----------------------------------------------------------------------------
+//
+// Trimmed layer images are packed onto one sheet with a shelf packer: tallest first,
+// left to right, starting a new shelf when the row is full. No rotation. The layout
+// only depends on the input sizes and order, so the same project packs the same way.
+//
+// The JSON follows TexturePacker's hash format, so existing loaders read it as is:
+//
+//   { "frames": { "body.png": { "frame", "rotated", "trimmed", "spriteSourceSize",
+//                               "sourceSize", "pivot" }, ... },
+//     "meta": { "app", "version", "image", "format", "size", "scale" } }
+
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Transparent pixels kept between packed images so filtering can't bleed across
+pub const PADDING: u32 = 1;
+
+/// Place boxes of `sizes` on a sheet. Returns the sheet size and each box's top-left.
+pub fn pack(sizes: &[[u32; 2]]) -> ([u32; 2], Vec<[u32; 2]>) {
+    let area: u64 = sizes
+        .iter()
+        .map(|[w, h]| (*w + PADDING) as u64 * (*h + PADDING) as u64)
+        .sum();
+    let widest = sizes.iter().map(|[w, _]| *w).max().unwrap_or(1);
+    // Aim for a roughly square sheet, but never narrower than the widest image
+    let sheet_width = ((area as f64).sqrt().ceil() as u32)
+        .next_power_of_two()
+        .max(widest);
+
+    let mut by_height: Vec<usize> = (0..sizes.len()).collect();
+    by_height.sort_by_key(|&i| std::cmp::Reverse(sizes[i][1]));
+
+    let mut positions = vec![[0, 0]; sizes.len()];
+    let (mut x, mut shelf_y, mut shelf_height, mut used_width) = (0, 0, 0, 0);
+    for i in by_height {
+        let [w, h] = sizes[i];
+        if x > 0 && x + w > sheet_width {
+            shelf_y += shelf_height + PADDING;
+            x = 0;
+            shelf_height = 0;
+        }
+        positions[i] = [x, shelf_y];
+        used_width = used_width.max(x + w);
+        x += w + PADDING;
+        shelf_height = shelf_height.max(h);
+    }
+    (
+        [used_width.max(1), (shelf_y + shelf_height).max(1)],
+        positions,
+    )
+}
+
+#[derive(Serialize)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize)]
+pub struct Size {
+    pub w: u32,
+    pub h: u32,
+}
+
+#[derive(Serialize)]
+pub struct Pivot {
+    pub x: f32,
+    pub y: f32,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Frame {
+    pub frame: Rect, // Box on the sheet
+    pub rotated: bool,
+    pub trimmed: bool,
+    pub sprite_source_size: Rect, // Trimmed box within the untrimmed image
+    pub source_size: Size,        // Untrimmed image, i.e. the export canvas
+    pub pivot: Pivot,             // Normalized within `source_size`
+}
+
+#[derive(Serialize)]
+pub struct Meta {
+    pub app: String,
+    pub version: String,
+    pub image: String,
+    pub format: String,
+    pub size: Size,
+    pub scale: String,
+}
+
+#[derive(Serialize)]
+pub struct Atlas {
+    pub frames: BTreeMap<String, Frame>,
+    pub meta: Meta,
+}
+
+/// Godot 4 `AtlasTexture` resource for one frame of the sheet at `image_path`. The margin
+/// restores the trimmed-away border, so the texture keeps the untrimmed size and placement.
+pub fn godot_atlas_texture(image_path: &str, frame: &Frame) -> String {
+    let path = image_path.replace('\\', "\\\\").replace('"', "\\\"");
+    let (f, trim, source) = (&frame.frame, &frame.sprite_source_size, &frame.source_size);
+    format!(
+        "[gd_resource type=\"AtlasTexture\" load_steps=2 format=3]\n\
+         \n\
+         [ext_resource type=\"Texture2D\" path=\"{}\" id=\"1\"]\n\
+         \n\
+         [resource]\n\
+         atlas = ExtResource(\"1\")\n\
+         region = Rect2({}, {}, {}, {})\n\
+         margin = Rect2({}, {}, {}, {})\n",
+        path,
+        f.x,
+        f.y,
+        f.w,
+        f.h,
+        trim.x,
+        trim.y,
+        source.w - f.w,
+        source.h - f.h,
+    )
+}
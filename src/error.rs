@@ -0,0 +1,64 @@
+// ----------------------------------------------------------------------------
+// Errors
+// ----------------------------------------------------------------------------
+//
+// Failures that reach the user: exports and imports return `KitbashError`, and the app
+// shows its message as a toast instead of panicking. The message is the whole UI text,
+// e.g. "Failed to encode PNG: <reason>".
+
+use std::fmt;
+
+#[derive(Debug)]
+pub enum KitbashError {
+    Encode {
+        format: &'static str,
+        source: image::ImageError,
+    },
+    Zip(zip::result::ZipError),
+    Io(std::io::Error),
+    Json(serde_json::Error),
+    Decode {
+        name: String,
+        source: image::ImageError,
+    },
+    Export(String), // Anything else that stops an export, already phrased for the user
+    Cancelled,
+}
+
+impl fmt::Display for KitbashError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KitbashError::Encode { format, source } => {
+                write!(f, "Failed to encode {}: {}", format, source)
+            }
+            KitbashError::Zip(err) => write!(f, "Failed to write ZIP: {}", err),
+            KitbashError::Io(err) => write!(f, "Failed to write export: {}", err),
+            KitbashError::Json(err) => write!(f, "Failed to write JSON: {}", err),
+            KitbashError::Decode { name, source } => {
+                write!(f, "Failed to decode {}: {}", name, source)
+            }
+            KitbashError::Export(message) => f.write_str(message),
+            KitbashError::Cancelled => f.write_str("Export cancelled"),
+        }
+    }
+}
+
+impl std::error::Error for KitbashError {}
+
+impl From<zip::result::ZipError> for KitbashError {
+    fn from(err: zip::result::ZipError) -> Self {
+        KitbashError::Zip(err)
+    }
+}
+
+impl From<std::io::Error> for KitbashError {
+    fn from(err: std::io::Error) -> Self {
+        KitbashError::Io(err)
+    }
+}
+
+impl From<serde_json::Error> for KitbashError {
+    fn from(err: serde_json::Error) -> Self {
+        KitbashError::Json(err)
+    }
+}
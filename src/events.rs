@@ -0,0 +1,167 @@
+// ----------------------------------------------------------------------------
+// App events
+// ----------------------------------------------------------------------------
+//
+// Background tasks (file dialogs, the import worker, ...) report back to the UI by
+// pushing typed `AppEvent`s onto a shared `EventQueue`, which the app drains once per
+// frame and routes to a handler per category.
+//
+// The queue is bounded for progress updates only: once `CAPACITY` events are waiting,
+// new progress events are dropped, while results and completion events are always kept.
+// Events are never reordered, so a completion still arrives after the last progress
+// event that made it in.
+
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+
+/// Waiting events past which progress updates are dropped
+const CAPACITY: usize = 256;
+
+pub enum AppEvent {
+    Import(ImportEvent),
+    Io(IoEvent),
+    Export(ExportEvent),
+}
+
+/// Progress and results of a batch import worker
+pub enum ImportEvent {
+    Queued(Vec<String>), // file names of a picked batch, in import order
+    Started(usize),      // queue index now being read
+    Loaded(String, image::DynamicImage), // name, decoded image
+    Failed(String),      // why a file could not be decoded
+    Skipped(usize),      // queue index dropped by the user
+    Finished,            // queue drained, cancelled or dialog dismissed
+}
+
+/// Files picked in a dialog and read or written in the background
+pub enum IoEvent {
+    ProfileLoaded(Vec<u8>),   // settings profile JSON picked for import
+    VerifyZipLoaded(Vec<u8>), // exported ZIP picked for verification
+    #[cfg(not(target_arch = "wasm32"))]
+    SaveRequested(std::path::PathBuf), // directory confirmed in an export save dialog
+    #[cfg(not(target_arch = "wasm32"))]
+    SaveCompleted(Result<String, String>), // summary of the written files, or why it failed
+}
+
+/// Progress and result of a background export
+pub enum ExportEvent {
+    Progress(f32),                    // fraction done, 0..=1
+    Finished(Vec<(String, Vec<u8>)>), // file name, bytes
+    Failed(String),
+    Cancelled,
+    Estimated(u64, usize, usize), // estimate key, composite PNG bytes, HTML viewer bytes
+    Previewed(u64, image::RgbaImage, usize), // preview key, 1x image as encoded, file bytes
+    Paletted(u64, Vec<[u8; 3]>),  // palette key, limited palette swatches
+}
+
+impl AppEvent {
+    /// Progress is superseded by later events, so it may be dropped under load
+    fn is_progress(&self) -> bool {
+        matches!(
+            self,
+            AppEvent::Import(ImportEvent::Started(_)) | AppEvent::Export(ExportEvent::Progress(_))
+        )
+    }
+}
+
+/// Multi-producer event queue; clones share the same queue
+#[derive(Clone, Default)]
+pub struct EventQueue {
+    events: Arc<Mutex<VecDeque<AppEvent>>>,
+}
+
+impl EventQueue {
+    pub fn send(&self, event: AppEvent) {
+        let Ok(mut events) = self.events.lock() else {
+            return;
+        };
+        if events.len() >= CAPACITY && event.is_progress() {
+            return;
+        }
+        events.push_back(event);
+    }
+
+    /// Take every waiting event, oldest first
+    pub fn drain(&self) -> VecDeque<AppEvent> {
+        self.events
+            .lock()
+            .map(|mut events| std::mem::take(&mut *events))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progress(fraction: f32) -> AppEvent {
+        AppEvent::Export(ExportEvent::Progress(fraction))
+    }
+
+    #[test]
+    fn progress_is_dropped_past_capacity_but_results_are_kept() {
+        let queue = EventQueue::default();
+        for i in 0..CAPACITY + 10 {
+            queue.send(progress(i as f32));
+        }
+        queue.send(AppEvent::Import(ImportEvent::Started(0)));
+        queue.send(AppEvent::Import(ImportEvent::Failed("bad.png".to_owned())));
+        queue.send(AppEvent::Export(ExportEvent::Cancelled));
+        queue.send(progress(1.0));
+        queue.send(AppEvent::Import(ImportEvent::Finished));
+
+        let events = queue.drain();
+        assert_eq!(events.len(), CAPACITY + 3);
+        let kept = events.iter().filter(|e| e.is_progress()).count();
+        assert_eq!(kept, CAPACITY);
+        let tail: Vec<_> = events.iter().skip(CAPACITY).collect();
+        assert!(matches!(
+            tail[..],
+            [
+                AppEvent::Import(ImportEvent::Failed(_)),
+                AppEvent::Export(ExportEvent::Cancelled),
+                AppEvent::Import(ImportEvent::Finished),
+            ]
+        ));
+
+        // Draining makes room again
+        queue.send(progress(0.5));
+        assert_eq!(queue.drain().len(), 1);
+    }
+
+    #[test]
+    fn completion_arrives_after_the_last_progress() {
+        let queue = EventQueue::default();
+        let worker = {
+            let queue = queue.clone();
+            std::thread::spawn(move || {
+                for i in 0..=1000 {
+                    queue.send(progress(i as f32 / 1000.0));
+                }
+                queue.send(AppEvent::Export(ExportEvent::Finished(Vec::new())));
+            })
+        };
+
+        // Drain while the worker is still sending, like the UI does once per frame
+        let mut events = Vec::new();
+        while !worker.is_finished() {
+            events.extend(queue.drain());
+        }
+        worker.join().unwrap();
+        events.extend(queue.drain());
+
+        let finished = events
+            .iter()
+            .position(|e| matches!(e, AppEvent::Export(ExportEvent::Finished(_))))
+            .expect("completion event");
+        assert_eq!(finished, events.len() - 1);
+        let fractions: Vec<f32> = events
+            .iter()
+            .filter_map(|e| match e {
+                AppEvent::Export(ExportEvent::Progress(f)) => Some(*f),
+                _ => None,
+            })
+            .collect();
+        assert!(fractions.windows(2).all(|w| w[0] < w[1]));
+    }
+}
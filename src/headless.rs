@@ -0,0 +1,186 @@
+// ----------------------------------------------------------------------------
+// Headless JSON command server (native only)
+// ----------------------------------------------------------------------------
+//
+// `kitbash --serve-stdio` reads one JSON request per line from stdin and answers
+// each with one JSON line on stdout, without creating a window:
+//
+//   {"id": 1, "method": "add_layer", "params": {"path": "body.png"}}
+//   {"id": 1, "ok": true, "result": {"id": 0, "name": "body.png"}}
+//
+// Methods: add_layer, list_layers, set_canvas, set_visible, set_offset, composite, quit.
+// Layers are addressed by name; the first layer with that name wins.
+
+use crate::{
+    composite_image, KitbashApp, LayerImage, MAX_CANVAS_SIZE, MAX_EXPORT_SCALE, MIN_CANVAS_SIZE,
+};
+use eframe::egui;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::{BufRead, Write};
+
+#[derive(Deserialize)]
+struct Request {
+    #[serde(default)]
+    id: Value,
+    method: String,
+    #[serde(default)]
+    params: Value,
+}
+
+/// What to do after answering a request
+enum Flow {
+    Continue,
+    Quit,
+}
+
+pub fn serve_stdio() {
+    let mut app = KitbashApp::default();
+    let stdin = std::io::stdin();
+    let mut stdout = std::io::stdout();
+
+    for line in stdin.lock().lines() {
+        let Ok(line) = line else { break };
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let (response, flow) = match serde_json::from_str::<Request>(&line) {
+            Ok(request) => {
+                let (result, flow) = execute(&mut app, &request.method, &request.params);
+                (respond(request.id, result), flow)
+            }
+            Err(err) => (
+                respond(Value::Null, Err(format!("Invalid request: {}", err))),
+                Flow::Continue,
+            ),
+        };
+
+        if writeln!(stdout, "{}", response)
+            .and_then(|_| stdout.flush())
+            .is_err()
+        {
+            break;
+        }
+        if let Flow::Quit = flow {
+            break;
+        }
+    }
+}
+
+fn respond(id: Value, result: Result<Value, String>) -> Value {
+    match result {
+        Ok(result) => json!({ "id": id, "ok": true, "result": result }),
+        Err(error) => json!({ "id": id, "ok": false, "error": error }),
+    }
+}
+
+fn execute(app: &mut KitbashApp, method: &str, params: &Value) -> (Result<Value, String>, Flow) {
+    let result = match method {
+        "add_layer" => add_layer(app, params),
+        "list_layers" => Ok(app
+            .layers
+            .iter()
+            .map(|l| json!({ "id": l.id, "name": l.name, "visible": l.visible }))
+            .collect()),
+        "set_canvas" => set_canvas(app, params),
+        "set_visible" => set_visible(app, params),
+        "set_offset" => set_offset(app, params),
+        "composite" => composite(app, params),
+        "quit" => return (Ok(Value::Null), Flow::Quit),
+        _ => Err(format!("Unknown method: {}", method)),
+    };
+    (result, Flow::Continue)
+}
+
+fn str_param<'a>(params: &'a Value, key: &str) -> Result<&'a str, String> {
+    params[key]
+        .as_str()
+        .ok_or_else(|| format!("Missing string param '{}'", key))
+}
+
+fn f32_param(params: &Value, key: &str) -> Result<f32, String> {
+    params[key]
+        .as_f64()
+        .map(|v| v as f32)
+        .ok_or_else(|| format!("Missing number param '{}'", key))
+}
+
+fn u32_param(params: &Value, key: &str) -> Result<u32, String> {
+    params[key]
+        .as_u64()
+        .and_then(|v| u32::try_from(v).ok())
+        .ok_or_else(|| format!("Missing integer param '{}'", key))
+}
+
+fn find_layer<'a>(app: &'a mut KitbashApp, params: &Value) -> Result<&'a mut LayerImage, String> {
+    let name = str_param(params, "name")?;
+    app.layers
+        .iter_mut()
+        .find(|l| l.name == name)
+        .ok_or_else(|| format!("No layer named '{}'", name))
+}
+
+fn add_layer(app: &mut KitbashApp, params: &Value) -> Result<Value, String> {
+    let path = str_param(params, "path")?;
+    let img = image::open(path).map_err(|e| format!("Failed to load {}: {}", path, e))?;
+    let name = match params["name"].as_str() {
+        Some(name) => name.to_owned(),
+        None => std::path::Path::new(path)
+            .file_name()
+            .map(|n| n.to_string_lossy().into_owned())
+            .unwrap_or_else(|| path.to_owned()),
+    };
+
+    let id = app.allocate_id();
+    app.layers.push(LayerImage::new(id, name.clone(), img));
+    Ok(json!({ "id": id, "name": name }))
+}
+
+fn set_canvas(app: &mut KitbashApp, params: &Value) -> Result<Value, String> {
+    let width = u32_param(params, "width")?;
+    let height = u32_param(params, "height")?;
+    // Same bounds as the canvas size fields in the UI
+    let range = MIN_CANVAS_SIZE..=MAX_CANVAS_SIZE;
+    if !range.contains(&width) || !range.contains(&height) {
+        return Err(format!(
+            "Canvas size must be {} to {} pixels",
+            MIN_CANVAS_SIZE, MAX_CANVAS_SIZE
+        ));
+    }
+    app.canvas_size = [width, height];
+    Ok(Value::Null)
+}
+
+fn set_visible(app: &mut KitbashApp, params: &Value) -> Result<Value, String> {
+    let visible = params["visible"]
+        .as_bool()
+        .ok_or("Missing bool param 'visible'")?;
+    find_layer(app, params)?.visible = visible;
+    Ok(Value::Null)
+}
+
+fn set_offset(app: &mut KitbashApp, params: &Value) -> Result<Value, String> {
+    let offset = egui::vec2(f32_param(params, "x")?, f32_param(params, "y")?);
+    find_layer(app, params)?.transform.offset = offset;
+    Ok(Value::Null)
+}
+
+fn composite(app: &mut KitbashApp, params: &Value) -> Result<Value, String> {
+    let path = str_param(params, "path")?;
+    let scale = match params.get("scale") {
+        Some(_) => u32_param(params, "scale")?.clamp(1, MAX_EXPORT_SCALE),
+        None => app.export_scale,
+    };
+
+    let img = composite_image(
+        app.canvas_size,
+        &app.layers,
+        scale,
+        app.bg_color,
+        &app.output_effects,
+    );
+    img.save_with_format(path, image::ImageFormat::Png)
+        .map_err(|e| format!("Failed to write {}: {}", path, e))?;
+    Ok(json!({ "path": path, "width": img.width(), "height": img.height() }))
+}
@@ -0,0 +1,205 @@
+// ----------------------------------------------------------------------------
+// Logging
+// ----------------------------------------------------------------------------
+//
+// Every `log` record goes to the platform logger (env_logger on native, the browser
+// console on web) and into a small in-memory ring buffer that the debug console shows.
+// On native, records are also appended to `kitbash.log` in the data dir, rotated by size.
+
+use std::collections::VecDeque;
+use std::sync::Mutex;
+
+/// Records kept for the debug console
+const RING_CAPACITY: usize = 2000;
+
+/// One captured log record
+#[derive(Clone)]
+pub struct LogEntry {
+    pub time_secs: f64,
+    pub level: log::Level,
+    pub target: String,
+    pub message: String,
+}
+
+impl LogEntry {
+    pub fn format(&self) -> String {
+        format!(
+            "[{:>9.3}] {:<5} {}: {}",
+            self.time_secs, self.level, self.target, self.message
+        )
+    }
+}
+
+static RING: Mutex<VecDeque<LogEntry>> = Mutex::new(VecDeque::new());
+
+/// Snapshot of the recent records, oldest first
+pub fn recent() -> Vec<LogEntry> {
+    RING.lock()
+        .map(|ring| ring.iter().cloned().collect())
+        .unwrap_or_default()
+}
+
+pub fn clear() {
+    if let Ok(mut ring) = RING.lock() {
+        ring.clear();
+    }
+}
+
+/// Seconds since the logger was installed
+fn elapsed_secs() -> f64 {
+    #[cfg(not(target_arch = "wasm32"))]
+    {
+        static START: std::sync::OnceLock<std::time::Instant> = std::sync::OnceLock::new();
+        START
+            .get_or_init(std::time::Instant::now)
+            .elapsed()
+            .as_secs_f64()
+    }
+    #[cfg(target_arch = "wasm32")]
+    {
+        static START: std::sync::OnceLock<f64> = std::sync::OnceLock::new();
+        let now = js_sys::Date::now() / 1000.0;
+        now - START.get_or_init(|| now)
+    }
+}
+
+struct RingLogger {
+    inner: Box<dyn log::Log>,
+    #[cfg(not(target_arch = "wasm32"))]
+    file: Option<Mutex<file::RotatingFile>>,
+}
+
+impl log::Log for RingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        // Our own records are always captured, even if the platform logger filters them
+        metadata.target().starts_with("kitbash") || self.inner.enabled(metadata)
+    }
+
+    fn log(&self, record: &log::Record) {
+        if !self.enabled(record.metadata()) {
+            return;
+        }
+        self.inner.log(record);
+
+        let entry = LogEntry {
+            time_secs: elapsed_secs(),
+            level: record.level(),
+            target: record.target().to_owned(),
+            message: record.args().to_string(),
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        if let Some(file) = &self.file {
+            if let Ok(mut file) = file.lock() {
+                file.write_line(&entry.format());
+            }
+        }
+        if let Ok(mut ring) = RING.lock() {
+            if ring.len() == RING_CAPACITY {
+                ring.pop_front();
+            }
+            ring.push_back(entry);
+        }
+    }
+
+    fn flush(&self) {
+        self.inner.flush();
+    }
+}
+
+/// Install the logger; call once at startup
+pub fn init() {
+    #[cfg(not(target_arch = "wasm32"))]
+    let logger = RingLogger {
+        inner: Box::new(env_logger::Builder::from_default_env().build()),
+        file: file::RotatingFile::open().map(Mutex::new),
+    };
+    #[cfg(target_arch = "wasm32")]
+    let logger = RingLogger {
+        inner: Box::new(eframe::WebLogger::new(log::LevelFilter::Debug)),
+    };
+
+    elapsed_secs();
+    if log::set_boxed_logger(Box::new(logger)).is_ok() {
+        log::set_max_level(log::LevelFilter::Trace);
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+mod file {
+    use std::io::Write;
+    use std::path::PathBuf;
+
+    /// Rotate once the log grows past this many bytes
+    const MAX_BYTES: u64 = 1024 * 1024;
+    /// Rotated logs kept next to the current one (`kitbash.log.1` is the newest)
+    const KEEP: usize = 3;
+
+    pub struct RotatingFile {
+        path: PathBuf,
+        file: std::fs::File,
+        written: u64,
+    }
+
+    /// Per-user data dir, without pulling in a crate for it
+    fn data_dir() -> Option<PathBuf> {
+        let var = |key| {
+            std::env::var_os(key)
+                .filter(|v| !v.is_empty())
+                .map(PathBuf::from)
+        };
+        let base = if cfg!(windows) {
+            var("APPDATA")?
+        } else if cfg!(target_os = "macos") {
+            var("HOME")?.join("Library/Application Support")
+        } else {
+            var("XDG_DATA_HOME").or_else(|| Some(var("HOME")?.join(".local/share")))?
+        };
+        Some(base.join("kitbash"))
+    }
+
+    fn rotated(path: &std::path::Path, n: usize) -> PathBuf {
+        let mut name = path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    impl RotatingFile {
+        pub fn open() -> Option<Self> {
+            let dir = data_dir()?;
+            std::fs::create_dir_all(&dir).ok()?;
+            let path = dir.join("kitbash.log");
+            let file = std::fs::OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .ok()?;
+            let written = file.metadata().map(|m| m.len()).unwrap_or(0);
+            Some(Self {
+                path,
+                file,
+                written,
+            })
+        }
+
+        pub fn write_line(&mut self, line: &str) {
+            if self.written >= MAX_BYTES {
+                self.rotate();
+            }
+            // Logging must never take the app down, so write errors are dropped
+            if writeln!(self.file, "{}", line).is_ok() {
+                self.written += line.len() as u64 + 1;
+            }
+        }
+
+        fn rotate(&mut self) {
+            for n in (1..KEEP).rev() {
+                let _ = std::fs::rename(rotated(&self.path, n), rotated(&self.path, n + 1));
+            }
+            let _ = std::fs::rename(&self.path, rotated(&self.path, 1));
+            if let Ok(file) = std::fs::File::create(&self.path) {
+                self.file = file;
+                self.written = 0;
+            }
+        }
+    }
+}
@@ -1,544 +1,9133 @@
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")] // hide console window on Windows in release
 
+mod aseprite;
+mod atlas;
+mod error;
+mod events;
+#[cfg(not(target_arch = "wasm32"))]
+mod headless;
+mod logging;
+mod metadata;
+mod palette;
+#[cfg(test)]
+mod tests;
+
 use eframe::egui;
+use error::KitbashError;
+use events::{AppEvent, EventQueue, ExportEvent, ImportEvent, IoEvent};
+use futures::channel::mpsc::{unbounded, TryRecvError, UnboundedReceiver, UnboundedSender};
+use futures::StreamExt;
 use image::{imageops::FilterType, Rgba, RgbaImage};
+use metadata::{LayerMeta, MetaNode};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashSet, VecDeque};
 use std::io::{Cursor, Write};
-use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
 
 // ----------------------------------------------------------------------------
 // Data Structures
 // ----------------------------------------------------------------------------
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq)]
 struct Transform {
     offset: egui::Vec2,
-    scale: f32,
+    scale_x: f32,
+    scale_y: f32,
+    anchor: egui::Vec2, // Normalized point of the image that stays fixed while scaling
+    flip_h: bool,
 }
 
 impl Default for Transform {
     fn default() -> Self {
         Self {
             offset: egui::Vec2::ZERO,
-            scale: 1.0,
+            scale_x: 1.0,
+            scale_y: 1.0,
+            anchor: egui::Vec2::ZERO, // top-left
+            flip_h: false,
         }
     }
 }
 
+#[derive(Clone)]
 struct LayerImage {
     id: u64,
     name: String,
-    source_image: image::DynamicImage,
+    source_image: Arc<image::DynamicImage>, // Shared between duplicates
+    source_revision: u64, // Changes with every new `source_image`, keys pixel caches
     texture: Option<egui::TextureHandle>,
+    thumb_texture: Option<egui::TextureHandle>, // Layer list thumbnail of the source image
     transform: Transform,
     visible: bool,
+    z_override: Option<i32>, // Draw above/below regardless of list position
+    tint: egui::Color32,     // Multiplied into every pixel, WHITE = unchanged
+    locked: bool,            // Excluded from canvas dragging and transform edits
+    adjust: ColorAdjust,     // Non-destructive hue/saturation/lightness
+    tone: ToneAdjust,
+    outline: Outline,
+    shadow: Shadow,
+    clip_to_below: bool, // Clipping mask: only visible where the layer below is opaque
+    label_color: Option<egui::Color32>, // Organizational tag only, never rendered into output
+    user_data: BTreeMap<String, String>, // Free-form engine data, exported as-is
+    is_reference: bool,  // Tracing aid: shown faded in the preview, never exported
+    texture_key: u64,    // `effects_key` the cached texture was built with
+    texture_options: egui::TextureOptions, // Sampling the cached texture was uploaded with
 }
 
-enum AppMessage {
-    ImageLoaded(String, Vec<u8>), // name, bytes
+/// Rebindable keyboard shortcuts
+struct Keybindings {
+    duplicate_layer: egui::KeyboardShortcut,
+    undo: egui::KeyboardShortcut,
+    redo: egui::KeyboardShortcut,
+    place_at_click: egui::KeyboardShortcut,
+    paste_replace: egui::KeyboardShortcut,
 }
 
-struct KitbashApp {
-    // Canvas Config
-    canvas_size: [u32; 2],
-    bg_color: egui::Color32,
-    export_scale: u32, // New: Export multiplier
+impl Default for Keybindings {
+    fn default() -> Self {
+        Self {
+            duplicate_layer: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::D),
+            undo: egui::KeyboardShortcut::new(egui::Modifiers::COMMAND, egui::Key::Z),
+            redo: egui::KeyboardShortcut::new(
+                egui::Modifiers::COMMAND.plus(egui::Modifiers::SHIFT),
+                egui::Key::Z,
+            ),
+            place_at_click: egui::KeyboardShortcut::new(egui::Modifiers::NONE, egui::Key::P),
+            // Not Ctrl+Shift+V: egui-winit turns every Ctrl+V combination into a text paste
+            // event, and drops it entirely when the clipboard holds only an image
+            paste_replace: egui::KeyboardShortcut::new(egui::Modifiers::ALT, egui::Key::V),
+        }
+    }
+}
 
-    // State
-    layers: Vec<LayerImage>, // Flat list again
-    selected_layer_id: Option<u64>,
-    next_id: u64,
+impl Keybindings {
+    /// Every binding with the stable action name used in settings profiles
+    fn entries_mut(&mut self) -> [(&'static str, &mut egui::KeyboardShortcut); 5] {
+        [
+            ("duplicate_layer", &mut self.duplicate_layer),
+            ("undo", &mut self.undo),
+            ("redo", &mut self.redo),
+            ("place_at_click", &mut self.place_at_click),
+            ("paste_replace", &mut self.paste_replace),
+        ]
+    }
+}
 
-    // Async Communication
-    msg_sender: Sender<AppMessage>,
-    msg_receiver: Receiver<AppMessage>,
+/// Portable user settings (no project data), exchanged as JSON between machines/builds.
+///
+/// Every section is optional and unknown keys are ignored, so profiles written by newer
+/// versions still import: whatever this version understands is applied, the rest is skipped.
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct SettingsProfile {
+    version: u32,
+    keybindings: BTreeMap<String, String>, // action -> "Ctrl+Shift+D"
+    ui: UiPreferences,
+    export: ExportPreferences,
+    colors: ColorPreferences,
+}
 
-    // UI State
-    preview_zoom: f32,
-    canvas_pan: egui::Vec2, // New: Canvas panning
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct UiPreferences {
+    preview_zoom: Option<f32>,
+    reduce_motion: Option<bool>,
 }
 
-impl Default for KitbashApp {
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct ColorPreferences {
+    bg_history: Option<Vec<String>>, // "#rrggbbaa", most recent first
+    saved_colors: Option<BTreeMap<String, String>>, // name -> "#rrggbbaa"
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(default)]
+struct ExportPreferences {
+    export_scale: Option<u32>,
+    filename_template: Option<String>,
+    tight: Option<bool>,
+    zip_max_entries: Option<usize>, // 0 = never split
+    sources: Option<bool>,
+    background: Option<ExportBackground>,
+}
+
+const SETTINGS_PROFILE_VERSION: u32 = 1;
+
+/// Background colors: recently used ones plus user-named swatches, remembered between
+/// sessions
+#[derive(Default, Serialize, Deserialize)]
+#[serde(default)]
+struct BgPalette {
+    history: Vec<egui::Color32>, // Most recent first
+    saved: BTreeMap<String, egui::Color32>,
+    #[serde(skip)]
+    new_name: String, // Name field for saving the current color
+}
+
+impl BgPalette {
+    const HISTORY_LEN: usize = 8;
+
+    /// Move `color` to the front of the history
+    fn note_used(&mut self, color: egui::Color32) {
+        if self.history.first() == Some(&color) {
+            return;
+        }
+        self.history.retain(|&c| c != color);
+        self.history.insert(0, color);
+        self.history.truncate(Self::HISTORY_LEN);
+    }
+
+    /// Color picker, recent colors and saved swatches for `bg`
+    fn ui(&mut self, ui: &mut egui::Ui, bg: &mut egui::Color32) {
+        ui.horizontal(|ui| {
+            ui.label("BG Color:");
+            ui.color_edit_button_srgba(bg);
+            // Record a color once the picker is let go, not every step of a drag
+            if !ui.input(|i| i.pointer.any_down()) {
+                self.note_used(*bg);
+            }
+        });
+        ui.horizontal_wrapped(|ui| {
+            for &color in self.history.iter().skip(1) {
+                if swatch(ui, color).on_hover_text(color.to_hex()).clicked() {
+                    *bg = color;
+                }
+            }
+        });
+
+        let mut remove = None;
+        ui.horizontal_wrapped(|ui| {
+            for (name, &color) in &self.saved {
+                let response = swatch(ui, color).on_hover_text(name);
+                if response.clicked() {
+                    *bg = color;
+                }
+                response.context_menu(|ui| {
+                    if ui.button("Remove").clicked() {
+                        remove = Some(name.clone());
+                        ui.close_menu();
+                    }
+                });
+            }
+        });
+        if let Some(name) = remove {
+            self.saved.remove(&name);
+        }
+        ui.horizontal(|ui| {
+            ui.add(
+                egui::TextEdit::singleline(&mut self.new_name)
+                    .hint_text("Color name")
+                    .desired_width(100.0),
+            );
+            let name = self.new_name.trim();
+            if ui
+                .add_enabled(!name.is_empty(), egui::Button::new("Save Color"))
+                .clicked()
+            {
+                self.saved.insert(name.to_owned(), *bg);
+                self.new_name.clear();
+            }
+        });
+    }
+}
+
+/// Clickable color chip, checkered behind translucent colors
+fn swatch(ui: &mut egui::Ui, color: egui::Color32) -> egui::Response {
+    let (rect, response) = ui.allocate_exact_size(egui::vec2(16.0, 16.0), egui::Sense::click());
+    let painter = ui.painter();
+    if color.a() < 255 {
+        let half = rect.size() / 2.0;
+        painter.rect_filled(rect, 2.0, egui::Color32::WHITE);
+        painter.rect_filled(
+            egui::Rect::from_min_size(rect.min, half),
+            0.0,
+            egui::Color32::LIGHT_GRAY,
+        );
+        painter.rect_filled(
+            egui::Rect::from_min_size(rect.min + half, half),
+            0.0,
+            egui::Color32::LIGHT_GRAY,
+        );
+    }
+    painter.rect_filled(rect, 2.0, color);
+    let stroke = if response.hovered() {
+        ui.visuals().widgets.hovered.fg_stroke
+    } else {
+        ui.visuals().widgets.noninteractive.bg_stroke
+    };
+    painter.rect_stroke(rect, 2.0, stroke);
+    response
+}
+
+/// Keep the previous file as `name.bak-{timestamp}` before an export overwrites it (native
+/// only), remembered between sessions
+#[derive(Clone, Copy, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(default)]
+struct BackupPolicy {
+    enabled: bool,
+    keep: usize, // Newest backups kept per file, older ones are pruned
+}
+
+impl Default for BackupPolicy {
     fn default() -> Self {
-        let (sender, receiver) = channel();
         Self {
-            canvas_size: [64, 64],
-            bg_color: egui::Color32::TRANSPARENT,
-            export_scale: 1,
-            layers: Vec::new(),
-            selected_layer_id: None,
-            next_id: 0,
-            msg_sender: sender,
-            msg_receiver: receiver,
-            preview_zoom: 4.0,
-            canvas_pan: egui::Vec2::ZERO,
+            enabled: false,
+            keep: 5,
         }
     }
 }
 
-// ----------------------------------------------------------------------------
-// Helper Functions
-// ----------------------------------------------------------------------------
+/// Parameters of the Scatter dialog: N seeded copies of one layer spread over an area
+struct ScatterSettings {
+    source_id: u64,
+    count: u32,
+    seed: u64,
+    area: egui::Rect, // Canvas pixels the copies' top-left corners are spread over
+    scale_range: [f32; 2], // Random factor applied to the source scale
+    flip_chance: f32, // 0..1 chance of toggling flip_h
+}
 
-/// Render a single layer to a buffer (full canvas size)
-fn render_single_layer(
-    canvas_size: [u32; 2],
-    layer: &LayerImage,
-    export_scale: u32,
-) -> Option<RgbaImage> {
-    if !layer.visible {
-        return None;
+/// One generated copy of a scatter
+struct ScatterPlacement {
+    offset: egui::Vec2,
+    scale: f32,
+    flip: bool,
+}
+
+impl ScatterPlacement {
+    fn apply(&self, layer: &mut LayerImage) {
+        layer.transform.offset = self.offset;
+        layer.transform.scale_x *= self.scale;
+        layer.transform.scale_y *= self.scale;
+        layer.transform.flip_h ^= self.flip;
     }
+}
 
-    let scale_f = export_scale as f32;
-    let width = canvas_size[0] * export_scale;
-    let height = canvas_size[1] * export_scale;
+impl ScatterSettings {
+    fn new(source_id: u64, canvas_size: [u32; 2]) -> Self {
+        Self {
+            source_id,
+            count: 10,
+            seed: 1,
+            area: egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(canvas_size[0] as f32, canvas_size[1] as f32),
+            ),
+            scale_range: [1.0, 1.0],
+            flip_chance: 0.0,
+        }
+    }
 
-    let mut buffer = RgbaImage::new(width, height);
-    // Note: Individual layers are transparent background by default
+    /// Deterministic placements: the same seed and parameters always give the same result
+    fn placements(&self, source: &LayerImage) -> Vec<ScatterPlacement> {
+        let mut rng = SplitMix64(self.seed);
+        let [min_scale, max_scale] = self.scale_range;
+        (0..self.count)
+            .map(|_| {
+                let scale = rng.range(min_scale, max_scale.max(min_scale));
+                // Keep the scaled copy inside the area where it fits
+                let size = source.source_size()
+                    * egui::vec2(source.transform.scale_x, source.transform.scale_y)
+                    * scale;
+                let max = (self.area.max - size).max(self.area.min);
+                let x = rng.range(self.area.min.x, max.x).round();
+                let y = rng.range(self.area.min.y, max.y).round();
+                let flip = rng.next_f32() < self.flip_chance;
+                ScatterPlacement {
+                    offset: egui::vec2(x, y),
+                    scale,
+                    flip,
+                }
+            })
+            .collect()
+    }
+}
 
-    let src_width = layer.source_image.width();
-    let src_height = layer.source_image.height();
+/// Tiny seeded PRNG (SplitMix64) so generated layouts are reproducible
+struct SplitMix64(u64);
 
-    let final_scale = layer.transform.scale * scale_f;
+impl SplitMix64 {
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
 
-    let target_width = (src_width as f32 * final_scale).round() as u32;
-    let target_height = (src_height as f32 * final_scale).round() as u32;
+    /// Uniform in [0, 1)
+    fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
 
-    if target_width == 0 || target_height == 0 {
-        return Some(buffer);
+    fn range(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.next_f32()
+    }
+}
+
+/// Per-layer HSL adjustment: hue in degrees, saturation/lightness in percent (-100..100)
+#[derive(Clone, Copy, Default, PartialEq)]
+struct ColorAdjust {
+    hue: f32,
+    saturation: f32,
+    lightness: f32,
+}
+
+impl ColorAdjust {
+    fn is_identity(&self) -> bool {
+        *self == Self::default()
     }
 
-    let resized = layer
-        .source_image
-        .resize_exact(target_width, target_height, FilterType::Nearest);
+    fn hash_into(&self, hasher: &mut Fnv64) {
+        for value in [self.hue, self.saturation, self.lightness] {
+            hasher.write_u32(value.to_bits());
+        }
+    }
 
-    let x = (layer.transform.offset.x * scale_f).round() as i64;
-    let y = (layer.transform.offset.y * scale_f).round() as i64;
+    fn apply(&self, image: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+        let saturation = self.saturation / 100.0;
+        let lightness = self.lightness / 100.0;
+        for pixel in image.pixels_mut() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let [r, g, b, _] = pixel.0.map(|c| c as f32 / 255.0);
+            let (mut h, mut s, mut l) = rgb_to_hsl(r, g, b);
 
-    image::imageops::overlay(&mut buffer, &resized, x, y);
+            h = (h + self.hue / 360.0).rem_euclid(1.0);
+            s = (s * (1.0 + saturation)).clamp(0.0, 1.0);
+            l = if lightness >= 0.0 {
+                l + (1.0 - l) * lightness
+            } else {
+                l * (1.0 + lightness)
+            };
 
-    Some(buffer)
+            let (r, g, b) = hsl_to_rgb(h, s, l.clamp(0.0, 1.0));
+            pixel[0] = (r * 255.0).round() as u8;
+            pixel[1] = (g * 255.0).round() as u8;
+            pixel[2] = (b * 255.0).round() as u8;
+        }
+    }
 }
 
-#[cfg(target_arch = "wasm32")]
-fn trigger_download(filename: &str, data: &[u8]) {
-    use wasm_bindgen::JsCast;
-    use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+/// Canvas-wide effects applied after compositing, for "what if" mockups
+#[derive(Clone, Copy, PartialEq)]
+struct OutputEffects {
+    pixelate: u32,  // Block size in canvas pixels, 1 = off
+    posterize: u32, // Levels per channel, 0 = off
+    preview: bool,  // Show them in the canvas preview too
+}
 
-    let window = web_sys::window().unwrap();
-    let document = window.document().unwrap();
-    let body = document.body().unwrap();
+impl Default for OutputEffects {
+    fn default() -> Self {
+        Self {
+            pixelate: 1,
+            posterize: 0,
+            preview: true,
+        }
+    }
+}
 
-    let array = js_sys::Uint8Array::from(data);
-    let parts = js_sys::Array::new();
-    parts.push(&array);
+impl OutputEffects {
+    fn is_active(&self) -> bool {
+        self.pixelate > 1 || self.posterize >= 2
+    }
 
-    let props = BlobPropertyBag::new();
-    if filename.ends_with(".zip") {
-        props.set_type("application/zip");
-    } else {
-        props.set_type("image/png");
+    fn hash_into(&self, hasher: &mut Fnv64) {
+        hasher.write_u32(self.pixelate);
+        hasher.write_u32(self.posterize);
+    }
+
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({ "pixelate": self.pixelate, "posterize": self.posterize })
     }
 
-    let blob = Blob::new_with_u8_array_sequence_and_options(&parts, &props).unwrap();
-    let url = Url::create_object_url_with_blob(&blob).unwrap();
+    /// Pixelate (average each block, then fill it back) and posterize, in that order.
+    /// Blocks are aligned to the canvas origin so per-layer exports line up.
+    fn apply(&self, image: &mut RgbaImage, export_scale: u32) {
+        let block = self.pixelate * export_scale;
+        if self.pixelate > 1 {
+            let (w, h) = image.dimensions();
+            for by in (0..h).step_by(block as usize) {
+                for bx in (0..w).step_by(block as usize) {
+                    let (bw, bh) = (block.min(w - bx), block.min(h - by));
+                    let mut sum = [0u64; 4];
+                    for y in by..by + bh {
+                        for x in bx..bx + bw {
+                            let p = image.get_pixel(x, y);
+                            // Weight color by alpha so transparent pixels don't darken edges
+                            for c in 0..3 {
+                                sum[c] += p[c] as u64 * p[3] as u64;
+                            }
+                            sum[3] += p[3] as u64;
+                        }
+                    }
+                    let count = (bw * bh) as u64;
+                    let channel = |c: usize| sum[c].checked_div(sum[3]).unwrap_or(0) as u8;
+                    let average =
+                        Rgba([channel(0), channel(1), channel(2), (sum[3] / count) as u8]);
+                    for y in by..by + bh {
+                        for x in bx..bx + bw {
+                            image.put_pixel(x, y, average);
+                        }
+                    }
+                }
+            }
+        }
 
-    let link = document.create_element("a").unwrap();
-    let link: HtmlAnchorElement = link.dyn_into().unwrap();
-    link.set_href(&url);
-    link.set_download(filename);
-    link.style().set_property("display", "none").unwrap();
+        if self.posterize >= 2 {
+            let steps = (self.posterize - 1) as f32;
+            for pixel in image.pixels_mut() {
+                for c in &mut pixel.0[..3] {
+                    *c = ((*c as f32 / 255.0 * steps).round() / steps * 255.0).round() as u8;
+                }
+            }
+        }
+    }
+}
 
-    body.append_child(&link).unwrap();
-    link.click();
-    body.remove_child(&link).unwrap();
-    Url::revoke_object_url(&url).unwrap();
+/// Smallest and largest canvas edge the canvas setup allows
+const MIN_CANVAS_SIZE: u32 = 16;
+const MAX_CANVAS_SIZE: u32 = 4096;
+/// Canvas edges past this get a performance warning in the canvas setup
+const LARGE_CANVAS_SIZE: u32 = 1024;
+/// Preview zoom limits, shared by the slider, shortcuts, Ctrl+scroll and Fit
+const MIN_ZOOM: f32 = 0.1;
+const MAX_ZOOM: f32 = 10.0;
+/// Preview opacity of reference layers
+const REFERENCE_OPACITY: f32 = 0.5;
+/// Preview opacity of layer pixels outside the canvas
+const OVERFLOW_OPACITY: f32 = 0.35;
+/// Preview opacity of the mirrored ghosts
+const MIRROR_GHOST_OPACITY: f32 = 0.4;
+/// Zoom toolbar buttons above the canvas
+const ZOOM_PRESETS: [f32; 4] = [1.0, 2.0, 4.0, 8.0];
+/// Screen pixels kept free around the canvas by Fit
+const FIT_MARGIN: f32 = 16.0;
+/// Square sprite sizes offered in the canvas setup
+const CANVAS_PRESETS: [u32; 7] = [16, 32, 48, 64, 96, 128, 256];
+
+/// Grow the canvas when a dragged layer runs past its edge
+struct AutoExpand {
+    enabled: bool,
+    auto_apply: bool, // Grow right away instead of asking
+    threshold: u32,   // Overflow in pixels before growing
+    increment: u32,   // Growth step in pixels
 }
 
-#[cfg(not(target_arch = "wasm32"))]
-fn trigger_download(filename: &str, data: &[u8]) {
-    if let Ok(mut file) = std::fs::File::create(filename) {
-        let _ = file.write_all(data);
-        println!("Saved to {}", filename);
+impl Default for AutoExpand {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            auto_apply: false,
+            threshold: 8,
+            increment: 64,
+        }
     }
 }
 
-// ----------------------------------------------------------------------------
-// App Implementation
-// ----------------------------------------------------------------------------
+/// What the canvas view shows behind the layers; never part of the output
+struct PreviewBackground {
+    checker_size: u32, // Cell edge in canvas pixels
+    checker_colors: [egui::Color32; 2],
+    solid: Option<egui::Color32>, // Replaces the checkerboard
+}
 
-impl eframe::App for KitbashApp {
-    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
-        // Handle async messages
-        while let Ok(msg) = self.msg_receiver.try_recv() {
-            match msg {
-                AppMessage::ImageLoaded(name, bytes) => {
-                    if let Ok(img) = image::load_from_memory(&bytes) {
-                        let id = self.next_id;
-                        self.next_id += 1;
-                        let layer = LayerImage {
-                            id,
-                            name,
-                            source_image: img,
-                            texture: None,
-                            transform: Transform::default(),
-                            visible: true,
-                        };
-                        self.layers.push(layer);
-                    } else {
-                        eprintln!("Failed to decode image: {}", name);
-                    }
+impl Default for PreviewBackground {
+    fn default() -> Self {
+        Self {
+            checker_size: 8,
+            checker_colors: [egui::Color32::from_gray(50), egui::Color32::from_gray(100)],
+            solid: None,
+        }
+    }
+}
+
+impl PreviewBackground {
+    /// Fill `canvas_rect` (showing a canvas of `canvas_size` at `zoom`), drawing only
+    /// the cells inside `visible`
+    fn paint(
+        &self,
+        painter: &egui::Painter,
+        canvas_rect: egui::Rect,
+        canvas_size: [u32; 2],
+        zoom: f32,
+        visible: egui::Rect,
+    ) {
+        if let Some(color) = self.solid {
+            painter.rect_filled(canvas_rect, 0.0, color);
+            return;
+        }
+        painter.rect_filled(canvas_rect, 0.0, self.checker_colors[0]);
+
+        // Cells are laid out in canvas pixels so they stay on the pixel grid at any zoom;
+        // the last row and column are cut at the canvas edge
+        let size = self.checker_size.max(1);
+        let cells = |axis: usize| {
+            let first = ((visible.min[axis] - canvas_rect.min[axis]) / zoom).max(0.0) as u32 / size;
+            let end =
+                ((visible.max[axis] - canvas_rect.min[axis]) / zoom).max(0.0) as u32 / size + 1;
+            first..end.min(canvas_size[axis].div_ceil(size))
+        };
+        let to_screen = |cell: u32, axis: usize| {
+            let px = (cell * size).min(canvas_size[axis]);
+            canvas_rect.min[axis] + px as f32 * zoom
+        };
+        for r in cells(1) {
+            for c in cells(0) {
+                if (r + c) % 2 == 0 {
+                    let rect = egui::Rect::from_min_max(
+                        egui::pos2(to_screen(c, 0), to_screen(r, 1)),
+                        egui::pos2(to_screen(c + 1, 0), to_screen(r + 1, 1)),
+                    );
+                    painter.rect_filled(rect, 0.0, self.checker_colors[1]);
                 }
             }
         }
+    }
 
-        let is_mobile = ctx.screen_rect().width() < 600.0;
+    fn ui(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            ui.label("Preview BG:");
+            let mut solid = self.solid.is_some();
+            ui.selectable_value(&mut solid, false, "Checker");
+            ui.selectable_value(&mut solid, true, "Solid");
+            if solid != self.solid.is_some() {
+                self.solid = solid.then_some(self.checker_colors[0]);
+            }
+        });
+        ui.horizontal(|ui| match &mut self.solid {
+            Some(color) => {
+                ui.label("Color:");
+                ui.color_edit_button_srgba(color);
+            }
+            None => {
+                ui.label("Cell:");
+                ui.add(
+                    egui::DragValue::new(&mut self.checker_size)
+                        .range(1..=256)
+                        .suffix(" px"),
+                );
+                ui.label("Colors:");
+                ui.color_edit_button_srgba(&mut self.checker_colors[0]);
+                ui.color_edit_button_srgba(&mut self.checker_colors[1]);
+                if ui.button("Reset").clicked() {
+                    *self = Self::default();
+                }
+            }
+        });
+    }
+}
 
-        // --------------------------------------------------------------------
-        // UI Components
-        // --------------------------------------------------------------------
+/// Pixels to add to each side of the canvas
+#[derive(Clone, Copy, Default, PartialEq)]
+struct CanvasGrowth {
+    left: u32,
+    top: u32,
+    right: u32,
+    bottom: u32,
+}
 
-        let control_panel_ui = |ui: &mut egui::Ui, app: &mut KitbashApp| {
-            egui::ScrollArea::vertical().show(ui, |ui| {
-                ui.heading("Kitbash Config");
-                ui.separator();
+impl CanvasGrowth {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
 
-                // Canvas Settings
-                ui.collapsing("Canvas Setup", |ui| {
-                    ui.horizontal(|ui| {
-                        ui.label("Base W:");
-                        ui.add(egui::DragValue::new(&mut app.canvas_size[0]).range(16..=1024));
-                        ui.label("Base H:");
-                        ui.add(egui::DragValue::new(&mut app.canvas_size[1]).range(16..=1024));
-                    });
-                    ui.horizontal(|ui| {
-                        ui.label("BG Color:");
-                        ui.color_edit_button_srgba(&mut app.bg_color);
-                    });
-                    ui.horizontal(|ui| {
-                        ui.label("View Zoom:");
-                        ui.add(egui::Slider::new(&mut app.preview_zoom, 0.5..=10.0));
-                    });
+    fn describe(&self) -> String {
+        [
+            ("left", self.left),
+            ("top", self.top),
+            ("right", self.right),
+            ("bottom", self.bottom),
+        ]
+        .iter()
+        .filter(|(_, px)| *px > 0)
+        .map(|(side, px)| format!("{} px {}", px, side))
+        .collect::<Vec<_>>()
+        .join(", ")
+    }
+}
 
-                    if ui.button("Reset View").clicked() {
-                        app.canvas_pan = egui::Vec2::ZERO;
-                        app.preview_zoom = 4.0;
-                    }
-                });
+/// Per-layer brightness/contrast (-100..100), a linear transform on RGB around mid-gray
+#[derive(Clone, Copy, Default, PartialEq)]
+struct ToneAdjust {
+    brightness: f32,
+    contrast: f32,
+    preserve_endpoints: bool, // Leave exact #000/#fff pixels (pixel-art outlines) untouched
+}
 
-                ui.separator();
+impl ToneAdjust {
+    fn is_identity(&self) -> bool {
+        self.brightness == 0.0 && self.contrast == 0.0
+    }
 
-                // Asset Pipeline
-                ui.heading("Layers");
-                if ui.button("Import Images (Batch)...").clicked() {
-                    let sender = app.msg_sender.clone();
-                    let task = async move {
-                        if let Some(handles) = rfd::AsyncFileDialog::new()
-                            .add_filter("Image", &["png", "jpg", "jpeg", "webp"])
-                            .pick_files()
-                            .await
-                        {
-                            for handle in handles {
-                                let data = handle.read().await;
-                                let name = handle.file_name();
-                                let _ = sender.send(AppMessage::ImageLoaded(name, data));
-                            }
-                        }
-                    };
+    fn hash_into(&self, hasher: &mut Fnv64) {
+        hasher.write_u32(self.brightness.to_bits());
+        hasher.write_u32(self.contrast.to_bits());
+        hasher.write(&[self.preserve_endpoints as u8]);
+    }
+
+    fn apply(&self, image: &mut RgbaImage) {
+        if self.is_identity() {
+            return;
+        }
+        let gain = 1.0 + self.contrast / 100.0;
+        let offset = self.brightness / 100.0;
+        for pixel in image.pixels_mut() {
+            if pixel[3] == 0 {
+                continue;
+            }
+            let rgb = [pixel[0], pixel[1], pixel[2]];
+            if self.preserve_endpoints && (rgb == [0, 0, 0] || rgb == [255, 255, 255]) {
+                continue;
+            }
+            for c in &mut pixel.0[..3] {
+                let v = (*c as f32 / 255.0 - 0.5) * gain + 0.5 + offset;
+                *c = (v.clamp(0.0, 1.0) * 255.0).round() as u8;
+            }
+        }
+    }
+}
+
+/// RGB in 0..1 to (hue 0..1, saturation, lightness)
+fn rgb_to_hsl(r: f32, g: f32, b: f32) -> (f32, f32, f32) {
+    let max = r.max(g).max(b);
+    let min = r.min(g).min(b);
+    let l = (max + min) / 2.0;
+    if max == min {
+        return (0.0, 0.0, l);
+    }
+
+    let d = max - min;
+    let s = if l > 0.5 {
+        d / (2.0 - max - min)
+    } else {
+        d / (max + min)
+    };
+    let h = if max == r {
+        (g - b) / d + if g < b { 6.0 } else { 0.0 }
+    } else if max == g {
+        (b - r) / d + 2.0
+    } else {
+        (r - g) / d + 4.0
+    };
+    (h / 6.0, s, l)
+}
+
+fn hsl_to_rgb(h: f32, s: f32, l: f32) -> (f32, f32, f32) {
+    if s == 0.0 {
+        return (l, l, l);
+    }
+    let q = if l < 0.5 {
+        l * (1.0 + s)
+    } else {
+        l + s - l * s
+    };
+    let p = 2.0 * l - q;
+    let channel = |t: f32| {
+        let t = t.rem_euclid(1.0);
+        if t < 1.0 / 6.0 {
+            p + (q - p) * 6.0 * t
+        } else if t < 0.5 {
+            q
+        } else if t < 2.0 / 3.0 {
+            p + (q - p) * (2.0 / 3.0 - t) * 6.0
+        } else {
+            p
+        }
+    };
+    (channel(h + 1.0 / 3.0), channel(h), channel(h - 1.0 / 3.0))
+}
+
+/// Per-layer outline drawn around (or just inside) the opaque pixels
+#[derive(Clone, Copy, PartialEq)]
+struct Outline {
+    enabled: bool,
+    color: egui::Color32,
+    thickness: u32, // Canvas pixels, independent of the layer's scale
+    inside: bool,   // Recolor the layer's own edge instead of growing it
+}
+
+impl Default for Outline {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            color: egui::Color32::BLACK,
+            thickness: 1,
+            inside: false,
+        }
+    }
+}
+
+impl Outline {
+    fn hash_into(&self, hasher: &mut Fnv64) {
+        hasher.write(&[self.enabled as u8, self.inside as u8]);
+        hasher.write(&self.color.to_array());
+        hasher.write_u32(self.thickness);
+    }
+
+    /// Apply to already scaled pixels; an outer outline grows the image by `radius` per side
+    fn apply(&self, image: RgbaImage, radius: u32) -> RgbaImage {
+        if !self.enabled || radius == 0 {
+            return image;
+        }
+        let color = Rgba(self.color.to_srgba_unmultiplied());
+        let pad = if self.inside { 0 } else { radius };
+        let (w, h) = (image.width() + 2 * pad, image.height() + 2 * pad);
+
+        let mut padded = RgbaImage::new(w, h);
+        image::imageops::replace(&mut padded, &image, pad as i64, pad as i64);
+        let opaque = |x: i64, y: i64| {
+            x >= 0
+                && y >= 0
+                && x < w as i64
+                && y < h as i64
+                && padded.get_pixel(x as u32, y as u32)[3] > 0
+        };
+
+        // Disc-shaped kernel so thick outlines get rounded corners
+        let r = radius as i64;
+        let kernel: Vec<(i64, i64)> = (-r..=r)
+            .flat_map(|dy| (-r..=r).map(move |dx| (dx, dy)))
+            .filter(|&(dx, dy)| dx * dx + dy * dy <= r * r + r)
+            .collect();
+
+        let mut out = if self.inside {
+            padded.clone()
+        } else {
+            RgbaImage::new(w, h)
+        };
+        for y in 0..h as i64 {
+            for x in 0..w as i64 {
+                let is_outline = if self.inside {
+                    // Opaque pixel near a transparent one (or the image border)
+                    opaque(x, y) && kernel.iter().any(|&(dx, dy)| !opaque(x + dx, y + dy))
+                } else {
+                    // Transparent pixel near an opaque one
+                    !opaque(x, y) && kernel.iter().any(|&(dx, dy)| opaque(x + dx, y + dy))
+                };
+                if is_outline {
+                    out.put_pixel(x as u32, y as u32, color);
+                }
+            }
+        }
+        if !self.inside {
+            image::imageops::overlay(&mut out, &padded, 0, 0);
+        }
+        out
+    }
+}
+
+/// Hard-edged drop shadow: the layer's silhouette in one color, offset in canvas pixels
+#[derive(Clone, Copy, PartialEq)]
+struct Shadow {
+    enabled: bool,
+    offset: [i32; 2],
+    color: egui::Color32,
+    opacity: f32,
+    include_in_bounds: bool, // Whether the shadow counts towards the layer's exported bounds
+}
+
+impl Default for Shadow {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            offset: [1, 1],
+            color: egui::Color32::BLACK,
+            opacity: 0.5,
+            include_in_bounds: false,
+        }
+    }
+}
+
+impl Shadow {
+    fn hash_into(&self, hasher: &mut Fnv64) {
+        hasher.write(&[self.enabled as u8, self.include_in_bounds as u8]);
+        hasher.write_u32(self.offset[0] as u32);
+        hasher.write_u32(self.offset[1] as u32);
+        hasher.write(&self.color.to_array());
+        hasher.write_u32(self.opacity.to_bits());
+    }
+
+    fn scaled_offset(&self, export_scale: u32) -> [i64; 2] {
+        if !self.enabled {
+            return [0, 0];
+        }
+        let scale = export_scale as i64;
+        [self.offset[0] as i64 * scale, self.offset[1] as i64 * scale]
+    }
+
+    /// Put the silhouette of `image` behind it; the result grows to fit both
+    fn apply(&self, image: RgbaImage, export_scale: u32) -> RgbaImage {
+        if !self.enabled {
+            return image;
+        }
+        let [sx, sy] = self.scaled_offset(export_scale);
+        let [r, g, b, a] = self.color.to_srgba_unmultiplied();
+        let opacity = a as f32 / 255.0 * self.opacity.clamp(0.0, 1.0);
+
+        let mut silhouette = RgbaImage::new(image.width(), image.height());
+        for (src, dst) in image.pixels().zip(silhouette.pixels_mut()) {
+            *dst = Rgba([r, g, b, (src[3] as f32 * opacity).round() as u8]);
+        }
+
+        let mut out = RgbaImage::new(
+            image.width() + sx.unsigned_abs() as u32,
+            image.height() + sy.unsigned_abs() as u32,
+        );
+        image::imageops::overlay(&mut out, &silhouette, sx.max(0), sy.max(0));
+        image::imageops::overlay(&mut out, &image, (-sx).max(0), (-sy).max(0));
+        out
+    }
+}
+
+/// Edge or center line used by the Align tools
+#[derive(Clone, Copy, PartialEq)]
+enum Align {
+    Left,
+    CenterH,
+    Right,
+    Top,
+    CenterV,
+    Bottom,
+}
+
+impl Align {
+    const ALL: [(Align, &'static str); 6] = [
+        (Align::Left, "⏴ Left"),
+        (Align::CenterH, "Center H"),
+        (Align::Right, "Right ⏵"),
+        (Align::Top, "⏶ Top"),
+        (Align::CenterV, "Center V"),
+        (Align::Bottom, "Bottom ⏷"),
+    ];
+}
+
+/// A validated profile waiting for the user to confirm its changes
+struct PendingProfile {
+    profile: SettingsProfile,
+    changes: Vec<String>,
+    warnings: Vec<String>,
+}
+
+/// Commands sent from the UI to a running import worker
+enum ImportCommand {
+    Pause,
+    Resume,
+    SkipCurrent,
+    Cancel,
+}
+
+/// App-side view of a batch import being processed by the worker
+struct ImportQueue {
+    files: Vec<String>,
+    current: Option<usize>,
+    finished: usize, // imported + skipped
+    skipped: Vec<usize>,
+    paused: bool,
+    commands: UnboundedSender<ImportCommand>,
+    imported: Vec<u64>, // Layer ids created by this batch
+}
+
+impl ImportQueue {
+    fn send(&self, command: ImportCommand) {
+        let _ = self.commands.unbounded_send(command);
+    }
+}
+
+/// Opt-in import heuristic pairing `arm_l.png`/`arm_r.png` style files
+struct MirrorPairSettings {
+    enabled: bool,
+    suffixes: String, // "left/right" suffix pairs, comma separated
+}
+
+impl Default for MirrorPairSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            suffixes: "_l/_r, _left/_right".to_owned(),
+        }
+    }
+}
+
+impl MirrorPairSettings {
+    fn suffix_pairs(&self) -> Vec<(String, String)> {
+        self.suffixes
+            .split(',')
+            .filter_map(|pair| {
+                let (left, right) = pair.split_once('/')?;
+                let (left, right) = (left.trim().to_lowercase(), right.trim().to_lowercase());
+                (!left.is_empty() && !right.is_empty()).then_some((left, right))
+            })
+            .collect()
+    }
+}
+
+/// A detected left/right pair awaiting confirmation after an import
+struct MirrorPair {
+    stem: String,
+    left_id: u64,
+    right_id: u64,
+    accepted: bool,
+}
+
+/// Pair up layers whose file stems differ only by a left/right suffix
+fn detect_mirror_pairs(layers: &[&LayerImage], suffixes: &[(String, String)]) -> Vec<MirrorPair> {
+    let stem_of = |name: &str| {
+        let name = name.to_lowercase();
+        match name.rsplit_once('.') {
+            Some((stem, _)) => stem.to_owned(),
+            None => name,
+        }
+    };
+
+    let mut pairs = Vec::new();
+    let mut used = HashSet::new();
+    for left in layers {
+        let left_stem = stem_of(&left.name);
+        for (left_suffix, right_suffix) in suffixes {
+            let Some(stem) = left_stem.strip_suffix(left_suffix.as_str()) else {
+                continue;
+            };
+            let right = layers.iter().find(|r| {
+                r.id != left.id
+                    && !used.contains(&r.id)
+                    && stem_of(&r.name) == format!("{}{}", stem, right_suffix)
+            });
+            if let Some(right) = right {
+                if used.insert(left.id) && used.insert(right.id) {
+                    pairs.push(MirrorPair {
+                        stem: stem.to_owned(),
+                        left_id: left.id,
+                        right_id: right.id,
+                        accepted: true,
+                    });
+                }
+                break;
+            }
+        }
+    }
+    pairs
+}
+
+/// Undoable document state; layer images are `Arc`s so snapshots stay cheap
+#[derive(Clone)]
+struct DocSnapshot {
+    layers: Vec<LayerImage>,
+    canvas_size: [u32; 2],
+    bg_color: egui::Color32,
+    notes: ProjectNotes,
+    guides: Vec<Guide>,
+    markers: Vec<Marker>,
+}
+
+/// An open document. The active one is edited in place in `KitbashApp`'s own fields, so
+/// its entry in `KitbashApp::documents` only holds the name until it is switched away from.
+struct Document {
+    name: String,
+    state: DocSnapshot,
+    history: History,
+    selected_layer_id: Option<u64>,
+    selection: HashSet<u64>,
+    next_id: u64,
+    preview_zoom: f32,
+    canvas_pan: egui::Vec2,
+    project_name: String, // Empty uses the tab name
+}
+
+impl Document {
+    fn new(name: String) -> Self {
+        Self {
+            name,
+            state: DocSnapshot {
+                layers: Vec::new(),
+                canvas_size: [64, 64],
+                bg_color: egui::Color32::TRANSPARENT,
+                notes: ProjectNotes::default(),
+                guides: Vec::new(),
+                markers: Vec::new(),
+            },
+            history: History::default(),
+            selected_layer_id: None,
+            selection: HashSet::new(),
+            next_id: 0,
+            preview_zoom: 4.0,
+            canvas_pan: egui::Vec2::ZERO,
+            project_name: String::new(),
+        }
+    }
+}
+
+/// Free-form project notes that travel with the export
+#[derive(Clone, Default, PartialEq)]
+struct ProjectNotes {
+    text: String,
+    show_on_open: bool, // Pop the notes up when the project is opened
+}
+
+impl ProjectNotes {
+    /// Notes are for short instructions, not documents
+    const MAX_BYTES: usize = 16 * 1024;
+
+    /// Cut `text` back to `MAX_BYTES` on a character boundary
+    fn enforce_limit(&mut self) {
+        if self.text.len() > Self::MAX_BYTES {
+            let mut end = Self::MAX_BYTES;
+            while !self.text.is_char_boundary(end) {
+                end -= 1;
+            }
+            self.text.truncate(end);
+        }
+    }
+
+    /// Plain text with http(s) URLs turned into links
+    fn show_linked(&self, ui: &mut egui::Ui) {
+        for line in self.text.lines() {
+            ui.horizontal_wrapped(|ui| {
+                ui.spacing_mut().item_spacing.x = 0.0;
+                for (i, word) in line.split(' ').enumerate() {
+                    if i > 0 {
+                        ui.label(" ");
+                    }
+                    if word.starts_with("https://") || word.starts_with("http://") {
+                        ui.hyperlink(word);
+                    } else if !word.is_empty() {
+                        ui.label(word);
+                    }
+                }
+            });
+        }
+    }
+}
+
+/// One undo step: the document as it was before the change
+struct UndoEntry {
+    label: String,
+    state: DocSnapshot,
+}
+
+/// An open `begin_transaction` scope; everything until the matching commit is one undo step
+struct Transaction {
+    label: String,
+    depth: u32,
+    spans_frames: bool, // Drags stay open across frames, everything else must not
+}
+
+/// Undo/redo stacks. Edits made outside a transaction (sliders, text fields) are picked up
+/// at the end of the frame once the pointer is released and recorded as a generic "Edit".
+#[derive(Default)]
+struct History {
+    undo: Vec<UndoEntry>,
+    redo: Vec<UndoEntry>,
+    baseline: Option<DocSnapshot>, // State as of the last recorded step
+    transaction: Option<Transaction>,
+}
+
+const UNDO_LIMIT: usize = 100;
+
+impl History {
+    /// Image memory held only by history, per undo entry (oldest first), plus the total.
+    /// Snapshots share unchanged source images with the document and with each other, so
+    /// an image is charged to the newest entry that holds it; dropping the oldest entry
+    /// frees exactly what it's charged.
+    fn image_bytes(&self, current: &[LayerImage]) -> (Vec<usize>, usize) {
+        let mut seen: HashSet<*const image::DynamicImage> = current
+            .iter()
+            .map(|l| Arc::as_ptr(&l.source_image))
+            .collect();
+        let mut charge = |snapshot: &DocSnapshot| {
+            snapshot
+                .layers
+                .iter()
+                .filter(|l| seen.insert(Arc::as_ptr(&l.source_image)))
+                .map(|l| l.source_image.as_bytes().len())
+                .sum::<usize>()
+        };
+        let mut total = self.baseline.as_ref().map_or(0, &mut charge);
+        total += self.redo.iter().map(|e| charge(&e.state)).sum::<usize>();
+        let mut per_entry: Vec<usize> = self.undo.iter().rev().map(|e| charge(&e.state)).collect();
+        per_entry.reverse();
+        total += per_entry.iter().sum::<usize>();
+        (per_entry, total)
+    }
+
+    /// Drop the oldest undo steps until history images fit in `budget` bytes (the newest
+    /// step is always kept)
+    fn enforce_budget(&mut self, current: &[LayerImage], budget: usize) {
+        let (per_entry, mut total) = self.image_bytes(current);
+        let mut evict = 0;
+        while total > budget && evict + 1 < per_entry.len() {
+            total -= per_entry[evict];
+            evict += 1;
+        }
+        if evict > 0 {
+            log::debug!("Undo budget: dropped {} oldest step(s)", evict);
+            self.undo.drain(..evict);
+        }
+    }
+}
+
+/// Layer list action, collected during the row pass and applied after it
+enum RowAction {
+    Rename(u64, String),
+    Duplicate(u64),
+    Delete(u64), // Deletes the whole selection if the row is selected
+    Move(usize, usize),
+    MoveToPosition(u64, usize),
+    ToggleLock(u64),
+}
+
+/// Color labels offered in the layer context menu
+const LABEL_COLORS: [(&str, egui::Color32); 6] = [
+    ("Red", egui::Color32::from_rgb(220, 60, 60)),
+    ("Orange", egui::Color32::from_rgb(230, 140, 40)),
+    ("Yellow", egui::Color32::from_rgb(220, 200, 50)),
+    ("Green", egui::Color32::from_rgb(70, 180, 80)),
+    ("Blue", egui::Color32::from_rgb(60, 130, 220)),
+    ("Purple", egui::Color32::from_rgb(150, 90, 200)),
+];
+
+/// Permutation exports with more combinations than this ask before rendering
+const PERMUTATION_CONFIRM: usize = 500;
+
+/// Permutation export setup: which color labels act as variant slots
+#[derive(Default)]
+struct PermutationDialog {
+    slots: [bool; LABEL_COLORS.len()],
+    confirm: bool, // Past PERMUTATION_CONFIRM combinations, waiting for "Export Anyway"
+}
+
+/// Export-time replacement for the canvas background color, remembered between sessions
+#[derive(Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+struct ExportBackground {
+    transparent: bool, // Wins over `solid`
+    solid: bool,
+    color: egui::Color32,
+}
+
+impl Default for ExportBackground {
+    fn default() -> Self {
+        Self {
+            transparent: false,
+            solid: false,
+            color: egui::Color32::WHITE,
+        }
+    }
+}
+
+impl ExportBackground {
+    fn describe(&self) -> String {
+        if self.transparent {
+            "transparent".to_owned()
+        } else if self.solid {
+            self.color.to_hex()
+        } else {
+            "canvas color".to_owned()
+        }
+    }
+}
+
+/// eframe storage key of the export background override
+const EXPORT_BACKGROUND_KEY: &str = "export_background";
+
+/// eframe storage key of the backup policy
+const BACKUP_POLICY_KEY: &str = "backup_policy";
+
+/// eframe storage key of the recent and saved background colors
+const BG_PALETTE_KEY: &str = "bg_palette";
+
+/// eframe storage key of the JPEG matte color
+const JPEG_MATTE_KEY: &str = "jpeg_matte";
+
+/// eframe storage key of whether the app opens in viewer mode
+const VIEWER_MODE_KEY: &str = "viewer_mode";
+
+/// "Preview Image..." dialog: the composite download as it would be saved, rebuilt
+/// whenever the document or an export option changes
+#[derive(Default)]
+struct ExportPreview {
+    zoom: f32, // Screen points per output pixel, 0 = fit on the next build
+    image: Option<(u64, egui::TextureHandle)>, // 1x composite as the encoder sees it, and its key
+    encoded_bytes: usize, // Size of the file Confirm saves
+    building: Option<u64>, // Key of the preview a worker is rendering
+}
+
+/// Encoding of the composited image download
+#[derive(Clone, Copy, Default, PartialEq)]
+enum CompositeFormat {
+    #[default]
+    Png,
+    WebP, // Lossless only; the image crate has no lossy WebP encoder
+    Jpeg,
+}
+
+impl CompositeFormat {
+    const ALL: [Self; 3] = [Self::Png, Self::WebP, Self::Jpeg];
+
+    fn label(self) -> &'static str {
+        match self {
+            Self::Png => "PNG",
+            Self::WebP => "WebP (lossless)",
+            Self::Jpeg => "JPEG",
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Self::Png => "png",
+            Self::WebP => "webp",
+            Self::Jpeg => "jpg",
+        }
+    }
+}
+
+/// Quantize the composite download to a few colors and save it as an indexed PNG
+#[derive(Clone, Copy, PartialEq)]
+struct PaletteLimit {
+    enabled: bool,
+    colors: usize, // Besides the transparent entry
+    dither: palette::Dither,
+}
+
+impl PaletteLimit {
+    fn to_json(self) -> serde_json::Value {
+        serde_json::json!({
+            "enabled": self.enabled,
+            "colors": self.colors,
+            "dither": self.dither.label(),
+        })
+    }
+}
+
+impl Default for PaletteLimit {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            colors: 16,
+            dither: palette::Dither::None,
+        }
+    }
+}
+
+/// Export running in the background, shown as a progress bar
+struct ExportJob {
+    label: String,
+    progress: f32, // 0..=1
+    cancel: Arc<AtomicBool>,
+}
+
+/// Handed to an export while it runs: reports progress and tells it when to stop
+struct ExportProgress {
+    events: EventQueue,
+    cancel: Arc<AtomicBool>,
+    #[cfg(target_arch = "wasm32")]
+    last_yield: std::cell::Cell<f64>, // `js_sys::Date::now()` when the browser last had a turn
+}
+
+/// On the web, how long an export may hold the UI thread before letting the browser run
+#[cfg(target_arch = "wasm32")]
+const EXPORT_SLICE_MS: f64 = 12.0;
+
+impl ExportProgress {
+    /// Report `done` of `total` steps. Fails once the user cancelled, so `?` stops the export.
+    /// On the web the export shares the UI thread, so this is also where it hands the
+    /// browser a turn to paint and handle input (a Cancel click among it).
+    async fn step(&self, done: usize, total: usize) -> Result<(), KitbashError> {
+        #[cfg(target_arch = "wasm32")]
+        if js_sys::Date::now() - self.last_yield.get() > EXPORT_SLICE_MS {
+            yield_to_browser().await;
+            self.last_yield.set(js_sys::Date::now());
+        }
+        if self.cancel.load(Ordering::Relaxed) {
+            return Err(KitbashError::Cancelled);
+        }
+        let fraction = done as f32 / total.max(1) as f32;
+        self.events
+            .send(AppEvent::Export(ExportEvent::Progress(fraction)));
+        Ok(())
+    }
+}
+
+/// Resolve on the next turn of the browser's event loop, after it had a chance to paint
+#[cfg(target_arch = "wasm32")]
+async fn yield_to_browser() {
+    let promise = js_sys::Promise::new(&mut |resolve, _| {
+        let scheduled = web_sys::window()
+            .is_some_and(|window| window.set_timeout_with_callback(&resolve).is_ok());
+        if !scheduled {
+            let _ = resolve.call0(&wasm_bindgen::JsValue::NULL);
+        }
+    });
+    let _ = wasm_bindgen_futures::JsFuture::from(promise).await;
+}
+
+/// Point where background work lets the browser paint and handle input; free natively,
+/// where that work has a thread of its own
+async fn yield_now() {
+    #[cfg(target_arch = "wasm32")]
+    yield_to_browser().await;
+}
+
+/// Run `task` off the UI thread: on a worker thread natively, and on the web (no threads)
+/// as a local task that gives the browser a turn wherever it awaits `yield_now`
+fn spawn_task<F>(task: impl FnOnce() -> F + Send + 'static)
+where
+    F: std::future::Future<Output = ()> + 'static,
+{
+    #[cfg(not(target_arch = "wasm32"))]
+    std::thread::spawn(move || futures::executor::block_on(task()));
+    #[cfg(target_arch = "wasm32")]
+    wasm_bindgen_futures::spawn_local(task());
+}
+
+/// Text of a caught panic, as passed to `panic!`
+#[cfg(not(target_arch = "wasm32"))]
+fn panic_message(payload: &(dyn std::any::Any + Send)) -> &str {
+    payload
+        .downcast_ref::<&str>()
+        .copied()
+        .or_else(|| payload.downcast_ref::<String>().map(String::as_str))
+        .unwrap_or("unknown error")
+}
+
+/// Set when the browser restores a lost WebGL context; every texture uploaded before is gone
+static GRAPHICS_CONTEXT_RESTORED: AtomicBool = AtomicBool::new(false);
+
+/// How long a toast notice stays up
+const TOAST_SECONDS: f64 = 3.0;
+
+/// Toasts on screen at once; a new one pushes out the oldest
+const TOAST_LIMIT: usize = 4;
+
+struct KitbashApp {
+    // Canvas Config
+    canvas_size: [u32; 2],
+    auto_expand: AutoExpand,
+    preview_bg: PreviewBackground,
+    pending_growth: Option<CanvasGrowth>, // Offered after a drag ran past the canvas edge
+    bg_color: egui::Color32,
+    notes: ProjectNotes,
+    guides: Vec<Guide>,
+    markers: Vec<Marker>, // Named attachment points, exported in data.json
+    notes_open: bool,     // Notes window shown
+    notes_editing: bool,  // Notes window shows the editor instead of the linked text
+    resize_dialog: Option<([u32; 2], egui::Vec2)>, // Resize Canvas dialog: new size and anchor
+    canvas_aspect_lock: bool, // Keep width:height while editing the canvas size
+    user_data_draft: (String, String), // New user data row: key, value
+    user_data_remove_key: String, // Key to strip from the whole selection
+    export_scale: u32,    // New: Export multiplier
+    export_filename_template: String, // Export file stem, see DEFAULT_EXPORT_TEMPLATE
+    export_scaled_metadata: bool, // data.json offsets/bounds in output pixels instead of canvas pixels
+    zip_max_entries: usize,       // Split ZIP exports past this many images, 0 = never
+    export_tight: bool,           // Crop each exported layer to its opaque pixels
+    export_atlas: bool,           // Also pack the layers into atlas.png + TexturePacker atlas.json
+    export_godot: bool,           // Also write godot/*.tres AtlasTextures for the atlas regions
+    export_sources: bool,         // Also write the untouched source images to sources/
+    export_background: ExportBackground, // Overrides bg_color in exports
+    gif_delay_ms: u32,            // Frame time of the GIF preview
+    composite_format: CompositeFormat,
+    jpeg_quality: u8,                             // 1..=100
+    jpeg_matte: egui::Color32, // JPEG is composited on this instead of the export background
+    palette_limit: PaletteLimit, // PNG only
+    palette_preview: Option<(u64, Vec<[u8; 3]>)>, // Swatches of the limited palette and their key
+    palette_building: Option<u64>, // Key of the palette a worker is computing
+    export_preview: Option<ExportPreview>,
+    godot_res_prefix: String, // Where atlas.png lives in the Godot project
+    export_estimate: Option<(u64, ExportEstimate)>, // Sanity line for the Export section and its key
+    export_estimate_seen: (u64, f64), // Latest estimate key and when it first appeared (debounce)
+    pending_html: Option<usize>,      // Estimated size of an HTML viewer awaiting confirmation
+    permutation_dialog: Option<PermutationDialog>, // Open permutation export setup
+    output_effects: OutputEffects,
+    composite_preview: Option<(u64, egui::TextureHandle)>, // Output-effects preview and its key
+    composite_preview_seen: (u64, f64), // Latest preview key and when it first appeared (debounce)
+    backup_policy: BackupPolicy,
+    export_error: Option<String>,
+    export_job: Option<ExportJob>, // One export at a time
+    #[cfg(not(target_arch = "wasm32"))]
+    last_export_dir: Option<std::path::PathBuf>, // Where the last save dialog was confirmed
+
+    verify_recomposite: bool,
+    verify_report: Option<Vec<String>>,
+
+    // State
+    project_name: String, // Active document's; fills `{project}` and the window title
+    #[cfg(not(target_arch = "wasm32"))]
+    window_title: String, // Title last sent to the native window
+    layers: Vec<LayerImage>, // Flat list again
+    selected_layer_id: Option<u64>,
+    selection: HashSet<u64>, // Every selected layer; includes the primary one above
+    layer_filter: String,    // Layer list search text, empty = show all
+    label_filter: Option<egui::Color32>, // Only list layers with this color label
+    move_dialog: Option<(u64, usize)>, // "Move to Position" dialog: layer and target index
+    reveal_layer: Option<u64>, // Scroll the layer list to this row on the next frame
+    renaming: Option<(u64, String)>, // Layer being renamed inline and the edited name
+    compact_rows: bool,      // Hide the per-row move/delete buttons, the context menu has them
+    bg_palette: BgPalette,
+    undo_budget_mb: u32, // Image memory undo history may hold beyond the document
+    debug_console: bool, // Hidden log window, toggled with Ctrl+Shift+F12
+    debug_console_level: log::LevelFilter,
+    replace_keeps_center: bool, // Paste Replace keeps the old visual center
+    toasts: VecDeque<(String, f64)>, // Short notices and the time each expires, oldest first
+    next_id: u64,
+    history: History,
+    viewer_mode: bool, // Read-only: document edits are rejected, viewing and export still work
+    confirm_unlock: bool, // Asking whether to leave viewer mode
+    documents: Vec<Document>, // Every open tab; see `Document` for the active one
+    active_document: usize,
+    layer_clipboard: Vec<LayerImage>, // Copied layers, pasteable into any document
+    renaming_document: Option<(usize, String)>, // Tab being renamed and the edited name
+    confirm_close_document: Option<usize>,
+
+    // Async Communication
+    events: EventQueue, // Filled by background tasks, drained every frame
+    import_queue: Option<ImportQueue>,
+    mirror_pairs: MirrorPairSettings,
+    pending_pairs: Vec<MirrorPair>, // Detected by the last import, awaiting confirmation
+
+    // Settings
+    keybindings: Keybindings,
+    pending_profile: Option<PendingProfile>,
+    profile_error: Option<String>,
+
+    // UI State
+    preview_zoom: f32,
+    zoom_fit: bool,     // Keep preview_zoom fitting the canvas view as it resizes
+    scale_linked: bool, // Scale X/Y together in the properties panel
+    fit_cover: bool,    // "Fit Canvas" fills the canvas instead of fitting inside
+    align_to_canvas: bool,
+    reduce_motion: bool, // Manual override, the OS/browser hint is checked too
+    show_origin: bool,
+    show_axis_lines: bool,
+    clean_view: bool, // Master switch hiding every non-content overlay
+
+    smooth_preview: bool,          // LINEAR sampling for layers drawn below 1:1
+    snap_grid: Option<u32>,        // Canvas drags snap offsets to this many pixels
+    snap_offset_fields: bool,      // Also snap the offset DragValues in Properties
+    snap_guides: bool,             // Canvas drags stick layer edges and centers to guides
+    offset_parity: Option<Parity>, // Applied after grid snapping, drags and fields alike
+    drag_raw_offset: Option<(u64, egui::Vec2)>, // Unsnapped offset of the layer being dragged
+    canvas_drag_target: Option<u64>, // Layer moved by the current canvas drag
+    pick_by_pixel: bool,           // Canvas clicks skip transparent pixels of a layer's rect
+    hide_references: bool,         // Preview the final look without reference layers
+    show_overflow: bool,           // Dim, rather than hide, layer pixels past the canvas edge
+    mirror_preview: bool,          // Ghost every layer mirrored across the symmetry axis
+    mirror_axis: Option<f32>,      // Symmetry axis x, `None` = canvas center
+    eyedropper: bool,              // Next canvas click copies the output color there
+    composite_sample: Option<(u64, RgbaImage)>, // 1x composite for the pixel readout
+    composite_sample_seen: (u64, f64), // Composite key and when it was first seen
+    placing: Option<u64>,          // Layer whose pivot goes where the canvas is clicked next
+    placing_marker: bool,          // Next canvas click adds a marker
+    scatter: Option<ScatterSettings>, // Open scatter dialog
+    canvas_pan: egui::Vec2,        // New: Canvas panning
+}
+
+impl Default for KitbashApp {
+    fn default() -> Self {
+        Self {
+            canvas_size: [64, 64],
+            auto_expand: AutoExpand::default(),
+            preview_bg: PreviewBackground::default(),
+            pending_growth: None,
+            bg_color: egui::Color32::TRANSPARENT,
+            notes: ProjectNotes::default(),
+            guides: Vec::new(),
+            markers: Vec::new(),
+            notes_open: false,
+            notes_editing: false,
+            resize_dialog: None,
+            canvas_aspect_lock: false,
+            user_data_draft: Default::default(),
+            user_data_remove_key: String::new(),
+            export_scale: 1,
+            export_filename_template: DEFAULT_EXPORT_TEMPLATE.to_owned(),
+            export_scaled_metadata: false,
+            zip_max_entries: 0,
+            export_tight: false,
+            export_atlas: false,
+            export_sources: false,
+            export_background: ExportBackground::default(),
+            export_godot: false,
+            gif_delay_ms: 500,
+            composite_format: CompositeFormat::default(),
+            jpeg_quality: 90,
+            jpeg_matte: egui::Color32::WHITE,
+            palette_limit: PaletteLimit::default(),
+            palette_preview: None,
+            palette_building: None,
+            export_preview: None,
+            godot_res_prefix: DEFAULT_GODOT_PREFIX.to_owned(),
+            export_estimate: None,
+            export_estimate_seen: (0, 0.0),
+            pending_html: None,
+            permutation_dialog: None,
+            output_effects: OutputEffects::default(),
+            composite_preview: None,
+            composite_preview_seen: (0, 0.0),
+
+            backup_policy: BackupPolicy::default(),
+            export_error: None,
+            export_job: None,
+            #[cfg(not(target_arch = "wasm32"))]
+            last_export_dir: None,
+
+            verify_recomposite: false,
+            verify_report: None,
+            project_name: String::new(),
+            #[cfg(not(target_arch = "wasm32"))]
+            window_title: String::new(),
+            layers: Vec::new(),
+            selected_layer_id: None,
+            selection: HashSet::new(),
+            layer_filter: String::new(),
+            label_filter: None,
+            move_dialog: None,
+            reveal_layer: None,
+            renaming: None,
+            compact_rows: false,
+            bg_palette: BgPalette::default(),
+            undo_budget_mb: 256,
+            debug_console: false,
+            debug_console_level: log::LevelFilter::Debug,
+            replace_keeps_center: true,
+            toasts: VecDeque::new(),
+            next_id: 0,
+            history: History::default(),
+            viewer_mode: false,
+            confirm_unlock: false,
+            documents: vec![Document::new("Untitled 1".to_owned())],
+            active_document: 0,
+            layer_clipboard: Vec::new(),
+            renaming_document: None,
+            confirm_close_document: None,
+            events: EventQueue::default(),
+            import_queue: None,
+            mirror_pairs: MirrorPairSettings::default(),
+            pending_pairs: Vec::new(),
+
+            keybindings: Keybindings::default(),
+            pending_profile: None,
+            profile_error: None,
+            preview_zoom: 4.0,
+            zoom_fit: false,
+            scale_linked: true,
+            fit_cover: false,
+            align_to_canvas: true,
+            reduce_motion: false,
+            show_origin: false,
+            show_axis_lines: false,
+            clean_view: false,
+
+            smooth_preview: true,
+            snap_grid: None,
+            snap_offset_fields: false,
+            snap_guides: true,
+            offset_parity: None,
+            drag_raw_offset: None,
+            canvas_drag_target: None,
+            pick_by_pixel: true,
+            hide_references: false,
+            show_overflow: true,
+            mirror_preview: false,
+            mirror_axis: None,
+            eyedropper: false,
+            composite_sample: None,
+            composite_sample_seen: (0, 0.0),
+            placing: None,
+            placing_marker: false,
+            scatter: None,
+            canvas_pan: egui::Vec2::ZERO,
+        }
+    }
+}
+
+/// Fresh value for `LayerImage::source_revision`, unique for the whole run
+fn next_source_revision() -> u64 {
+    static NEXT: AtomicU64 = AtomicU64::new(1);
+    NEXT.fetch_add(1, Ordering::Relaxed)
+}
+
+impl LayerImage {
+    fn new(id: u64, name: String, source_image: image::DynamicImage) -> Self {
+        Self {
+            id,
+            name,
+            source_image: Arc::new(source_image),
+            source_revision: next_source_revision(),
+            texture: None,
+            thumb_texture: None,
+            transform: Transform::default(),
+            visible: true,
+            z_override: None,
+            tint: egui::Color32::WHITE,
+            locked: false,
+            adjust: ColorAdjust::default(),
+            tone: ToneAdjust::default(),
+            outline: Outline::default(),
+            shadow: Shadow::default(),
+            clip_to_below: false,
+            label_color: None,
+            user_data: BTreeMap::new(),
+            is_reference: false,
+            texture_key: 0,
+            texture_options: egui::TextureOptions::NEAREST,
+        }
+    }
+
+    /// Equality of everything undo cares about, ignoring caches such as `texture`.
+    /// New persistent fields must be added here or their edits won't be undoable.
+    fn same_state(&self, other: &LayerImage) -> bool {
+        self.id == other.id
+            && self.name == other.name
+            && Arc::ptr_eq(&self.source_image, &other.source_image)
+            && self.transform == other.transform
+            && self.visible == other.visible
+            && self.z_override == other.z_override
+            && self.tint == other.tint
+            && self.locked == other.locked
+            && self.adjust == other.adjust
+            && self.tone == other.tone
+            && self.outline == other.outline
+            && self.shadow == other.shadow
+            && self.clip_to_below == other.clip_to_below
+            && self.label_color == other.label_color
+            && self.user_data == other.user_data
+            && self.is_reference == other.is_reference
+    }
+
+    /// Hash of the per-pixel effects baked into the preview texture
+    fn effects_key(&self) -> u64 {
+        let mut hasher = Fnv64::default();
+        self.adjust.hash_into(&mut hasher);
+        self.tone.hash_into(&mut hasher);
+        self.outline.hash_into(&mut hasher);
+        self.shadow.hash_into(&mut hasher);
+        if self.has_canvas_effects() {
+            // The texture is rendered at canvas resolution, so the transform is baked in too
+            let t = &self.transform;
+            hasher.write_u32(t.scale_x.to_bits());
+            hasher.write_u32(t.scale_y.to_bits());
+            hasher.write(&[t.flip_h as u8]);
+            hasher.write(&self.tint.to_array());
+        }
+        hasher.finish()
+    }
+
+    /// Everything except the source pixels that affects how the layer renders
+    fn hash_render_state(&self, hasher: &mut Fnv64) {
+        let t = &self.transform;
+        hasher.write(&[self.visible as u8, t.flip_h as u8]);
+        for value in [
+            t.offset.x, t.offset.y, t.scale_x, t.scale_y, t.anchor.x, t.anchor.y,
+        ] {
+            hasher.write_u32(value.to_bits());
+        }
+        hasher.write(&self.tint.to_array());
+        self.adjust.hash_into(hasher);
+        self.tone.hash_into(hasher);
+        self.outline.hash_into(hasher);
+        self.shadow.hash_into(hasher);
+        hasher.write(&[self.clip_to_below as u8, self.is_reference as u8]);
+    }
+
+    /// Resample the source at the current scale (nearest neighbor) and reset the scale to 1,
+    /// keeping the layer where it is with its offset rounded to whole pixels
+    fn bake_scale(&mut self) {
+        let size = self.scaled_size().round();
+        if size.x < 1.0 || size.y < 1.0 {
+            return;
+        }
+        let top_left = self.top_left().round();
+        let baked =
+            self.source_image
+                .resize_exact(size.x as u32, size.y as u32, FilterType::Nearest);
+        self.source_image = Arc::new(baked);
+        self.source_revision = next_source_revision();
+        self.transform.scale_x = 1.0;
+        self.transform.scale_y = 1.0;
+        self.set_top_left(top_left);
+        self.transform.offset = self.transform.offset.round();
+        self.texture = None;
+        self.thumb_texture = None;
+    }
+
+    /// Source alpha (tint included) under a canvas-space point, 0 outside the image.
+    /// Uses the whole-pixel position the preview draws the layer at.
+    fn alpha_at(&self, point: egui::Vec2) -> u8 {
+        let local = point - self.top_left().round();
+        let (w, h) = (self.source_image.width(), self.source_image.height());
+        let x = (local.x / self.transform.scale_x).floor();
+        let y = (local.y / self.transform.scale_y).floor();
+        if x < 0.0 || y < 0.0 || x >= w as f32 || y >= h as f32 {
+            return 0;
+        }
+        let x = if self.transform.flip_h {
+            w - 1 - x as u32
+        } else {
+            x as u32
+        };
+        let alpha = image::GenericImageView::get_pixel(&*self.source_image, x, y as u32)[3];
+        (alpha as u16 * self.tint.a() as u16 / 255) as u8
+    }
+
+    /// Swap in a new source image, keeping the transform. With `keep_center` the offset is
+    /// adjusted so a differently sized image stays centered where the old one was.
+    fn replace_source(&mut self, image: image::DynamicImage, keep_center: bool) {
+        let center = self.top_left() + self.scaled_size() / 2.0;
+        self.source_image = Arc::new(image);
+        self.source_revision = next_source_revision();
+        if keep_center {
+            self.set_top_left((center - self.scaled_size() / 2.0).round());
+        }
+        self.texture = None;
+        self.thumb_texture = None;
+    }
+
+    /// Layer list thumbnail, built once from the source image: fitted into a square with
+    /// nearest sampling and transparent letterboxing
+    fn thumbnail(&mut self, ctx: &egui::Context) -> egui::TextureId {
+        const THUMB_SIZE: u32 = 24;
+        if let Some(tex) = &self.thumb_texture {
+            return tex.id();
+        }
+        let (w, h) = (self.source_image.width(), self.source_image.height());
+        let fit = THUMB_SIZE as f32 / w.max(h).max(1) as f32;
+        let (tw, th) = (
+            ((w as f32 * fit).round() as u32).clamp(1, THUMB_SIZE),
+            ((h as f32 * fit).round() as u32).clamp(1, THUMB_SIZE),
+        );
+        let scaled = self
+            .source_image
+            .resize_exact(tw, th, FilterType::Nearest)
+            .to_rgba8();
+        let mut thumb = RgbaImage::new(THUMB_SIZE, THUMB_SIZE);
+        image::imageops::replace(
+            &mut thumb,
+            &scaled,
+            ((THUMB_SIZE - tw) / 2) as i64,
+            ((THUMB_SIZE - th) / 2) as i64,
+        );
+        let tex = ctx.load_texture(
+            format!("{}_thumb", self.name),
+            egui::ColorImage::from_rgba_unmultiplied(
+                [THUMB_SIZE as _, THUMB_SIZE as _],
+                thumb.as_flat_samples().as_slice(),
+            ),
+            egui::TextureOptions::NEAREST,
+        );
+        let id = tex.id();
+        self.thumb_texture = Some(tex);
+        id
+    }
+
+    /// Canvas-space rect covered by the rendered pixels at 1:1, effects included
+    fn render_rect(&self) -> egui::Rect {
+        let [ox, oy] = self.effect_origin(1);
+        let [sx, sy] = self.shadow.scaled_offset(1);
+        let pad = self.outline_padding(1) as f32 * 2.0;
+        let min = self.top_left().round() - egui::vec2(ox as f32, oy as f32);
+        let size = self.scaled_size().round()
+            + egui::vec2(
+                pad + sx.unsigned_abs() as f32,
+                pad + sy.unsigned_abs() as f32,
+            );
+        egui::Rect::from_min_size(min.to_pos2(), size)
+    }
+
+    /// Effects measured in canvas pixels; the preview then shows fully rendered pixels
+    /// instead of scaling the source texture
+    fn has_canvas_effects(&self) -> bool {
+        self.outline.enabled || self.shadow.enabled
+    }
+
+    /// How far an outer outline grows the scaled image on each side, in output pixels
+    fn outline_padding(&self, export_scale: u32) -> u32 {
+        if self.outline.enabled && !self.outline.inside {
+            self.outline.thickness * export_scale
+        } else {
+            0
+        }
+    }
+
+    /// How far the rendered pixels start up/left of the layer's top-left, in output pixels
+    fn effect_origin(&self, export_scale: u32) -> [u32; 2] {
+        let pad = self.outline_padding(export_scale);
+        let [sx, sy] = self.shadow.scaled_offset(export_scale);
+        [pad + (-sx).max(0) as u32, pad + (-sy).max(0) as u32]
+    }
+
+    /// Canvas-space rect the layer reports in exported metadata: the scaled image plus
+    /// its outline, and the shadow only when it is set to count
+    fn content_bounds(&self) -> egui::Rect {
+        let rect = egui::Rect::from_min_size(self.top_left().to_pos2(), self.scaled_size())
+            .expand(self.outline_padding(1) as f32);
+        if self.shadow.enabled && self.shadow.include_in_bounds {
+            let [sx, sy] = self.shadow.offset;
+            rect.union(rect.translate(egui::vec2(sx as f32, sy as f32)))
+        } else {
+            rect
+        }
+    }
+
+    /// Pixels for the preview texture
+    fn preview_pixels(&self) -> RgbaImage {
+        if self.has_canvas_effects() {
+            render_layer_pixels(self, 1).unwrap_or_else(|| RgbaImage::new(1, 1))
+        } else {
+            self.processed_source()
+        }
+    }
+
+    /// Source pixels with every per-pixel effect applied (tint stays a vertex color in the preview)
+    fn processed_source(&self) -> RgbaImage {
+        let mut pixels = self.source_image.to_rgba8();
+        self.adjust.apply(&mut pixels);
+        self.tone.apply(&mut pixels);
+        pixels
+    }
+
+    fn source_size(&self) -> egui::Vec2 {
+        egui::vec2(
+            self.source_image.width() as f32,
+            self.source_image.height() as f32,
+        )
+    }
+
+    /// Canvas position of the scaled image's top-left corner.
+    ///
+    /// `offset` places the unscaled image; scaling then grows around `anchor`, so the
+    /// anchor point stays where it would be at scale 1.
+    fn top_left(&self) -> egui::Vec2 {
+        let t = &self.transform;
+        let anchor_px = t.anchor * self.source_size();
+        t.offset + anchor_px - anchor_px * egui::vec2(t.scale_x, t.scale_y)
+    }
+
+    /// Canvas position of a point given in the layer's source pixels
+    fn source_to_canvas(&self, point: egui::Vec2) -> egui::Vec2 {
+        let mut point = point;
+        if self.transform.flip_h {
+            point.x = self.source_size().x - point.x;
+        }
+        self.top_left() + point * egui::vec2(self.transform.scale_x, self.transform.scale_y)
+    }
+
+    /// Inverse of `source_to_canvas`
+    fn canvas_to_source(&self, canvas: egui::Vec2) -> egui::Vec2 {
+        let scale = egui::vec2(self.transform.scale_x, self.transform.scale_y);
+        let mut point = (canvas - self.top_left()) / scale.max(egui::Vec2::splat(f32::EPSILON));
+        if self.transform.flip_h {
+            point.x = self.source_size().x - point.x;
+        }
+        point
+    }
+
+    fn scaled_size(&self) -> egui::Vec2 {
+        self.source_size() * egui::vec2(self.transform.scale_x, self.transform.scale_y)
+    }
+
+    /// Move the layer so its scaled image's top-left corner lands on `top_left`
+    fn set_top_left(&mut self, top_left: egui::Vec2) {
+        self.transform.offset += top_left - self.top_left();
+    }
+
+    /// Center the scaled image on the canvas
+    fn center_on(&mut self, canvas: egui::Vec2) {
+        self.set_top_left(((canvas - self.scaled_size()) / 2.0).round());
+    }
+
+    /// Uniformly scale to fill the canvas (`cover`) or fit inside it, then center
+    fn fit_to(&mut self, canvas: egui::Vec2, cover: bool) {
+        let size = self.source_size();
+        if size.x <= 0.0 || size.y <= 0.0 {
+            return;
+        }
+        let ratio = canvas / size;
+        let scale = if cover {
+            ratio.max_elem()
+        } else {
+            ratio.min_elem()
+        };
+        self.transform.scale_x = scale;
+        self.transform.scale_y = scale;
+        self.center_on(canvas);
+    }
+
+    /// Change the anchor while keeping the layer visually in place
+    fn set_anchor(&mut self, anchor: egui::Vec2) {
+        let top_left = self.top_left();
+        self.transform.anchor = anchor;
+        self.transform.offset += top_left - self.top_left();
+    }
+}
+
+impl KitbashApp {
+    fn snapshot(&self) -> DocSnapshot {
+        DocSnapshot {
+            layers: self.layers.clone(),
+            canvas_size: self.canvas_size,
+            bg_color: self.bg_color,
+            notes: self.notes.clone(),
+            guides: self.guides.clone(),
+            markers: self.markers.clone(),
+        }
+    }
+
+    fn matches_snapshot(&self, snapshot: &DocSnapshot) -> bool {
+        self.canvas_size == snapshot.canvas_size
+            && self.bg_color == snapshot.bg_color
+            && self.notes == snapshot.notes
+            && self.guides == snapshot.guides
+            && self.markers == snapshot.markers
+            && self.layers.len() == snapshot.layers.len()
+            && self
+                .layers
+                .iter()
+                .zip(&snapshot.layers)
+                .all(|(a, b)| a.same_state(b))
+    }
+
+    fn restore(&mut self, snapshot: DocSnapshot) {
+        self.layers = snapshot.layers;
+        self.canvas_size = snapshot.canvas_size;
+        self.bg_color = snapshot.bg_color;
+        self.notes = snapshot.notes;
+        self.guides = snapshot.guides;
+        self.markers = snapshot.markers;
+        self.prune_selection();
+    }
+
+    /// Move the active document out of the app's fields, leaving an empty one behind
+    fn take_document(&mut self) -> Document {
+        let empty = Document::new(String::new());
+        Document {
+            name: String::new(),
+            state: DocSnapshot {
+                layers: std::mem::take(&mut self.layers),
+                canvas_size: std::mem::replace(&mut self.canvas_size, empty.state.canvas_size),
+                bg_color: std::mem::replace(&mut self.bg_color, empty.state.bg_color),
+                notes: std::mem::take(&mut self.notes),
+                guides: std::mem::take(&mut self.guides),
+                markers: std::mem::take(&mut self.markers),
+            },
+            history: std::mem::take(&mut self.history),
+            selected_layer_id: self.selected_layer_id.take(),
+            selection: std::mem::take(&mut self.selection),
+            next_id: std::mem::take(&mut self.next_id),
+            preview_zoom: std::mem::replace(&mut self.preview_zoom, empty.preview_zoom),
+            canvas_pan: std::mem::take(&mut self.canvas_pan),
+            project_name: std::mem::take(&mut self.project_name),
+        }
+    }
+
+    /// Make `document` the active one, dropping view state that referred to the old one
+    fn put_document(&mut self, document: Document) {
+        self.layers = document.state.layers;
+        self.canvas_size = document.state.canvas_size;
+        self.bg_color = document.state.bg_color;
+        self.notes = document.state.notes;
+        self.guides = document.state.guides;
+        self.markers = document.state.markers;
+        self.history = document.history;
+        self.selected_layer_id = document.selected_layer_id;
+        self.selection = document.selection;
+        self.next_id = document.next_id;
+        self.preview_zoom = document.preview_zoom;
+        self.canvas_pan = document.canvas_pan;
+        self.project_name = document.project_name;
+
+        self.pending_growth = None;
+        self.placing = None;
+        self.placing_marker = false;
+        self.scatter = None;
+        self.renaming = None;
+        self.move_dialog = None;
+        self.resize_dialog = None;
+        self.reveal_layer = None;
+        self.drag_raw_offset = None;
+        self.canvas_drag_target = None;
+        self.pending_pairs.clear();
+    }
+
+    /// Whether the active document may be swapped out right now: not mid-edit, and not
+    /// while an import is still adding layers to it
+    fn can_switch_document(&self) -> bool {
+        self.history.transaction.is_none() && self.import_queue.is_none()
+    }
+
+    fn switch_document(&mut self, index: usize) {
+        if index == self.active_document
+            || index >= self.documents.len()
+            || !self.can_switch_document()
+        {
+            return;
+        }
+        self.record_pending_edits("Edit");
+        let current = self.take_document();
+        let slot = &mut self.documents[self.active_document];
+        *slot = Document {
+            name: std::mem::take(&mut slot.name),
+            ..current
+        };
+        let target = &mut self.documents[index];
+        let next = std::mem::replace(target, Document::new(target.name.clone()));
+        self.put_document(next);
+        self.active_document = index;
+    }
+
+    fn new_document(&mut self) {
+        if !self.can_switch_document() {
+            return;
+        }
+        let name = format!("Untitled {}", self.documents.len() + 1);
+        self.documents.push(Document::new(name));
+        self.switch_document(self.documents.len() - 1);
+    }
+
+    /// Close a document for good; its layers and undo history are discarded
+    fn close_document(&mut self, index: usize) {
+        if self.documents.len() < 2 || index >= self.documents.len() {
+            return;
+        }
+        if index == self.active_document {
+            if !self.can_switch_document() {
+                return;
+            }
+            self.switch_document(if index == 0 { 1 } else { index - 1 });
+        }
+        self.documents.remove(index);
+        if self.active_document > index {
+            self.active_document -= 1;
+        }
+    }
+
+    /// Put copies of the selected layers on the app-wide layer clipboard
+    fn copy_layers(&mut self) {
+        self.layer_clipboard = self
+            .layers
+            .iter()
+            .filter(|l| self.selection.contains(&l.id))
+            .map(|l| LayerImage {
+                texture: None,
+                thumb_texture: None,
+                ..l.clone()
+            })
+            .collect();
+    }
+
+    /// Add the clipboard layers to the active document with fresh ids, selected
+    fn paste_layers(&mut self) {
+        if self.layer_clipboard.is_empty() {
+            return;
+        }
+        self.begin_transaction("Paste layers");
+        self.selection.clear();
+        for mut layer in self.layer_clipboard.clone() {
+            layer.id = self.allocate_id();
+            self.selection.insert(layer.id);
+            self.selected_layer_id = Some(layer.id);
+            self.layers.push(layer);
+        }
+        self.commit_transaction();
+    }
+
+    /// The only source of new layer ids
+    fn allocate_id(&mut self) -> u64 {
+        self.fix_next_id();
+        let id = self.next_id;
+        self.next_id += 1;
+        id
+    }
+
+    /// Move `next_id` past every id in use, so layers inserted in bulk (or loaded) can't
+    /// make later allocations collide
+    fn fix_next_id(&mut self) {
+        if let Some(max) = max_layer_id(&self.layers) {
+            self.next_id = self.next_id.max(max + 1);
+        }
+    }
+
+    /// Give every layer whose id is already taken by an earlier layer a fresh id
+    fn dedup_layer_ids(&mut self) {
+        self.fix_next_id();
+        let mut seen = HashSet::new();
+        for layer in &mut self.layers {
+            if !seen.insert(layer.id) {
+                layer.id = self.next_id;
+                self.next_id += 1;
+                layer.texture = None;
+            }
+        }
+        self.prune_selection();
+    }
+
+    /// Whether the document may be changed; checked by every mutation entry point
+    fn can_edit(&self) -> bool {
+        !self.viewer_mode
+    }
+
+    /// Viewer mode backstop: roll back any document edit made this frame, keeping only
+    /// layer visibility (viewers may still switch parts on and off)
+    fn reject_edits(&mut self) {
+        let Some(baseline) = self.history.baseline.clone() else {
+            self.history.baseline = Some(self.snapshot());
+            return;
+        };
+        if self.matches_snapshot(&baseline) {
+            return;
+        }
+        let visibility: Vec<(u64, bool)> = self.layers.iter().map(|l| (l.id, l.visible)).collect();
+        self.restore(baseline);
+        for layer in &mut self.layers {
+            if let Some(&(_, visible)) = visibility.iter().find(|(id, _)| *id == layer.id) {
+                layer.visible = visible;
+            }
+        }
+        self.history.baseline = Some(self.snapshot());
+    }
+
+    /// Start an undo step; nested calls fold into the outermost one
+    fn begin_transaction(&mut self, label: &str) {
+        self.open_transaction(label, false);
+    }
+
+    /// Like `begin_transaction`, but allowed to stay open across frames (pointer drags)
+    fn begin_long_transaction(&mut self, label: &str) {
+        self.open_transaction(label, true);
+    }
+
+    fn open_transaction(&mut self, label: &str, spans_frames: bool) {
+        if let Some(transaction) = &mut self.history.transaction {
+            transaction.depth += 1;
+            return;
+        }
+        // Loose edits made before this point become their own step
+        self.record_pending_edits("Edit");
+        self.history.transaction = Some(Transaction {
+            label: label.to_owned(),
+            depth: 1,
+            spans_frames,
+        });
+    }
+
+    fn commit_transaction(&mut self) {
+        let Some(transaction) = &mut self.history.transaction else {
+            debug_assert!(false, "commit_transaction without begin_transaction");
+            return;
+        };
+        transaction.depth -= 1;
+        if transaction.depth == 0 {
+            let label = std::mem::take(&mut transaction.label);
+            self.history.transaction = None;
+            self.record_pending_edits(&label);
+        }
+    }
+
+    /// Fold edits made since the last undo step into that step instead of recording a new one
+    fn amend_last_step(&mut self) {
+        if self.history.transaction.is_none() && !self.history.undo.is_empty() {
+            self.history.baseline = Some(self.snapshot());
+        }
+    }
+
+    /// Push an undo step if the document differs from the last recorded state
+    fn record_pending_edits(&mut self, label: &str) {
+        match &self.history.baseline {
+            Some(baseline) if self.matches_snapshot(baseline) => return,
+            Some(_) => {
+                let state = self.history.baseline.take().unwrap();
+                self.history.undo.push(UndoEntry {
+                    label: label.to_owned(),
+                    state,
+                });
+                if self.history.undo.len() > UNDO_LIMIT {
+                    self.history.undo.remove(0);
+                }
+                self.history.redo.clear();
+                let budget = self.undo_budget_mb as usize * 1024 * 1024;
+                self.history.enforce_budget(&self.layers, budget);
+            }
+            None => {}
+        }
+        self.history.baseline = Some(self.snapshot());
+    }
+
+    fn undo(&mut self) {
+        if self.history.transaction.is_some() {
+            return;
+        }
+        self.pending_growth = None;
+        self.record_pending_edits("Edit");
+        if let Some(entry) = self.history.undo.pop() {
+            self.history.redo.push(UndoEntry {
+                label: entry.label,
+                state: self.snapshot(),
+            });
+            self.restore(entry.state);
+            self.history.baseline = Some(self.snapshot());
+        }
+    }
+
+    fn redo(&mut self) {
+        if self.history.transaction.is_some() {
+            return;
+        }
+        self.pending_growth = None;
+        self.record_pending_edits("Edit");
+        if let Some(entry) = self.history.redo.pop() {
+            self.history.undo.push(UndoEntry {
+                label: entry.label,
+                state: self.snapshot(),
+            });
+            self.restore(entry.state);
+            self.history.baseline = Some(self.snapshot());
+        }
+    }
+
+    /// Frame-end bookkeeping: record loose edits once the user lets go
+    fn finish_frame_history(&mut self, ctx: &egui::Context) {
+        if !self.can_edit() {
+            self.reject_edits();
+            return;
+        }
+        if let Some(transaction) = &self.history.transaction {
+            debug_assert!(
+                transaction.spans_frames,
+                "transaction \"{}\" left open across frames",
+                transaction.label
+            );
+            return;
+        }
+        if let Some(id) = duplicate_layer_id(&self.layers) {
+            debug_assert!(false, "layer id {} is used twice", id);
+            log::error!("Layer id {} is used twice, reassigning", id);
+            self.dedup_layer_ids();
+        }
+        let interacting = ctx.input(|i| i.pointer.any_down()) || ctx.wants_keyboard_input();
+        if !interacting {
+            self.record_pending_edits("Edit");
+        }
+    }
+
+    /// Growth needed for `rect` (canvas space) to fit, in whole increments, or nothing while
+    /// it overflows by no more than the threshold
+    fn needed_growth(&self, rect: egui::Rect) -> CanvasGrowth {
+        let settings = &self.auto_expand;
+        let grow = |overflow: f32| {
+            if overflow > settings.threshold as f32 {
+                let step = settings.increment.max(1);
+                (overflow / step as f32).ceil() as u32 * step
+            } else {
+                0
+            }
+        };
+        CanvasGrowth {
+            left: grow(-rect.min.x),
+            top: grow(-rect.min.y),
+            right: grow(rect.max.x - self.canvas_size[0] as f32),
+            bottom: grow(rect.max.y - self.canvas_size[1] as f32),
+        }
+    }
+
+    /// Add pixels around the canvas (capped at the maximum size) without moving content
+    /// relative to itself or on screen
+    fn grow_canvas(&mut self, growth: CanvasGrowth) {
+        let room_w = MAX_CANVAS_SIZE.saturating_sub(self.canvas_size[0]);
+        let room_h = MAX_CANVAS_SIZE.saturating_sub(self.canvas_size[1]);
+        let left = growth.left.min(room_w);
+        let right = growth.right.min(room_w - left);
+        let top = growth.top.min(room_h);
+        let bottom = growth.bottom.min(room_h - top);
+
+        let shift = egui::vec2(left as f32, top as f32);
+        for layer in &mut self.layers {
+            layer.transform.offset += shift;
+        }
+        self.shift_guides(shift);
+        self.canvas_size[0] += left + right;
+        self.canvas_size[1] += top + bottom;
+        // The canvas stays centered on screen, so pan to keep the content still
+        let recenter = egui::vec2(right as f32 - left as f32, bottom as f32 - top as f32);
+        self.canvas_pan += recenter * self.preview_zoom / 2.0;
+    }
+
+    /// Zoom the canvas view around its center, leaving Fit mode
+    fn set_zoom(&mut self, zoom: f32) {
+        let zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+        self.canvas_pan *= zoom / self.preview_zoom;
+        self.preview_zoom = zoom;
+        self.zoom_fit = false;
+    }
+
+    /// Move guides and free markers along with content shifted by a canvas size change;
+    /// attached markers already follow their layers
+    fn shift_guides(&mut self, shift: egui::Vec2) {
+        for guide in &mut self.guides {
+            guide.position += if guide.vertical { shift.x } else { shift.y };
+        }
+        for marker in self.markers.iter_mut().filter(|m| m.layer.is_none()) {
+            marker.position += shift;
+        }
+    }
+
+    /// Change the canvas size keeping content attached to `anchor` (0, 0.5 or 1 per axis):
+    /// every layer shifts by the anchored share of the size change, as one undo step
+    fn resize_canvas(&mut self, size: [u32; 2], anchor: egui::Vec2) {
+        let old = egui::vec2(self.canvas_size[0] as f32, self.canvas_size[1] as f32);
+        let new = egui::vec2(
+            size[0].clamp(MIN_CANVAS_SIZE, MAX_CANVAS_SIZE) as f32,
+            size[1].clamp(MIN_CANVAS_SIZE, MAX_CANVAS_SIZE) as f32,
+        );
+        // Whole pixels, so pixel art stays on the grid
+        let shift = ((new - old) * anchor).floor();
+
+        self.begin_transaction("Resize canvas");
+        for layer in &mut self.layers {
+            layer.transform.offset += shift;
+        }
+        self.shift_guides(shift);
+        self.canvas_size = [new.x as u32, new.y as u32];
+        self.commit_transaction();
+        // The canvas stays centered on screen, so pan to keep the content still
+        self.canvas_pan += ((new - old) / 2.0 - shift) * self.preview_zoom;
+    }
+
+    /// Forget every GPU texture the app holds, including those in undo history, so they
+    /// re-upload lazily from the CPU-side images. Used after the graphics context was lost.
+    fn drop_textures(&mut self) {
+        let history = &mut self.history;
+        let snapshots = history
+            .undo
+            .iter_mut()
+            .chain(history.redo.iter_mut())
+            .map(|entry| &mut entry.state)
+            .chain(history.baseline.as_mut());
+        let layers = snapshots
+            .flat_map(|snapshot| snapshot.layers.iter_mut())
+            .chain(self.layers.iter_mut());
+        for layer in layers {
+            layer.texture = None;
+            layer.thumb_texture = None;
+        }
+        self.composite_preview = None;
+    }
+
+    /// Move a layer to `index` in the list as one undo step and scroll to it
+    fn move_layer_to_index(&mut self, id: u64, index: usize) {
+        let Some(from) = self.layers.iter().position(|l| l.id == id) else {
+            return;
+        };
+        let to = index.min(self.layers.len() - 1);
+        if from != to {
+            self.begin_transaction("Move layer");
+            let layer = self.layers.remove(from);
+            self.layers.insert(to, layer);
+            self.commit_transaction();
+        }
+        self.reveal_layer = Some(id);
+    }
+
+    /// Route an event from a background task to its handler
+    fn handle_event(&mut self, ctx: &egui::Context, event: AppEvent) {
+        match event {
+            AppEvent::Import(event) => self.handle_import_event(ctx, event),
+            AppEvent::Io(event) => self.handle_io_event(ctx, event),
+            AppEvent::Export(event) => self.handle_export_event(ctx, event),
+        }
+    }
+
+    fn handle_export_event(&mut self, ctx: &egui::Context, event: ExportEvent) {
+        match event {
+            ExportEvent::Progress(fraction) => {
+                if let Some(job) = &mut self.export_job {
+                    job.progress = fraction;
+                }
+            }
+            ExportEvent::Finished(files) => {
+                self.export_job = None;
+                self.save_files(files);
+            }
+            ExportEvent::Failed(err) => {
+                self.export_job = None;
+                log::error!("{}", err);
+                self.show_toast(ctx, err.clone());
+                self.export_error = Some(err);
+            }
+            ExportEvent::Cancelled => {
+                self.export_job = None;
+                log::info!("Export cancelled");
+                self.show_toast(ctx, "Export cancelled");
+            }
+            ExportEvent::Estimated(key, png_bytes, html_bytes) => {
+                // Results for a document that has changed since are dropped
+                if let Some((_, estimate)) =
+                    self.export_estimate.as_mut().filter(|(k, _)| *k == key)
+                {
+                    estimate.png_bytes = Some(png_bytes);
+                    estimate.html_bytes = Some(html_bytes);
+                }
+            }
+            ExportEvent::Previewed(key, img, encoded_bytes) => {
+                // A closed dialog drops the result; a stale one still beats a spinner
+                // and is replaced on the next frame
+                let Some(preview) = &mut self.export_preview else {
+                    return;
+                };
+                if preview.building != Some(key) {
+                    return;
+                }
+                preview.building = None;
+                let texture = ctx.load_texture(
+                    "export_preview",
+                    egui::ColorImage::from_rgba_unmultiplied(
+                        [img.width() as _, img.height() as _],
+                        img.as_flat_samples().as_slice(),
+                    ),
+                    egui::TextureOptions::NEAREST,
+                );
+                let output = self.canvas_size[0].max(self.canvas_size[1]) * self.export_scale;
+                if preview.zoom == 0.0 {
+                    preview.zoom = (400.0 / output.max(1) as f32).min(1.0);
+                }
+                preview.image = Some((key, texture));
+                preview.encoded_bytes = encoded_bytes;
+            }
+            ExportEvent::Paletted(key, colors) => {
+                if self.palette_building == Some(key) {
+                    self.palette_building = None;
+                    self.palette_preview = Some((key, colors));
+                }
+            }
+        }
+    }
+
+    fn handle_import_event(&mut self, ctx: &egui::Context, event: ImportEvent) {
+        match event {
+            ImportEvent::Loaded(name, img) => {
+                log::debug!("Imported {} ({}x{})", name, img.width(), img.height());
+                let id = self.allocate_id();
+                let layer = LayerImage::new(id, name, img);
+                self.layers.push(layer);
+                if let Some(queue) = &mut self.import_queue {
+                    queue.imported.push(id);
+                    queue.finished += 1;
+                }
+            }
+            ImportEvent::Failed(message) => {
+                log::warn!("{}", message);
+                self.show_toast(ctx, message);
+                if let Some(queue) = &mut self.import_queue {
+                    queue.finished += 1;
+                }
+            }
+            ImportEvent::Queued(files) => {
+                log::info!("Importing {} file(s)", files.len());
+                if let Some(queue) = &mut self.import_queue {
+                    queue.files = files;
+                }
+            }
+            ImportEvent::Started(index) => {
+                if let Some(queue) = &mut self.import_queue {
+                    queue.current = Some(index);
+                }
+            }
+            ImportEvent::Skipped(index) => {
+                log::debug!("Skipped import #{}", index);
+                if let Some(queue) = &mut self.import_queue {
+                    queue.finished += 1;
+                    queue.skipped.push(index);
+                }
+            }
+            ImportEvent::Finished => {
+                if let Some(queue) = self.import_queue.take() {
+                    log::info!(
+                        "Import finished: {} imported, {} skipped",
+                        queue.imported.len(),
+                        queue.skipped.len()
+                    );
+                    if self.mirror_pairs.enabled {
+                        let imported: Vec<_> = self
+                            .layers
+                            .iter()
+                            .filter(|l| queue.imported.contains(&l.id))
+                            .collect();
+                        self.pending_pairs =
+                            detect_mirror_pairs(&imported, &self.mirror_pairs.suffix_pairs());
+                    }
+                }
+            }
+        }
+    }
+
+    fn handle_io_event(&mut self, ctx: &egui::Context, event: IoEvent) {
+        match event {
+            IoEvent::ProfileLoaded(bytes) => {
+                match serde_json::from_slice::<SettingsProfile>(&bytes) {
+                    Ok(profile) => {
+                        log::info!("Loaded settings profile");
+                        self.pending_profile = Some(self.review_profile(profile));
+                        self.profile_error = None;
+                    }
+                    Err(err) => {
+                        log::warn!("Invalid settings profile: {}", err);
+                        self.profile_error = Some(format!("Invalid settings profile: {}", err));
+                    }
+                }
+            }
+            IoEvent::VerifyZipLoaded(bytes) => {
+                self.verify_report = Some(
+                    self.export_snapshot()
+                        .verify_export(&bytes, self.verify_recomposite)
+                        .unwrap_or_else(|err| vec![err]),
+                );
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            IoEvent::SaveRequested(dir) => self.last_export_dir = Some(dir),
+            #[cfg(not(target_arch = "wasm32"))]
+            IoEvent::SaveCompleted(Ok(saved)) => {
+                log::info!("{}", saved);
+                self.show_toast(ctx, saved);
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            IoEvent::SaveCompleted(Err(err)) => {
+                log::error!("{}", err);
+                self.export_error = Some(err.clone());
+                self.show_toast(ctx, err);
+            }
+        }
+    }
+
+    /// The active document's project name, or its tab name when none is set
+    fn project_label(&self) -> &str {
+        match self.project_name.trim() {
+            "" => self.documents[self.active_document].name.trim(),
+            name => name,
+        }
+    }
+
+    /// File name stem for an export started now: the filename template with `{project}`
+    /// and `{timestamp}` filled in, made safe for any file system
+    fn export_stem(&self) -> String {
+        let project = match self.project_label() {
+            "" => "kitbash",
+            name => name,
+        };
+        let stem = self
+            .export_filename_template
+            .replace("{project}", project)
+            .replace("{timestamp}", &export_timestamp());
+        sanitize_entry_stem(&stem)
+    }
+
+    /// Run `job` on a worker thread and save the files it returns. `job` works on an export
+    /// snapshot, so editing goes on meanwhile. The web has no threads: there it runs on the
+    /// UI thread in slices, pausing in `ExportProgress::step`.
+    fn start_export<F>(
+        &mut self,
+        label: &str,
+        job: impl FnOnce(ExportProgress) -> F + Send + 'static,
+    ) where
+        F: std::future::Future<Output = Result<Vec<(String, Vec<u8>)>, KitbashError>> + 'static,
+    {
+        if self.export_job.is_some() {
+            return;
+        }
+        self.export_error = None;
+        let cancel = Arc::new(AtomicBool::new(false));
+        self.export_job = Some(ExportJob {
+            label: label.to_owned(),
+            progress: 0.0,
+            cancel: cancel.clone(),
+        });
+        let progress = ExportProgress {
+            events: self.events.clone(),
+            cancel,
+            #[cfg(target_arch = "wasm32")]
+            last_yield: std::cell::Cell::new(js_sys::Date::now()),
+        };
+        let events = self.events.clone();
+        let finish = move |result: Result<Vec<(String, Vec<u8>)>, KitbashError>| {
+            let event = match result {
+                Ok(files) => ExportEvent::Finished(files),
+                Err(KitbashError::Cancelled) => ExportEvent::Cancelled,
+                Err(err) => ExportEvent::Failed(err.to_string()),
+            };
+            events.send(AppEvent::Export(event));
+        };
+        #[cfg(not(target_arch = "wasm32"))]
+        std::thread::spawn(move || {
+            // A panicking export must still end the job, or the progress bar stays forever
+            let run = || futures::executor::block_on(job(progress));
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(run))
+                .unwrap_or_else(|payload| {
+                    Err(KitbashError::Export(format!(
+                        "Export crashed: {}",
+                        panic_message(&*payload)
+                    )))
+                });
+            finish(result);
+        });
+        #[cfg(target_arch = "wasm32")]
+        wasm_bindgen_futures::spawn_local(async move { finish(job(progress).await) });
+    }
+
+    /// Hand exported files to the user. The web gets one download per file; desktop asks
+    /// where to save in the background (a folder picker when there are several files),
+    /// starting in the last export directory, and reports back with `IoEvent`s.
+    fn save_files(&mut self, files: Vec<(String, Vec<u8>)>) {
+        if files.is_empty() {
+            return;
+        }
+        #[cfg(target_arch = "wasm32")]
+        for (filename, data) in &files {
+            if let Err(err) = trigger_download(filename, data) {
+                log::error!("{}", err);
+                self.export_error = Some(err);
+                break;
+            }
+        }
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let mut dialog = rfd::AsyncFileDialog::new();
+            if let Some(dir) = &self.last_export_dir {
+                dialog = dialog.set_directory(dir);
+            }
+            let task = save_files_task(dialog, files, self.backup_policy, self.events.clone());
+            std::thread::spawn(move || {
+                futures::executor::block_on(task);
+            });
+        }
+    }
+
+    /// Show a short notice at the bottom of the window, above any still showing
+    fn show_toast(&mut self, ctx: &egui::Context, text: impl Into<String>) {
+        let expires = ctx.input(|i| i.time) + TOAST_SECONDS;
+        self.toasts.push_back((text.into(), expires));
+        if self.toasts.len() > TOAST_LIMIT {
+            self.toasts.pop_front();
+        }
+    }
+
+    /// Replace the selected layer's image with the one on the clipboard
+    fn paste_replace(&mut self, ctx: &egui::Context) {
+        let Some(id) = self.selected_layer_id else {
+            self.show_toast(ctx, "Select a layer to paste into");
+            return;
+        };
+        let Some(idx) = self.layers.iter().position(|l| l.id == id) else {
+            return;
+        };
+        if self.layers[idx].locked {
+            self.show_toast(ctx, format!("{} is locked", self.layers[idx].name));
+            return;
+        }
+        match clipboard_image() {
+            Ok(image) => {
+                log::info!(
+                    "Pasted {}x{} image into {}",
+                    image.width(),
+                    image.height(),
+                    self.layers[idx].name
+                );
+                self.begin_transaction("Paste replace");
+                let keep_center = self.replace_keeps_center;
+                self.layers[idx].replace_source(image, keep_center);
+                self.commit_transaction();
+            }
+            Err(err) => self.show_toast(ctx, err),
+        }
+    }
+
+    /// Copy a layer (sharing its source image) right after the original and select it
+    fn duplicate_layer(&mut self, id: u64) -> Option<u64> {
+        let idx = self.layers.iter().position(|l| l.id == id)?;
+        self.begin_transaction("Duplicate layer");
+        let new_id = self.allocate_id();
+
+        let mut copy = self.layers[idx].clone();
+        copy.id = new_id;
+        copy.name = format!("{} copy", copy.name);
+        copy.texture = None;
+        self.layers.insert(idx + 1, copy);
+        self.select_only(new_id);
+        self.commit_transaction();
+        Some(new_id)
+    }
+
+    /// x coordinate (canvas pixels) of the symmetry axis
+    fn mirror_axis_x(&self) -> f32 {
+        self.mirror_axis.unwrap_or(self.canvas_size[0] as f32 / 2.0)
+    }
+
+    /// Add a flipped copy of every visible layer, mirrored across the symmetry axis, right
+    /// after its source, as one undo step
+    fn apply_mirror(&mut self) {
+        let axis = self.mirror_axis_x();
+        self.begin_transaction("Apply mirror");
+        self.selection.clear();
+        let mut idx = 0;
+        while idx < self.layers.len() {
+            let source = &self.layers[idx];
+            if !source.visible || source.is_reference {
+                idx += 1;
+                continue;
+            }
+            let rect = egui::Rect::from_min_size(source.top_left().to_pos2(), source.scaled_size());
+            let mut copy = source.clone();
+            copy.id = self.allocate_id();
+            copy.name = format!("{} (Mirrored)", copy.name);
+            copy.texture = None;
+            copy.transform.flip_h = !copy.transform.flip_h;
+            copy.set_top_left(egui::vec2(2.0 * axis - rect.max.x, rect.min.y));
+            self.selection.insert(copy.id);
+            self.selected_layer_id = Some(copy.id);
+            self.layers.insert(idx + 1, copy);
+            idx += 2;
+        }
+        self.commit_transaction();
+    }
+
+    /// Replace the visible layers in `ids` with one layer holding their composited pixels,
+    /// trimmed to the opaque content. It takes the topmost merged layer's place.
+    fn merge_layers(&mut self, ids: &[u64]) -> Option<u64> {
+        let merged: Vec<usize> = draw_order(&self.layers)
+            .into_iter()
+            .filter(|&i| ids.contains(&self.layers[i].id) && self.layers[i].visible)
+            .collect();
+        if merged.len() < 2 {
+            return None;
+        }
+
+        // Composite just these layers, shifted so their combined footprint starts at 0,0
+        let bounds = merged
+            .iter()
+            .map(|&i| self.layers[i].render_rect())
+            .reduce(|a, b| a.union(b))?;
+        let origin = bounds.min.to_vec2();
+        let parts: Vec<LayerImage> = merged
+            .iter()
+            .map(|&i| {
+                let mut part = self.layers[i].clone();
+                part.transform.offset -= origin;
+                part.z_override = None; // Already in draw order
+                part
+            })
+            .collect();
+        let img = composite_image(
+            [bounds.width() as u32, bounds.height() as u32],
+            &parts,
+            1,
+            egui::Color32::TRANSPARENT,
+            &OutputEffects::default(),
+        );
+        let [x, y, w, h] = alpha_bounds(&img)?;
+        let trimmed = image::imageops::crop_imm(&img, x, y, w, h).to_image();
+
+        self.begin_transaction("Merge layers");
+        let top = *merged.last()?;
+        let id = self.allocate_id();
+        let mut layer = LayerImage::new(
+            id,
+            format!("{} (merged)", self.layers[top].name),
+            image::DynamicImage::ImageRgba8(trimmed),
+        );
+        layer.transform.offset = origin + egui::vec2(x as f32, y as f32);
+        layer.z_override = self.layers[top].z_override;
+        self.layers[top] = layer;
+
+        let removed: Vec<u64> = merged[..merged.len() - 1]
+            .iter()
+            .map(|&i| self.layers[i].id)
+            .collect();
+        self.layers.retain(|l| !removed.contains(&l.id));
+        self.select_only(id);
+        self.commit_transaction();
+        Some(id)
+    }
+
+    /// Merge a layer with the one drawn directly below it
+    fn merge_down(&mut self, id: u64) -> Option<u64> {
+        let order = draw_order(&self.layers);
+        let pos = order.iter().position(|&i| self.layers[i].id == id)?;
+        let below = self.layers[*order.get(pos.checked_sub(1)?)?].id;
+        self.merge_layers(&[below, id])
+    }
+
+    /// Insert the scatter copies right after their source layer
+    fn apply_scatter(&mut self, scatter: &ScatterSettings) {
+        let Some(idx) = self.layers.iter().position(|l| l.id == scatter.source_id) else {
+            return;
+        };
+        self.begin_transaction("Scatter");
+        let source = self.layers[idx].clone();
+        let mut copies = Vec::new();
+        for (i, placement) in scatter.placements(&source).iter().enumerate() {
+            let mut copy = source.clone();
+            copy.id = self.allocate_id();
+            copy.name = format!("{} #{}", source.name, i + 1);
+            copy.texture = None;
+            placement.apply(&mut copy);
+            copies.push(copy);
+        }
+        self.layers.splice(idx + 1..idx + 1, copies);
+        self.commit_transaction();
+    }
+
+    /// Flip the right layer of each accepted pair and place it mirrored about the canvas
+    /// center relative to its left counterpart
+    fn mirror_pairs_apply(&mut self, pairs: &[MirrorPair]) {
+        self.begin_transaction("Mirror pairs");
+        let canvas_w = self.canvas_size[0] as f32;
+        for pair in pairs.iter().filter(|p| p.accepted) {
+            let Some(left) = self.layers.iter().find(|l| l.id == pair.left_id) else {
+                continue;
+            };
+            let left_rect =
+                egui::Rect::from_min_size(left.top_left().to_pos2(), left.scaled_size());
+            if let Some(right) = self.layers.iter_mut().find(|l| l.id == pair.right_id) {
+                right.transform.flip_h = true;
+                right.set_top_left(egui::vec2(canvas_w - left_rect.max.x, left_rect.min.y));
+            }
+        }
+        self.commit_transaction();
+    }
+
+    /// Ids the bulk tools (align, distribute, ...) operate on, in list order
+    fn selected_ids(&self) -> Vec<u64> {
+        self.layers
+            .iter()
+            .filter(|l| self.selection.contains(&l.id))
+            .map(|l| l.id)
+            .collect()
+    }
+
+    /// Set (or with `None` remove) one user data key on several layers as one undo step
+    fn set_user_data(&mut self, ids: &[u64], key: &str, value: Option<&str>) {
+        self.begin_transaction("Edit user data");
+        for layer in self.layers.iter_mut().filter(|l| ids.contains(&l.id)) {
+            match value {
+                Some(value) => layer.user_data.insert(key.to_owned(), value.to_owned()),
+                None => layer.user_data.remove(key),
+            };
+        }
+        self.commit_transaction();
+    }
+
+    /// Plain click: select just this layer
+    fn select_only(&mut self, id: u64) {
+        self.selection.clear();
+        self.selection.insert(id);
+        self.selected_layer_id = Some(id);
+    }
+
+    /// Ctrl+click: add or remove one layer, moving the primary along
+    fn toggle_selected(&mut self, id: u64) {
+        if self.selection.remove(&id) {
+            if self.selected_layer_id == Some(id) {
+                self.selected_layer_id = self.selected_ids().last().copied();
+            }
+        } else {
+            self.selection.insert(id);
+            self.selected_layer_id = Some(id);
+        }
+    }
+
+    /// Shift+click: select every layer in the list between the primary and `id`
+    fn select_range(&mut self, id: u64) {
+        let position = |target: u64| self.layers.iter().position(|l| l.id == target);
+        let (Some(anchor), Some(end)) = (self.selected_layer_id.and_then(position), position(id))
+        else {
+            self.select_only(id);
+            return;
+        };
+        let range = anchor.min(end)..=anchor.max(end);
+        self.selection = self.layers[range].iter().map(|l| l.id).collect();
+        // Keep the primary so further Shift+clicks extend from the same anchor
+    }
+
+    /// Apply a click on a layer with the current modifiers
+    fn click_select(&mut self, id: u64, modifiers: egui::Modifiers) {
+        if modifiers.shift {
+            self.select_range(id);
+        } else if modifiers.command {
+            self.toggle_selected(id);
+        } else {
+            self.select_only(id);
+        }
+    }
+
+    /// Drop selected ids that no longer exist
+    fn prune_selection(&mut self) {
+        let layers = &self.layers;
+        self.selection
+            .retain(|id| layers.iter().any(|l| l.id == *id));
+        if let Some(id) = self.selected_layer_id {
+            if !self.selection.contains(&id) {
+                self.selected_layer_id = None;
+            }
+        }
+    }
+
+    /// Remove the given layers as one undo step
+    fn delete_layers(&mut self, ids: &[u64]) {
+        self.begin_transaction("Delete layer");
+        self.layers.retain(|l| !ids.contains(&l.id));
+        self.prune_selection();
+        self.commit_transaction();
+    }
+
+    /// Canvas-space rect of each unlocked layer in `ids`
+    fn layer_bounds(&self, ids: &[u64]) -> Vec<(u64, egui::Rect)> {
+        self.layers
+            .iter()
+            .filter(|l| ids.contains(&l.id) && !l.locked)
+            .map(|l| {
+                let rect = egui::Rect::from_min_size(l.top_left().to_pos2(), l.scaled_size());
+                (l.id, rect)
+            })
+            .collect()
+    }
+
+    /// Move each layer so its top-left corner ends up at the given canvas position
+    fn move_layers_to(&mut self, label: &str, targets: &[(u64, egui::Pos2)]) {
+        self.begin_transaction(label);
+        for &(id, pos) in targets {
+            if let Some(layer) = self.layers.iter_mut().find(|l| l.id == id) {
+                layer.set_top_left(pos.to_vec2().round());
+            }
+        }
+        self.commit_transaction();
+    }
+
+    /// Align layers to the canvas, or to their common bounding box
+    fn align_layers(&mut self, ids: &[u64], align: Align, to_canvas: bool) {
+        let bounds = self.layer_bounds(ids);
+        let reference = if to_canvas {
+            egui::Rect::from_min_size(
+                egui::Pos2::ZERO,
+                egui::vec2(self.canvas_size[0] as f32, self.canvas_size[1] as f32),
+            )
+        } else {
+            match bounds.iter().map(|(_, r)| *r).reduce(|a, b| a.union(b)) {
+                Some(rect) => rect,
+                None => return,
+            }
+        };
+
+        let targets: Vec<_> = bounds
+            .iter()
+            .map(|&(id, rect)| {
+                let mut min = rect.min;
+                match align {
+                    Align::Left => min.x = reference.min.x,
+                    Align::CenterH => min.x = reference.center().x - rect.width() / 2.0,
+                    Align::Right => min.x = reference.max.x - rect.width(),
+                    Align::Top => min.y = reference.min.y,
+                    Align::CenterV => min.y = reference.center().y - rect.height() / 2.0,
+                    Align::Bottom => min.y = reference.max.y - rect.height(),
+                }
+                (id, min)
+            })
+            .collect();
+        self.move_layers_to("Align", &targets);
+    }
+
+    /// Space layers so the gaps between them are equal, keeping the outermost two in place
+    fn distribute_layers(&mut self, ids: &[u64], horizontal: bool) {
+        let axis = |v: egui::Vec2| if horizontal { v.x } else { v.y };
+        let mut bounds = self.layer_bounds(ids);
+        if bounds.len() < 3 {
+            return;
+        }
+        bounds.sort_by(|a, b| axis(a.1.min.to_vec2()).total_cmp(&axis(b.1.min.to_vec2())));
+
+        let start = axis(bounds[0].1.min.to_vec2());
+        let end = bounds
+            .iter()
+            .map(|(_, r)| axis(r.max.to_vec2()))
+            .fold(f32::MIN, f32::max);
+        let total: f32 = bounds.iter().map(|(_, r)| axis(r.size())).sum();
+        let gap = (end - start - total) / (bounds.len() - 1) as f32;
+
+        let mut cursor = start;
+        let targets: Vec<_> = bounds
+            .iter()
+            .map(|&(id, rect)| {
+                let mut min = rect.min;
+                if horizontal {
+                    min.x = cursor;
+                } else {
+                    min.y = cursor;
+                }
+                cursor += axis(rect.size()) + gap;
+                (id, min)
+            })
+            .collect();
+        self.move_layers_to("Distribute", &targets);
+    }
+
+    /// Cheap key for everything that affects the 1x composite
+    fn composite_key(&self) -> u64 {
+        let mut hasher = Fnv64::default();
+        hasher.write_u32(self.canvas_size[0]);
+        hasher.write_u32(self.canvas_size[1]);
+        hasher.write(&self.bg_color.to_array());
+        self.output_effects.hash_into(&mut hasher);
+        for idx in draw_order(&self.layers) {
+            let layer = &self.layers[idx];
+            // Unlike the image's address, a revision is never reused after the image is freed
+            hasher.write(&layer.source_revision.to_le_bytes());
+            layer.hash_render_state(&mut hasher);
+        }
+        hasher.finish()
+    }
+
+    /// The 1x composite for pixel readouts, rebuilt once edits settle; `None` while stale
+    fn composite_sample(&mut self, ctx: &egui::Context) -> Option<&RgbaImage> {
+        const DEBOUNCE: f64 = 0.15;
+
+        let key = self.composite_key();
+        let now = ctx.input(|i| i.time);
+        if self
+            .composite_sample
+            .as_ref()
+            .is_none_or(|(k, _)| *k != key)
+        {
+            if self.composite_sample_seen.0 != key {
+                self.composite_sample_seen = (key, now);
+            }
+            let waited = now - self.composite_sample_seen.1;
+            if waited < DEBOUNCE {
+                ctx.request_repaint_after(std::time::Duration::from_secs_f64(DEBOUNCE - waited));
+                return None;
+            }
+            let img = composite_image(
+                self.canvas_size,
+                &self.layers,
+                1,
+                self.bg_color,
+                &self.output_effects,
+            );
+            self.composite_sample = Some((key, img));
+        }
+        self.composite_sample.as_ref().map(|(_, img)| img)
+    }
+
+    /// Rebuild the output-effects preview once the document has been still for a moment
+    fn update_composite_preview(&mut self, ctx: &egui::Context) {
+        const DEBOUNCE: f64 = 0.15;
+
+        let key = self.composite_key();
+        if self
+            .composite_preview
+            .as_ref()
+            .is_some_and(|(k, _)| *k == key)
+        {
+            return;
+        }
+
+        let now = ctx.input(|i| i.time);
+        if self.composite_preview_seen.0 != key {
+            self.composite_preview_seen = (key, now);
+        }
+        let waited = now - self.composite_preview_seen.1;
+        // The first build is immediate; later ones wait until edits settle
+        if self.composite_preview.is_some() && waited < DEBOUNCE {
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64(DEBOUNCE - waited));
+            return;
+        }
+
+        let img = composite_image(
+            self.canvas_size,
+            &self.layers,
+            1,
+            self.bg_color,
+            &self.output_effects,
+        );
+        let tex = ctx.load_texture(
+            "composite_preview",
+            egui::ColorImage::from_rgba_unmultiplied(
+                [img.width() as _, img.height() as _],
+                img.as_flat_samples().as_slice(),
+            ),
+            egui::TextureOptions::NEAREST,
+        );
+        self.composite_preview = Some((key, tex));
+    }
+
+    /// Snapshot for the composited image. JPEG has no alpha, so it is composited on its
+    /// matte color instead of the export background, and the stamp records which.
+    fn composite_snapshot(&self) -> CompositeSnapshot {
+        let mut snapshot = self.export_snapshot();
+        if self.composite_format == CompositeFormat::Jpeg {
+            snapshot.bg_color = self.jpeg_matte;
+            snapshot.matte = Some(self.jpeg_matte);
+        }
+        snapshot
+    }
+
+    /// Save the composited image in the chosen format, in the background
+    fn download_composite(&mut self) {
+        let snapshot = self.composite_snapshot();
+        let matte = self.jpeg_matte;
+        let filename = format!(
+            "{}{}.{}",
+            self.export_stem(),
+            scale_suffix(self.export_scale),
+            snapshot.composite_format.extension()
+        );
+        self.start_export("Image", move |progress| async move {
+            progress.step(0, 1).await?;
+            let stamp = snapshot.export_stamp().to_string();
+            let bytes = encode_composite(
+                &snapshot.composite(),
+                snapshot.composite_format,
+                snapshot.jpeg_quality,
+                snapshot.palette_limit,
+                matte,
+                &stamp,
+            )?;
+            Ok(vec![(filename, bytes)])
+        });
+    }
+
+    /// Format picker for the composited image, with its quality or palette options
+    fn composite_format_ui(&mut self, ui: &mut egui::Ui) {
+        egui::ComboBox::from_id_salt("composite_format")
+            .width(110.0)
+            .selected_text(self.composite_format.label())
+            .show_ui(ui, |ui| {
+                for format in CompositeFormat::ALL {
+                    ui.selectable_value(&mut self.composite_format, format, format.label());
+                }
+            });
+        if self.composite_format == CompositeFormat::Jpeg {
+            ui.add(egui::Slider::new(&mut self.jpeg_quality, 1..=100).text("Quality"));
+            ui.horizontal(|ui| {
+                ui.label("Matte:");
+                let mut rgb = [
+                    self.jpeg_matte.r(),
+                    self.jpeg_matte.g(),
+                    self.jpeg_matte.b(),
+                ];
+                if ui.color_edit_button_srgb(&mut rgb).changed() {
+                    self.jpeg_matte = egui::Color32::from_rgb(rgb[0], rgb[1], rgb[2]);
+                }
+            })
+            .response
+            .on_hover_text("JPEG has no transparency, so it is composited on this color");
+        }
+        if self.composite_format == CompositeFormat::Png {
+            let limit = &mut self.palette_limit;
+            ui.checkbox(&mut limit.enabled, "Limit Palette")
+                .on_hover_text("Quantize to a few colors and save an indexed PNG");
+            if limit.enabled {
+                ui.add(
+                    egui::DragValue::new(&mut limit.colors)
+                        .range(2..=palette::MAX_COLORS)
+                        .suffix(" colors"),
+                );
+                egui::ComboBox::from_id_salt("palette_dither")
+                    .width(110.0)
+                    .selected_text(limit.dither.label())
+                    .show_ui(ui, |ui| {
+                        for dither in palette::Dither::ALL {
+                            ui.selectable_value(&mut limit.dither, dither, dither.label());
+                        }
+                    });
+            }
+        }
+    }
+
+    /// Rebuild the export preview when the document or an export option changed. Waits
+    /// while the pointer is down, so dragging a slider doesn't re-encode every step, and
+    /// while a rebuild is still running; the worker's result shows up in `handle_export_event`.
+    fn update_export_preview(&mut self, ctx: &egui::Context) {
+        let mut hasher = Fnv64::default();
+        hasher.write(&self.composite_key().to_le_bytes());
+        hasher.write_u32(self.export_scale);
+        hasher.write(&self.export_bg_color().to_array());
+        hasher.write(self.composite_format.extension().as_bytes());
+        hasher.write(&[self.jpeg_quality, self.palette_limit.enabled as u8]);
+        hasher.write(&self.jpeg_matte.to_array());
+        hasher.write(&self.palette_limit.colors.to_le_bytes());
+        hasher.write(self.palette_limit.dither.label().as_bytes());
+        let key = hasher.finish();
+        let pointer_down = ctx.input(|i| i.pointer.any_down());
+        let Some(preview) = &mut self.export_preview else {
+            return;
+        };
+        if preview.image.as_ref().is_some_and(|(k, _)| *k == key)
+            || preview.building.is_some()
+            || (preview.image.is_some() && pointer_down)
+        {
+            return;
+        }
+
+        preview.building = Some(key);
+        let snapshot = self.composite_snapshot();
+        let matte = self.jpeg_matte;
+        let events = self.events.clone();
+        spawn_task(move || async move {
+            let (img, encoded_bytes) = snapshot.export_preview(matte).await;
+            events.send(AppEvent::Export(ExportEvent::Previewed(
+                key,
+                img,
+                encoded_bytes,
+            )));
+        });
+    }
+
+    /// Recompute the limited palette's swatches once edits settle; it comes from the 1x
+    /// composite, which yields the same palette as any nearest-neighbor export scale
+    fn update_palette_preview(&mut self, ctx: &egui::Context) {
+        let mut hasher = Fnv64::default();
+        hasher.write(&self.composite_key().to_le_bytes());
+        hasher.write(&self.palette_limit.colors.to_le_bytes());
+        hasher.write(&self.export_bg_color().to_array());
+        let key = hasher.finish();
+        if self
+            .palette_preview
+            .as_ref()
+            .is_some_and(|(k, _)| *k == key)
+            || self.palette_building.is_some()
+            || ctx.input(|i| i.pointer.any_down())
+        {
+            return;
+        }
+
+        // Median cut over a large canvas takes a while, so it runs like the export preview
+        self.palette_building = Some(key);
+        let snapshot = self.export_snapshot();
+        let events = self.events.clone();
+        spawn_task(move || async move {
+            let colors = snapshot.limited_palette().await;
+            events.send(AppEvent::Export(ExportEvent::Paletted(key, colors)));
+        });
+    }
+
+    /// Build the HTML viewer in the background; it is saved when the job finishes
+    fn save_html_viewer(&mut self) {
+        let snapshot = self.export_snapshot();
+        let filename = format!("{}_viewer.html", self.export_stem());
+        self.start_export("HTML viewer", move |progress| async move {
+            let html = snapshot.build_html_viewer(&progress).await?;
+            log::info!("Built HTML viewer ({})", format_bytes(html.len()));
+            Ok(vec![(filename, html.into_bytes())])
+        });
+    }
+
+    /// Refresh the export estimate when the document or export scale changed
+    fn update_export_estimate(&mut self, ctx: &egui::Context) {
+        const DEBOUNCE: f64 = 0.3;
+
+        let mut hasher = Fnv64::default();
+        hasher.write(&self.composite_key().to_le_bytes());
+        hasher.write_u32(self.export_scale);
+        hasher.write(&self.export_bg_color().to_array());
+        let key = hasher.finish();
+        if self
+            .export_estimate
+            .as_ref()
+            .is_some_and(|(k, _)| *k == key)
+        {
+            return;
+        }
+        let now = ctx.input(|i| i.time);
+        if self.export_estimate_seen.0 != key {
+            self.export_estimate_seen = (key, now);
+        }
+        let waited = now - self.export_estimate_seen.1;
+        // Compositing on every frame of a drag would be wasted work, so wait for edits to settle
+        if self.export_estimate.is_some() && waited < DEBOUNCE {
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64(DEBOUNCE - waited));
+            return;
+        }
+
+        // The file sizes need composites and encodes, so they arrive later from a worker
+        self.export_estimate = Some((key, self.estimate_export()));
+        let snapshot = self.export_snapshot();
+        let events = self.events.clone();
+        spawn_task(move || async move {
+            let png_bytes = snapshot.estimate_png_bytes().await;
+            let html_bytes = snapshot.estimate_html_viewer_bytes().await;
+            events.send(AppEvent::Export(ExportEvent::Estimated(
+                key, png_bytes, html_bytes,
+            )));
+        });
+    }
+
+    /// Which layers contribute to the export and how large it comes out, without file sizes
+    fn estimate_export(&self) -> ExportEstimate {
+        let canvas = egui::Rect::from_min_size(
+            egui::Pos2::ZERO,
+            egui::vec2(self.canvas_size[0] as f32, self.canvas_size[1] as f32),
+        );
+        let mut estimate = ExportEstimate {
+            contributing: 0,
+            total: self.layers.len(),
+            largest_source: None,
+            output_size: [
+                self.canvas_size[0] * self.export_scale,
+                self.canvas_size[1] * self.export_scale,
+            ],
+            png_bytes: None,
+            html_bytes: None,
+            skipped: Vec::new(),
+        };
+        for layer in &self.layers {
+            let mut reach = layer.content_bounds();
+            if layer.shadow.enabled {
+                let [sx, sy] = layer.shadow.offset;
+                reach = reach.union(reach.translate(egui::vec2(sx as f32, sy as f32)));
+            }
+            let size = [layer.source_image.width(), layer.source_image.height()];
+            let reason = if layer.is_reference {
+                Some("reference")
+            } else if !layer.visible {
+                Some("hidden")
+            } else if layer.tint.a() == 0 || size[0] == 0 || size[1] == 0 {
+                Some("fully transparent")
+            } else if !reach.intersects(canvas) {
+                Some("outside the canvas")
+            } else {
+                None
+            };
+            match reason {
+                Some(reason) => estimate.skipped.push(format!("{}: {}", layer.name, reason)),
+                None => {
+                    estimate.contributing += 1;
+                    let area = |s: [u32; 2]| s[0] as u64 * s[1] as u64;
+                    if estimate
+                        .largest_source
+                        .is_none_or(|largest| area(size) > area(largest))
+                    {
+                        estimate.largest_source = Some(size);
+                    }
+                }
+            }
+        }
+
+        estimate
+    }
+
+    /// A new app with the settings `save` kept from the last session
+    fn restored(storage: Option<&dyn eframe::Storage>) -> Self {
+        let mut app = Self::default();
+        if let Some(background) =
+            storage.and_then(|storage| eframe::get_value(storage, EXPORT_BACKGROUND_KEY))
+        {
+            app.export_background = background;
+        }
+        if let Some(policy) =
+            storage.and_then(|storage| eframe::get_value(storage, BACKUP_POLICY_KEY))
+        {
+            app.backup_policy = policy;
+        }
+        if let Some(palette) =
+            storage.and_then(|storage| eframe::get_value(storage, BG_PALETTE_KEY))
+        {
+            app.bg_palette = palette;
+        }
+        if let Some(matte) = storage.and_then(|storage| eframe::get_value(storage, JPEG_MATTE_KEY))
+        {
+            app.jpeg_matte = matte;
+        }
+        if let Some(viewer) =
+            storage.and_then(|storage| eframe::get_value(storage, VIEWER_MODE_KEY))
+        {
+            app.viewer_mode = viewer;
+        }
+        app
+    }
+
+    /// Background the exports are composited on: the canvas color unless overridden
+    fn export_bg_color(&self) -> egui::Color32 {
+        let bg = &self.export_background;
+        if bg.transparent {
+            egui::Color32::TRANSPARENT
+        } else if bg.solid {
+            bg.color
+        } else {
+            self.bg_color
+        }
+    }
+
+    /// Freeze everything an export reads. Exports work only from the snapshot, so later
+    /// edits can't leak into output that is already being produced.
+    fn export_snapshot(&self) -> CompositeSnapshot {
+        CompositeSnapshot {
+            canvas_size: self.canvas_size,
+            bg_color: self.export_bg_color(),
+            layers: self
+                .layers
+                .iter()
+                .filter(|layer| !layer.is_reference)
+                .map(|layer| LayerImage {
+                    texture: None,
+                    thumb_texture: None,
+                    ..layer.clone()
+                })
+                .collect(),
+            export_scale: self.export_scale,
+            export_scaled_metadata: self.export_scaled_metadata,
+            export_tight: self.export_tight,
+            export_atlas: self.export_atlas,
+            export_godot: self.export_godot,
+            export_sources: self.export_sources,
+            godot_res_prefix: self.godot_res_prefix.clone(),
+            output_effects: self.output_effects,
+            composite_format: self.composite_format,
+            jpeg_quality: self.jpeg_quality,
+            palette_limit: self.palette_limit,
+            zip_max_entries: self.zip_max_entries,
+            notes: self.notes.clone(),
+            matte: None,
+            markers: self
+                .markers
+                .iter()
+                .map(|marker| {
+                    let at = marker.canvas_position(&self.layers);
+                    metadata::MarkerMeta {
+                        name: marker.name.clone(),
+                        x: at.x,
+                        y: at.y,
+                        layer: marker.attached(&self.layers).map(|l| l.name.clone()),
+                    }
+                })
+                .collect(),
+        }
+    }
+
+    /// Snapshot the current user settings as a shareable profile
+    fn settings_profile(&mut self) -> SettingsProfile {
+        let keybindings = self
+            .keybindings
+            .entries_mut()
+            .into_iter()
+            .map(|(action, shortcut)| (action.to_owned(), format_shortcut(shortcut)))
+            .collect();
+        SettingsProfile {
+            version: SETTINGS_PROFILE_VERSION,
+            keybindings,
+            ui: UiPreferences {
+                preview_zoom: Some(self.preview_zoom),
+                reduce_motion: Some(self.reduce_motion),
+            },
+            export: ExportPreferences {
+                export_scale: Some(self.export_scale),
+                filename_template: Some(self.export_filename_template.clone()),
+                tight: Some(self.export_tight),
+                zip_max_entries: Some(self.zip_max_entries),
+                sources: Some(self.export_sources),
+                background: Some(self.export_background),
+            },
+            colors: ColorPreferences {
+                bg_history: Some(self.bg_palette.history.iter().map(|c| c.to_hex()).collect()),
+                saved_colors: Some(
+                    self.bg_palette
+                        .saved
+                        .iter()
+                        .map(|(name, c)| (name.clone(), c.to_hex()))
+                        .collect(),
+                ),
+            },
+        }
+    }
+
+    /// Validate an imported profile and describe what applying it would change
+    fn review_profile(&mut self, mut profile: SettingsProfile) -> PendingProfile {
+        let mut changes = Vec::new();
+        let mut warnings = Vec::new();
+
+        if profile.version > SETTINGS_PROFILE_VERSION {
+            warnings.push(format!(
+                "Profile is from a newer version ({}), unknown settings are ignored",
+                profile.version
+            ));
+        }
+
+        let mut known_actions = Vec::new();
+        for (action, shortcut) in self.keybindings.entries_mut() {
+            known_actions.push(action);
+            let Some(text) = profile.keybindings.get(action) else {
+                continue;
+            };
+            match parse_shortcut(text) {
+                Some(parsed) if parsed != *shortcut => {
+                    changes.push(format!(
+                        "{}: {} → {}",
+                        action,
+                        format_shortcut(shortcut),
+                        text
+                    ));
+                }
+                Some(_) => {}
+                None => warnings.push(format!("{}: invalid shortcut \"{}\" ignored", action, text)),
+            }
+        }
+        profile
+            .keybindings
+            .retain(|action, _| known_actions.contains(&action.as_str()));
+
+        if let Some(zoom) = &mut profile.ui.preview_zoom {
+            *zoom = zoom.clamp(MIN_ZOOM, MAX_ZOOM);
+            if *zoom != self.preview_zoom {
+                changes.push(format!("View zoom: {} → {}", self.preview_zoom, zoom));
+            }
+        }
+        if let Some(reduce) = profile.ui.reduce_motion {
+            if reduce != self.reduce_motion {
+                changes.push(format!(
+                    "Reduce motion: {} → {}",
+                    self.reduce_motion, reduce
+                ));
+            }
+        }
+        if let Some(scale) = &mut profile.export.export_scale {
+            *scale = (*scale).clamp(1, MAX_EXPORT_SCALE);
+            if *scale != self.export_scale {
+                changes.push(format!("Export scale: {} → {}", self.export_scale, scale));
+            }
+        }
+        if let Some(template) = &profile.export.filename_template {
+            if *template != self.export_filename_template {
+                changes.push(format!(
+                    "Export file name: {} → {}",
+                    self.export_filename_template, template
+                ));
+            }
+        }
+        if let Some(tight) = profile.export.tight {
+            if tight != self.export_tight {
+                changes.push(format!("Tight layers: {} → {}", self.export_tight, tight));
+            }
+        }
+        if let Some(max) = &mut profile.export.zip_max_entries {
+            *max = (*max).min(10_000);
+            if *max != self.zip_max_entries {
+                changes.push(format!(
+                    "Split ZIP past: {} → {} images",
+                    self.zip_max_entries, max
+                ));
+            }
+        }
+        if let Some(sources) = profile.export.sources {
+            if sources != self.export_sources {
+                changes.push(format!(
+                    "Include sources: {} → {}",
+                    self.export_sources, sources
+                ));
+            }
+        }
+        if let Some(background) = profile.export.background {
+            if background != self.export_background {
+                changes.push(format!(
+                    "Export background: {} → {}",
+                    self.export_background.describe(),
+                    background.describe()
+                ));
+            }
+        }
+
+        if let Some(history) = &mut profile.colors.bg_history {
+            let before = history.len();
+            history.retain(|hex| egui::Color32::from_hex(hex).is_ok());
+            history.truncate(BgPalette::HISTORY_LEN);
+            if history.len() < before {
+                warnings.push("Invalid recent background colors ignored".to_owned());
+            }
+            changes.push(format!("Recent background colors: {}", history.len()));
+        }
+        if let Some(saved) = &mut profile.colors.saved_colors {
+            saved.retain(|name, hex| {
+                let valid = egui::Color32::from_hex(hex).is_ok();
+                if !valid {
+                    warnings.push(format!("Saved color {}: invalid \"{}\" ignored", name, hex));
+                }
+                valid
+            });
+            changes.push(format!("Saved background colors: {}", saved.len()));
+        }
+
+        PendingProfile {
+            profile,
+            changes,
+            warnings,
+        }
+    }
+
+    fn apply_profile(&mut self, profile: SettingsProfile) {
+        for (action, shortcut) in self.keybindings.entries_mut() {
+            if let Some(parsed) = profile
+                .keybindings
+                .get(action)
+                .and_then(|t| parse_shortcut(t))
+            {
+                *shortcut = parsed;
+            }
+        }
+        if let Some(zoom) = profile.ui.preview_zoom {
+            self.preview_zoom = zoom;
+            self.zoom_fit = false;
+        }
+        if let Some(reduce) = profile.ui.reduce_motion {
+            self.reduce_motion = reduce;
+        }
+        if let Some(scale) = profile.export.export_scale {
+            self.export_scale = scale;
+        }
+        if let Some(template) = profile.export.filename_template {
+            self.export_filename_template = template;
+        }
+        if let Some(tight) = profile.export.tight {
+            self.export_tight = tight;
+        }
+        if let Some(max) = profile.export.zip_max_entries {
+            self.zip_max_entries = max;
+        }
+        if let Some(sources) = profile.export.sources {
+            self.export_sources = sources;
+        }
+        if let Some(background) = profile.export.background {
+            self.export_background = background;
+        }
+        if let Some(history) = profile.colors.bg_history {
+            self.bg_palette.history = history
+                .iter()
+                .filter_map(|hex| egui::Color32::from_hex(hex).ok())
+                .collect();
+        }
+        if let Some(saved) = profile.colors.saved_colors {
+            self.bg_palette.saved = saved
+                .into_iter()
+                .filter_map(|(name, hex)| Some((name, egui::Color32::from_hex(&hex).ok()?)))
+                .collect();
+        }
+    }
+
+    /// Whether overlays are hidden this frame: the toggle, or backtick held down to peek.
+    /// Read from the live key state so releasing mid-drag restores overlays right away.
+    fn clean_view_active(&self, ctx: &egui::Context) -> bool {
+        self.clean_view
+            || (!ctx.wants_keyboard_input() && ctx.input(|i| i.key_down(egui::Key::Backtick)))
+    }
+
+    /// Single switch for every animated UI element; consult it before animating anything
+    fn motion_enabled(&self) -> bool {
+        !self.reduce_motion && !system_prefers_reduced_motion()
+    }
+}
+
+/// Immutable copy of the document and export settings that an export works from.
+///
+/// Layers are cloned without their GPU textures; their source images are shared `Arc`s,
+/// which are never mutated in place (edits that change pixels swap in a new image), so
+/// holding a snapshot is cheap and later edits can't change what it sees.
+#[derive(Clone)]
+struct CompositeSnapshot {
+    canvas_size: [u32; 2],
+    bg_color: egui::Color32,
+    layers: Vec<LayerImage>,
+    export_scale: u32,
+    export_scaled_metadata: bool,
+    export_tight: bool,
+    export_atlas: bool,
+    export_godot: bool,
+    export_sources: bool,
+    godot_res_prefix: String,
+    output_effects: OutputEffects,
+    composite_format: CompositeFormat,
+    jpeg_quality: u8,
+    palette_limit: PaletteLimit,
+    zip_max_entries: usize,
+    notes: ProjectNotes,
+    markers: Vec<metadata::MarkerMeta>, // Resolved to canvas pixels
+    matte: Option<egui::Color32>,       // Set when the output format flattens alpha onto it
+}
+
+impl CompositeSnapshot {
+    /// Stable FNV-1a hash of everything that affects composited pixels
+    fn content_hash(&self) -> String {
+        let mut hasher = Fnv64::default();
+        hasher.write_u32(self.canvas_size[0]);
+        hasher.write_u32(self.canvas_size[1]);
+        hasher.write(&self.bg_color.to_array());
+        for idx in draw_order(&self.layers) {
+            let layer = &self.layers[idx];
+            layer.hash_render_state(&mut hasher);
+            hasher.write_u32(layer.source_image.width());
+            hasher.write_u32(layer.source_image.height());
+            hasher.write(format!("{:?}", layer.source_image.color()).as_bytes());
+            hasher.write(layer.source_image.as_bytes());
+        }
+        format!("{:016x}", hasher.finish())
+    }
+
+    /// Export settings that shape the output files, recorded next to the content hash
+    fn export_settings_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "scale": self.export_scale,
+            "tight": self.export_tight,
+            "output_effects": self.output_effects.to_json(),
+            "composite_format": self.composite_format.extension(),
+            "jpeg_quality": self.jpeg_quality,
+            "palette_limit": self.palette_limit.to_json(),
+            "atlas": self.export_atlas,
+            "sources": self.export_sources,
+            "godot": self.export_godot,
+            "godot_res_prefix": self.godot_res_prefix,
+            "zip_max_entries": self.zip_max_entries,
+        })
+    }
+
+    /// Provenance block embedded in data.json and every exported PNG
+    fn export_stamp(&self) -> serde_json::Value {
+        let mut stamp = serde_json::json!({
+            "app_version": env!("CARGO_PKG_VERSION"),
+            // Output is a pure function of content_hash + export_settings (nearest-neighbor only)
+            "export_settings": self.export_settings_json(),
+            "content_hash": self.content_hash(),
+        });
+        if let Some(matte) = self.matte {
+            stamp["matte"] = serde_json::json!(matte.to_hex());
+        }
+        stamp
+    }
+
+    /// Raw (unsanitized) name stem of each exported layer in draw order
+    fn layer_export_stems(&self, order: &[usize]) -> Vec<Option<String>> {
+        let skipped = self.layer_skip_reasons(order);
+        order
+            .iter()
+            .enumerate()
+            .map(|(i, &idx)| {
+                let layer = &self.layers[idx];
+                (layer.visible && skipped[i].is_none())
+                    .then(|| format!("{}_{}{}", i, layer.name, self.scale_suffix()))
+            })
+            .collect()
+    }
+
+    /// Why each visible layer in draw order is left out of the export before rendering,
+    /// recorded as data.json `skipped` (empty tight parts are only known after rendering)
+    fn layer_skip_reasons(&self, order: &[usize]) -> Vec<Option<&'static str>> {
+        clip_bases(&self.layers, order)
+            .iter()
+            .zip(order)
+            .map(|(base, &idx)| match base {
+                Some(base) if self.layers[idx].visible && !self.layers[*base].visible => {
+                    Some("clip base hidden")
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// `@2x`-style file name suffix for scaled exports, empty at 1x
+    fn scale_suffix(&self) -> String {
+        scale_suffix(self.export_scale)
+    }
+
+    /// Zip file name of each layer in draw order, `None` for layers that aren't exported
+    fn layer_export_files(&self, order: &[usize]) -> Vec<Option<String>> {
+        let stems = self.layer_export_stems(order);
+        let wanted: Vec<String> = stems.iter().flatten().cloned().collect();
+        let mut names = entry_names(&wanted, "png").0.into_iter();
+        stems
+            .iter()
+            .map(|stem| stem.as_ref().and_then(|_| names.next()))
+            .collect()
+    }
+
+    /// Renames the export will apply to keep entry names safe and unique
+    fn export_renames(&self) -> Vec<String> {
+        let order = draw_order(&self.layers);
+        let wanted: Vec<String> = self
+            .layer_export_stems(&order)
+            .into_iter()
+            .flatten()
+            .collect();
+        entry_names(&wanted, "png").1
+    }
+
+    /// Factor applied to data.json pixel positions. Pre-multiplied metadata lines up with
+    /// the upscaled PNGs pixel for pixel.
+    fn metadata_scale(&self) -> f32 {
+        if self.export_scaled_metadata {
+            self.export_scale as f32
+        } else {
+            1.0
+        }
+    }
+
+    /// data.json entry of each layer in draw order
+    fn layer_metadata(&self, order: &[usize], files: &[Option<String>]) -> Vec<LayerMeta> {
+        use metadata::{Hsl, Outline, PixelOffset, Point, Rect, Shadow, Size, Tone};
+
+        let k = self.metadata_scale();
+        let skipped = self.layer_skip_reasons(order);
+        order
+            .iter()
+            .zip(files)
+            .enumerate()
+            .map(|(i, (&idx, file))| {
+                let l = &self.layers[idx];
+                let bounds = l.content_bounds();
+                LayerMeta {
+                    name: l.name.clone(),
+                    file: file.clone(),
+                    draw_order: i,
+                    z_override: l.z_override,
+                    tint: l.tint.to_hex(),
+                    locked: l.locked,
+                    visible: l.visible,
+                    scale: Point {
+                        x: l.transform.scale_x * k,
+                        y: l.transform.scale_y * k,
+                    },
+                    anchor: Point {
+                        x: l.transform.anchor.x,
+                        y: l.transform.anchor.y,
+                    },
+                    flip_h: l.transform.flip_h,
+                    hsl: Hsl {
+                        hue: l.adjust.hue,
+                        saturation: l.adjust.saturation,
+                        lightness: l.adjust.lightness,
+                    },
+                    tone: Tone {
+                        brightness: l.tone.brightness,
+                        contrast: l.tone.contrast,
+                        preserve_endpoints: l.tone.preserve_endpoints,
+                    },
+                    outline: Outline {
+                        enabled: l.outline.enabled,
+                        color: l.outline.color.to_hex(),
+                        thickness: l.outline.thickness,
+                        inside: l.outline.inside,
+                    },
+                    clip_to_below: l.clip_to_below,
+                    label: l.label_color.map(|c| c.to_hex()),
+                    user_data: l.user_data.clone(),
+                    shadow: Shadow {
+                        enabled: l.shadow.enabled,
+                        offset: PixelOffset {
+                            x: l.shadow.offset[0],
+                            y: l.shadow.offset[1],
+                        },
+                        color: l.shadow.color.to_hex(),
+                        opacity: l.shadow.opacity,
+                        include_in_bounds: l.shadow.include_in_bounds,
+                    },
+                    offset: Point {
+                        x: round_px(l.transform.offset.x) * k,
+                        y: round_px(l.transform.offset.y) * k,
+                    },
+                    bounds: Rect {
+                        x: round_px(bounds.min.x) * k,
+                        y: round_px(bounds.min.y) * k,
+                        width: round_px(bounds.width()) * k,
+                        height: round_px(bounds.height()) * k,
+                    },
+                    source: Size {
+                        width: l.source_image.width(),
+                        height: l.source_image.height(),
+                    },
+                    crop: None,
+                    skipped: skipped[i].map(str::to_owned),
+                    pack: None,
+                    source_file: None,
+                }
+            })
+            .collect()
+    }
+
+    /// data.json `tree`: the flat list regrouped so layers clipped to a base become its
+    /// children, with offsets relative to that base
+    fn layer_tree(&self, order: &[usize], layers: &[LayerMeta]) -> Vec<MetaNode> {
+        let mut tree: Vec<MetaNode> = Vec::new();
+        for (meta, base) in layers.iter().zip(clip_bases(&self.layers, order)) {
+            let mut node = MetaNode {
+                node_type: metadata::NodeType::Image,
+                name: meta.name.clone(),
+                file: meta.file.clone(),
+                visible: meta.visible,
+                offset: meta.offset,
+                scale: meta.scale,
+                source: meta.source,
+                source_file: meta.source_file.clone(),
+                children: Vec::new(),
+            };
+            match (base, tree.last_mut()) {
+                // The base is always the most recent top-level node
+                (Some(_), Some(parent)) => {
+                    node.offset.x -= parent.offset.x;
+                    node.offset.y -= parent.offset.y;
+                    parent.children.push(node);
+                }
+                _ => tree.push(node),
+            }
+        }
+        tree
+    }
+
+    /// `sources/` entry of each layer in draw order, and the entries to write with the
+    /// layer index whose image fills each. Layers with identical pixels share one entry,
+    /// named after the first of them.
+    fn source_files(&self, order: &[usize]) -> (Vec<String>, Vec<(String, usize)>) {
+        let mut by_hash: BTreeMap<u64, Vec<usize>> = BTreeMap::new(); // Pixel hash -> entries
+        let mut firsts: Vec<usize> = Vec::new();
+        let entry_of: Vec<usize> = order
+            .iter()
+            .map(|&idx| {
+                let source = &self.layers[idx].source_image;
+                let pixels = source.to_rgba8();
+                let mut hasher = Fnv64::default();
+                hasher.write_u32(source.width());
+                hasher.write_u32(source.height());
+                hasher.write(pixels.as_raw());
+                let entries = by_hash.entry(hasher.finish()).or_default();
+                // Equal hashes only make a match likely; share the entry once the pixels agree
+                let same = entries.iter().copied().find(|&entry| {
+                    let first = &self.layers[firsts[entry]].source_image;
+                    Arc::ptr_eq(first, source) || first.to_rgba8() == pixels
+                });
+                same.unwrap_or_else(|| {
+                    firsts.push(idx);
+                    entries.push(firsts.len() - 1);
+                    firsts.len() - 1
+                })
+            })
+            .collect();
+        let stems: Vec<String> = firsts
+            .iter()
+            .map(|&idx| self.layers[idx].name.clone())
+            .collect();
+        let names: Vec<String> = entry_names(&stems, "png")
+            .0
+            .into_iter()
+            .map(|name| format!("sources/{}", name))
+            .collect();
+        let files = entry_of.iter().map(|&entry| names[entry].clone()).collect();
+        (files, names.into_iter().zip(firsts).collect())
+    }
+
+    /// One layer's export image and its top-left on the export canvas. Tight mode crops the
+    /// final pixels (after scaling, clipping and effects) to their opaque box and yields
+    /// `None` for layers that end up empty.
+    fn export_layer_image(&self, order: &[usize], pos: usize) -> Option<(RgbaImage, [u32; 2])> {
+        let img = render_export_layer(
+            self.canvas_size,
+            &self.layers,
+            order,
+            pos,
+            self.export_scale,
+            &self.output_effects,
+        )?;
+        if !self.export_tight {
+            return Some((img, [0, 0]));
+        }
+        let [x, y, w, h] = alpha_bounds(&img)?;
+        Some((
+            image::imageops::crop_imm(&img, x, y, w, h).to_image(),
+            [x, y],
+        ))
+    }
+
+    /// Every exported layer trimmed to its opaque pixels and packed onto one sheet, with
+    /// TexturePacker "JSON (Hash)" frames keyed by (de-duplicated) layer name
+    fn build_atlas(&self) -> (RgbaImage, atlas::Atlas) {
+        let order = draw_order(&self.layers);
+        let mut parts = Vec::new(); // (layer index, trimmed image, box on the export canvas)
+        for (pos, file) in self.layer_export_files(&order).iter().enumerate() {
+            if file.is_none() {
+                continue;
+            }
+            let Some(img) = render_export_layer(
+                self.canvas_size,
+                &self.layers,
+                &order,
+                pos,
+                self.export_scale,
+                &self.output_effects,
+            ) else {
+                continue;
+            };
+            let Some([x, y, w, h]) = alpha_bounds(&img) else {
+                continue;
+            };
+            let trimmed = image::imageops::crop_imm(&img, x, y, w, h).to_image();
+            parts.push((order[pos], trimmed, [x, y, w, h]));
+        }
+
+        let names: Vec<String> = parts
+            .iter()
+            .map(|(idx, _, _)| self.layers[*idx].name.clone())
+            .collect();
+        let names = entry_names(&names, "png").0;
+        let sizes: Vec<[u32; 2]> = parts.iter().map(|(_, _, [_, _, w, h])| [*w, *h]).collect();
+        let ([sheet_w, sheet_h], positions) = atlas::pack(&sizes);
+
+        let mut sheet = RgbaImage::new(sheet_w, sheet_h);
+        let canvas = egui::vec2(self.canvas_size[0] as f32, self.canvas_size[1] as f32);
+        let mut frames = BTreeMap::new();
+        for ((name, (idx, img, [x, y, w, h])), [sx, sy]) in
+            names.into_iter().zip(&parts).zip(positions)
+        {
+            image::imageops::overlay(&mut sheet, img, sx as i64, sy as i64);
+            let layer = &self.layers[*idx];
+            let pivot = (layer.top_left() + layer.transform.anchor * layer.scaled_size()) / canvas;
+            frames.insert(
+                name,
+                atlas::Frame {
+                    frame: atlas::Rect {
+                        x: sx,
+                        y: sy,
+                        w: *w,
+                        h: *h,
+                    },
+                    rotated: false,
+                    trimmed: true,
+                    sprite_source_size: atlas::Rect {
+                        x: *x,
+                        y: *y,
+                        w: *w,
+                        h: *h,
+                    },
+                    source_size: atlas::Size {
+                        w: self.canvas_size[0] * self.export_scale,
+                        h: self.canvas_size[1] * self.export_scale,
+                    },
+                    pivot: atlas::Pivot {
+                        x: pivot.x,
+                        y: pivot.y,
+                    },
+                },
+            );
+        }
+        let meta = atlas::Meta {
+            app: "kitbash".to_owned(),
+            version: env!("CARGO_PKG_VERSION").to_owned(),
+            image: "atlas.png".to_owned(),
+            format: "RGBA8888".to_owned(),
+            size: atlas::Size {
+                w: sheet_w,
+                h: sheet_h,
+            },
+            scale: "1".to_owned(),
+        };
+        (sheet, atlas::Atlas { frames, meta })
+    }
+
+    /// `.aseprite` file with one layer per exported layer, its baked pixels as the cel
+    async fn build_aseprite(&self, progress: &ExportProgress) -> Result<Vec<u8>, KitbashError> {
+        let order = draw_order(&self.layers);
+        let files = self.layer_export_files(&order);
+        let exported: Vec<usize> = (0..files.len())
+            .filter(|&pos| files[pos].is_some())
+            .collect();
+        let mut layers = Vec::with_capacity(exported.len());
+        for (done, &pos) in exported.iter().enumerate() {
+            progress.step(done, exported.len()).await?;
+            let cel = render_export_layer(
+                self.canvas_size,
+                &self.layers,
+                &order,
+                pos,
+                self.export_scale,
+                &self.output_effects,
+            )
+            .and_then(|img| {
+                let [x, y, w, h] = alpha_bounds(&img)?;
+                Some((
+                    image::imageops::crop_imm(&img, x, y, w, h).to_image(),
+                    [x, y],
+                ))
+            });
+            layers.push(aseprite::Layer {
+                name: self.layers[order[pos]].name.clone(),
+                cel,
+            });
+        }
+        aseprite::encode(
+            self.canvas_size[0] * self.export_scale,
+            self.canvas_size[1] * self.export_scale,
+            &layers,
+        )
+        .map_err(KitbashError::Export)
+    }
+
+    /// Animated GIF with one frame per layer in `variants` (bottom to top): that layer
+    /// shown and the other variants hidden, everything else as it is
+    async fn build_gif(
+        &self,
+        variants: &HashSet<u64>,
+        delay_ms: u32,
+        progress: &ExportProgress,
+    ) -> Result<Vec<u8>, KitbashError> {
+        let shown: Vec<usize> = draw_order(&self.layers)
+            .into_iter()
+            .filter(|&idx| variants.contains(&self.layers[idx].id))
+            .collect();
+        let mut frames = Vec::with_capacity(shown.len());
+        for (done, &shown_idx) in shown.iter().enumerate() {
+            progress.step(done, shown.len()).await?;
+            let mut layers = self.layers.clone();
+            for (idx, layer) in layers.iter_mut().enumerate() {
+                if variants.contains(&layer.id) {
+                    layer.visible = idx == shown_idx;
+                }
+            }
+            let img = composite_image(
+                self.canvas_size,
+                &layers,
+                self.export_scale,
+                self.bg_color,
+                &self.output_effects,
+            );
+            let delay = image::Delay::from_numer_denom_ms(delay_ms, 1);
+            frames.push(image::Frame::from_parts(img, 0, 0, delay));
+        }
+
+        let mut bytes = Vec::new();
+        {
+            // Quantizes each frame to 256 colors; fully transparent pixels stay transparent
+            let mut encoder = image::codecs::gif::GifEncoder::new_with_speed(&mut bytes, 10);
+            encoder
+                .set_repeat(image::codecs::gif::Repeat::Infinite)
+                .and_then(|()| encoder.encode_frames(frames))
+                .map_err(|source| KitbashError::Encode {
+                    format: "GIF",
+                    source,
+                })?;
+        }
+        Ok(bytes)
+    }
+
+    /// Heuristic size of the composite PNG: encode the 1x composite and scale by the export
+    /// factor. Nearest-neighbor upscaling repeats rows, which deflate nearly absorbs, so
+    /// growth is roughly linear.
+    async fn estimate_png_bytes(&self) -> usize {
+        yield_now().await;
+        let img = composite_image(
+            self.canvas_size,
+            &self.layers,
+            1,
+            self.bg_color,
+            &self.output_effects,
+        );
+        yield_now().await;
+        encode_png(&img, "").map_or(0, |png| png.len()) * self.export_scale as usize
+    }
+
+    /// Heuristic size of the HTML viewer: its trimmed parts encoded at 1x and scaled like
+    /// `estimate_png_bytes`, grown by a third for base64, plus the page around them
+    async fn estimate_html_viewer_bytes(&self) -> usize {
+        let mut snapshot = self.html_viewer_snapshot();
+        snapshot.export_scale = 1;
+        let order = draw_order(&snapshot.layers);
+        let mut png_bytes = 0;
+        for pos in 0..order.len() {
+            yield_now().await;
+            if let Some((img, _)) = snapshot.export_layer_image(&order, pos) {
+                png_bytes += encode_png(&img, "").map_or(0, |png| png.len());
+            }
+        }
+        HTML_VIEWER_TEMPLATE.len() + png_bytes * self.export_scale as usize * 4 / 3
+    }
+
+    /// What the export preview shows: the 1x composite as the encoder sees it (output pixels
+    /// are the 1x ones repeated, so it shows them all), and the size of the file it saves
+    async fn export_preview(&self, matte: egui::Color32) -> (RgbaImage, usize) {
+        yield_now().await;
+        let stamp = self.export_stamp().to_string();
+        let encoded = encode_composite(
+            &self.composite(),
+            self.composite_format,
+            self.jpeg_quality,
+            self.palette_limit,
+            matte,
+            &stamp,
+        );
+        let encoded_bytes = encoded.map_or_else(
+            |err| {
+                log::warn!("{}", err);
+                0
+            },
+            |bytes| bytes.len(),
+        );
+        yield_now().await;
+        let mut img = composite_image(
+            self.canvas_size,
+            &self.layers,
+            1,
+            self.bg_color,
+            &self.output_effects,
+        );
+        if self.composite_format == CompositeFormat::Png && self.palette_limit.enabled {
+            let colors = palette::median_cut(&img, self.palette_limit.colors);
+            let indices = palette::remap(&img, &colors, self.palette_limit.dither);
+            for (pixel, index) in img.pixels_mut().zip(indices) {
+                *pixel = match index {
+                    0 => Rgba([0, 0, 0, 0]),
+                    i => {
+                        let [r, g, b] = colors[i as usize - 1];
+                        Rgba([r, g, b, 255])
+                    }
+                };
+            }
+        }
+        (img, encoded_bytes)
+    }
+
+    /// Swatches of the limited palette, from the 1x composite
+    async fn limited_palette(&self) -> Vec<[u8; 3]> {
+        yield_now().await;
+        let img = composite_image(
+            self.canvas_size,
+            &self.layers,
+            1,
+            self.bg_color,
+            &self.output_effects,
+        );
+        yield_now().await;
+        palette::median_cut(&img, self.palette_limit.colors)
+    }
+
+    /// The single composited image, exactly as the composite export renders it
+    fn composite(&self) -> RgbaImage {
+        composite_image(
+            self.canvas_size,
+            &self.layers,
+            self.export_scale,
+            self.bg_color,
+            &self.output_effects,
+        )
+    }
+
+    /// Layers carrying each checked label, bottom to top, as `(label name, layer indices)`.
+    /// Labels no layer carries are left out.
+    fn variant_slots(&self, checked: &[bool]) -> Vec<(&'static str, Vec<usize>)> {
+        let order = draw_order(&self.layers);
+        LABEL_COLORS
+            .iter()
+            .zip(checked)
+            .filter(|(_, checked)| **checked)
+            .map(|((name, color), _)| {
+                let members = order
+                    .iter()
+                    .copied()
+                    .filter(|&idx| self.layers[idx].label_color == Some(*color))
+                    .collect();
+                (*name, members)
+            })
+            .filter(|(_, members): &(_, Vec<usize>)| !members.is_empty())
+            .collect()
+    }
+
+    /// ZIP with one composite per combination of variants (exactly one layer per slot
+    /// shown, every other slot member hidden) plus index.json naming each file's choices
+    async fn build_permutation_zip(
+        &self,
+        slots: &[(&str, Vec<usize>)],
+        progress: &ExportProgress,
+    ) -> Result<Vec<u8>, KitbashError> {
+        let count: usize = slots.iter().map(|(_, members)| members.len()).product();
+        let mut combos = Vec::with_capacity(count);
+        let mut choice = vec![0; slots.len()];
+        for _ in 0..count {
+            combos.push(choice.clone());
+            // Count up in mixed radix, last slot fastest
+            for (digit, (_, members)) in choice.iter_mut().zip(slots).rev() {
+                *digit += 1;
+                if *digit < members.len() {
+                    break;
+                }
+                *digit = 0;
+            }
+        }
+
+        let stems: Vec<String> = combos
+            .iter()
+            .map(|combo| {
+                let names: Vec<&str> = combo
+                    .iter()
+                    .zip(slots)
+                    .map(|(&pick, (_, members))| self.layers[members[pick]].name.as_str())
+                    .collect();
+                format!("perm_{}", names.join("_"))
+            })
+            .collect();
+        let files = entry_names(&stems, "png").0;
+        let stamp = self.export_stamp().to_string();
+
+        let mut zip_buffer = Vec::new();
+        {
+            let mut zip = zip::ZipWriter::new(Cursor::new(&mut zip_buffer));
+            let options = zip_file_options();
+            let mut index = serde_json::Map::new();
+            let mut layers = self.layers.clone();
+            for (done, (combo, file)) in combos.iter().zip(&files).enumerate() {
+                progress.step(done, count).await?;
+                let mut picks = serde_json::Map::new();
+                for (&pick, (slot, members)) in combo.iter().zip(slots) {
+                    for (i, &idx) in members.iter().enumerate() {
+                        layers[idx].visible = i == pick;
+                    }
+                    picks.insert(
+                        (*slot).to_owned(),
+                        serde_json::json!(layers[members[pick]].name),
+                    );
+                }
+                let img = composite_image(
+                    self.canvas_size,
+                    &layers,
+                    self.export_scale,
+                    self.bg_color,
+                    &self.output_effects,
+                );
+                zip.start_file(file.as_str(), options)?;
+                zip.write_all(&encode_png(&img, &stamp)?)?;
+                index.insert(file.clone(), serde_json::Value::Object(picks));
+            }
+
+            let index = serde_json::json!({
+                "slots": slots.iter().map(|(slot, _)| slot).collect::<Vec<_>>(),
+                "combinations": index,
+            });
+            zip.start_file("index.json", options)?;
+            serde_json::to_writer_pretty(&mut zip, &index)?;
+            zip.finish()?;
+        }
+        Ok(zip_buffer)
+    }
+
+    /// Per-layer PNGs plus data.json, packed as one ZIP or, past `zip_max_entries` images,
+    /// several. Every part carries the full data.json with its own `pack` index.
+    async fn build_layer_zips(
+        &self,
+        progress: &ExportProgress,
+    ) -> Result<Vec<Vec<u8>>, KitbashError> {
+        let order = draw_order(&self.layers);
+        let mut files = self.layer_export_files(&order);
+        // Tight exports render everything up front and encode it later: two steps per layer
+        let steps = files.len() * if self.export_tight { 2 } else { 1 };
+        let mut done = 0;
+        let stamp = self.export_stamp();
+        let stamp_text = stamp.to_string();
+        let mut layers = self.layer_metadata(&order, &files);
+
+        // Tight parts are small, so render them up front to know which ones are empty
+        let mut tight_parts = Vec::new();
+        if self.export_tight {
+            for (pos, file) in files.iter().enumerate() {
+                progress.step(pos, steps).await?;
+                let part = file
+                    .as_ref()
+                    .and_then(|_| self.export_layer_image(&order, pos));
+                tight_parts.push(part);
+            }
+        }
+        for (pos, part) in tight_parts.iter().enumerate() {
+            match part {
+                Some((img, [x, y])) => {
+                    layers[pos].crop = Some(metadata::PixelRect {
+                        x: *x,
+                        y: *y,
+                        width: img.width(),
+                        height: img.height(),
+                    });
+                }
+                None if files[pos].is_some() => {
+                    layers[pos].file = None;
+                    layers[pos].skipped = Some("empty".to_owned());
+                    files[pos] = None;
+                }
+                None => {}
+            }
+        }
+
+        let exported: Vec<usize> = (0..files.len())
+            .filter(|&pos| files[pos].is_some())
+            .collect();
+        let per_pack = match self.zip_max_entries {
+            0 => exported.len().max(1),
+            max => max,
+        };
+        let packs: Vec<&[usize]> = if exported.is_empty() {
+            vec![&[]]
+        } else {
+            exported.chunks(per_pack).collect()
+        };
+
+        if packs.len() > 1 {
+            for (pack, positions) in packs.iter().enumerate() {
+                for &pos in positions.iter() {
+                    layers[pos].pack = Some(pack + 1);
+                }
+            }
+        }
+
+        let sources = if self.export_sources {
+            let (source_files, entries) = self.source_files(&order);
+            for (meta, file) in layers.iter_mut().zip(source_files) {
+                meta.source_file = Some(file);
+            }
+            entries
+        } else {
+            Vec::new()
+        };
+
+        let tree = self.layer_tree(&order, &layers);
+        let k = self.metadata_scale();
+        let markers: Vec<metadata::MarkerMeta> = self
+            .markers
+            .iter()
+            .map(|m| metadata::MarkerMeta {
+                x: round_px(m.x) * k,
+                y: round_px(m.y) * k,
+                ..m.clone()
+            })
+            .collect();
+        // Godot resources point into the sheet, so they need it too
+        let atlas = (self.export_atlas || self.export_godot).then(|| self.build_atlas());
+        let mut zips = Vec::with_capacity(packs.len());
+        for (pack, positions) in packs.iter().enumerate() {
+            let mut zip_buffer = Vec::new();
+            {
+                let mut zip = zip::ZipWriter::new(Cursor::new(&mut zip_buffer));
+                let options = zip_file_options();
+
+                // 1. Export each visible layer as PNG
+                for &pos in positions.iter() {
+                    let Some(file) = &files[pos] else { continue };
+                    progress.step(tight_parts.len() + done, steps).await?;
+                    done += 1;
+                    let img = match tight_parts.get(pos) {
+                        Some(part) => part.as_ref().map(|(img, _)| Cow::Borrowed(img)),
+                        None => self
+                            .export_layer_image(&order, pos)
+                            .map(|(img, _)| Cow::Owned(img)),
+                    };
+                    if let Some(img) = img {
+                        let bytes = encode_png(&img, &stamp_text)?;
+                        zip.start_file(file.as_str(), options)?;
+                        zip.write_all(&bytes)?;
+                    }
+                }
+
+                // 2. Export Metadata
+                let mut meta = stamp.clone();
+                meta["schema_version"] = serde_json::json!(metadata::SCHEMA_VERSION);
+                meta["units"] = serde_json::json!(if self.export_scaled_metadata {
+                    "output"
+                } else {
+                    "canvas"
+                });
+                meta["layers"] = serde_json::json!(layers);
+                meta["tree"] = serde_json::json!(tree);
+                meta["markers"] = serde_json::json!(markers);
+                if !self.notes.text.is_empty() {
+                    meta["notes"] = serde_json::json!({
+                        "text": self.notes.text,
+                        "show_on_open": self.notes.show_on_open,
+                    });
+                }
+                if packs.len() > 1 {
+                    meta["pack"] = serde_json::json!({ "index": pack + 1, "count": packs.len() });
+                }
+                zip.start_file("data.json", options)?;
+                serde_json::to_writer_pretty(&mut zip, &meta)?;
+
+                // 3. Sources and the atlas go into the first part only
+                if pack == 0 {
+                    for (file, idx) in &sources {
+                        let source = self.layers[*idx].source_image.to_rgba8();
+                        zip.start_file(file.as_str(), options)?;
+                        zip.write_all(&encode_png(&source, &stamp_text)?)?;
+                    }
+                }
+
+                if let (Some((sheet, frames)), 0) = (&atlas, pack) {
+                    zip.start_file("atlas.png", options)?;
+                    zip.write_all(&encode_png(sheet, &stamp_text)?)?;
+                    zip.start_file("atlas.json", options)?;
+                    serde_json::to_writer_pretty(&mut zip, frames)?;
+                    if self.export_godot {
+                        let prefix = &self.godot_res_prefix;
+                        let sheet_path = if prefix.is_empty() || prefix.ends_with('/') {
+                            format!("{}atlas.png", prefix)
+                        } else {
+                            format!("{}/atlas.png", prefix)
+                        };
+                        for (name, frame) in &frames.frames {
+                            let stem = name.strip_suffix(".png").unwrap_or(name);
+                            zip.start_file(format!("godot/{}.tres", stem), options)?;
+                            zip.write_all(
+                                atlas::godot_atlas_texture(&sheet_path, frame).as_bytes(),
+                            )?;
+                        }
+                    }
+                }
+
+                zip.finish()?;
+            }
+            zips.push(zip_buffer);
+        }
+        Ok(zips)
+    }
+
+    /// The snapshot the HTML viewer renders its parts from: trimmed, with every layer shown
+    fn html_viewer_snapshot(&self) -> CompositeSnapshot {
+        let mut snapshot = self.clone();
+        snapshot.export_tight = true;
+        // Hidden layers are included too, starting unchecked
+        for layer in &mut snapshot.layers {
+            layer.visible = true;
+        }
+        snapshot
+    }
+
+    /// Self-contained HTML page that stacks every layer (trimmed, embedded as base64 PNGs)
+    /// with a checkbox per layer, so the parts can be mixed and matched in any browser
+    async fn build_html_viewer(&self, progress: &ExportProgress) -> Result<String, KitbashError> {
+        let snapshot = self.html_viewer_snapshot();
+        let order = draw_order(&snapshot.layers);
+        let mut parts = Vec::new();
+        for pos in 0..order.len() {
+            progress.step(pos, order.len()).await?;
+            let Some((img, [x, y])) = snapshot.export_layer_image(&order, pos) else {
+                continue;
+            };
+            let png = encode_png(&img, "")?;
+            let layer = &self.layers[order[pos]];
+            parts.push(serde_json::json!({
+                "name": layer.name,
+                "visible": layer.visible,
+                "x": x,
+                "y": y,
+                "width": img.width(),
+                "height": img.height(),
+                "src": format!("data:image/png;base64,{}", base64_encode(&png)),
+            }));
+        }
+        let model = serde_json::json!({
+            "width": self.canvas_size[0] * self.export_scale,
+            "height": self.canvas_size[1] * self.export_scale,
+            "background": self.bg_color.to_hex(),
+            "parts": parts,
+        });
+        // "</" would end the script element early
+        let model = model.to_string().replace("</", "<\\/");
+        Ok(HTML_VIEWER_TEMPLATE.replace("/*MODEL*/null", &model))
+    }
+
+    /// Check whether the current project and export settings would reproduce an exported ZIP
+    fn verify_export(&self, zip_bytes: &[u8], recomposite: bool) -> Result<Vec<String>, String> {
+        let mut archive = zip::ZipArchive::new(Cursor::new(zip_bytes))
+            .map_err(|e| format!("Not a readable ZIP: {}", e))?;
+        let meta: serde_json::Value = {
+            let entry = archive
+                .by_name("data.json")
+                .map_err(|_| "ZIP has no data.json".to_owned())?;
+            serde_json::from_reader(entry).map_err(|e| format!("Invalid data.json: {}", e))?
+        };
+        let Some(hash) = meta["content_hash"].as_str() else {
+            return Err("data.json has no content hash (exported by an older version?)".to_owned());
+        };
+
+        let mut report = Vec::new();
+        if let Some(version) = meta["app_version"].as_str() {
+            if version != env!("CARGO_PKG_VERSION") {
+                report.push(format!(
+                    "Exported by version {}, this is {}",
+                    version,
+                    env!("CARGO_PKG_VERSION")
+                ));
+            }
+        }
+        let settings_match = meta["export_settings"] == self.export_settings_json();
+        if !settings_match {
+            report.push(format!(
+                "Export settings differ: file {} vs current {}",
+                meta["export_settings"],
+                self.export_settings_json()
+            ));
+        }
+        let hash_match = hash == self.content_hash();
+        report.push(if hash_match {
+            "Content hash matches".to_owned()
+        } else {
+            "Content hash differs: the project changed since this export".to_owned()
+        });
+
+        if recomposite {
+            let order = draw_order(&self.layers);
+            let mut mismatched = Vec::new();
+            let split = meta["pack"]["count"]
+                .as_u64()
+                .is_some_and(|count| count > 1);
+            for (pos, file) in self.layer_export_files(&order).into_iter().enumerate() {
+                let Some(file) = file else { continue };
+                // Layers of a split export live in the other parts
+                if split && !archive.file_names().any(|name| name == file) {
+                    continue;
+                }
+                let rendered = self.export_layer_image(&order, pos).map(|(img, _)| img);
+                let exported = archive.by_name(&file).ok().and_then(|mut entry| {
+                    let mut bytes = Vec::new();
+                    std::io::Read::read_to_end(&mut entry, &mut bytes).ok()?;
+                    image::load_from_memory(&bytes)
+                        .ok()
+                        .map(|img| img.to_rgba8())
+                });
+                // Tight exports leave empty layers out, so both sides are None for those
+                if rendered != exported {
+                    mismatched.push(file);
+                }
+            }
+            report.push(if mismatched.is_empty() {
+                "Recomposite: all layer images are identical".to_owned()
+            } else {
+                format!(
+                    "Recomposite: {} layer(s) differ: {}",
+                    mismatched.len(),
+                    mismatched.join(", ")
+                )
+            });
+        }
+
+        report.insert(
+            0,
+            if hash_match && settings_match {
+                "✔ Current project reproduces this export".to_owned()
+            } else {
+                "✖ Current project would NOT reproduce this export".to_owned()
+            },
+        );
+        Ok(report)
+    }
+}
+
+// ----------------------------------------------------------------------------
+// Helper Functions
+// ----------------------------------------------------------------------------
+
+/// Highest layer id in use
+fn max_layer_id(layers: &[LayerImage]) -> Option<u64> {
+    layers.iter().map(|l| l.id).max()
+}
+
+/// First id used by more than one layer
+fn duplicate_layer_id(layers: &[LayerImage]) -> Option<u64> {
+    let mut seen = HashSet::new();
+    layers.iter().map(|l| l.id).find(|&id| !seen.insert(id))
+}
+
+/// Indices of `layers` in the order they are drawn (bottom to top).
+///
+/// Layers keep their list position unless they carry a `z_override`, in which case
+/// they are stably re-sorted around the others by that value. No override counts as 0,
+/// so a layer with an explicit `Some(0)` ties with unset ones and stays in list order.
+fn draw_order(layers: &[LayerImage]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..layers.len()).collect();
+    order.sort_by_key(|&i| layers[i].z_override.unwrap_or(0));
+    order
+}
+
+fn format_shortcut(shortcut: &egui::KeyboardShortcut) -> String {
+    shortcut.format(&egui::ModifierNames::NAMES, false)
+}
+
+/// Parse shortcuts written by `format_shortcut`, e.g. "Ctrl+Shift+D"
+fn parse_shortcut(text: &str) -> Option<egui::KeyboardShortcut> {
+    let mut modifiers = egui::Modifiers::NONE;
+    let mut key = None;
+    for part in text.split('+').map(str::trim) {
+        match part.to_ascii_lowercase().as_str() {
+            "ctrl" | "cmd" | "command" => modifiers = modifiers | egui::Modifiers::COMMAND,
+            "shift" => modifiers.shift = true,
+            "alt" | "option" => modifiers.alt = true,
+            _ if key.is_none() => key = Some(egui::Key::from_name(part)?),
+            _ => return None,
+        }
+    }
+    Some(egui::KeyboardShortcut::new(modifiers, key?))
+}
+
+/// A line across the canvas at a canvas x (vertical) or y (horizontal); never exported
+#[derive(Clone, Copy, PartialEq)]
+struct Guide {
+    vertical: bool,
+    position: f32, // Canvas pixels from the left or top edge
+}
+
+/// Named attachment point ("hand_r", "hat_socket") exported in data.json; never rendered.
+/// An attached marker is kept in its layer's source pixels, so it follows the layer's
+/// offset, scale and flip.
+#[derive(Clone, PartialEq)]
+struct Marker {
+    name: String,
+    position: egui::Vec2, // Canvas pixels, or the layer's source pixels when attached
+    layer: Option<u64>,
+}
+
+impl Marker {
+    fn attached<'a>(&self, layers: &'a [LayerImage]) -> Option<&'a LayerImage> {
+        self.layer.and_then(|id| layers.iter().find(|l| l.id == id))
+    }
+
+    /// Where the marker sits on the canvas. A marker whose layer is gone stays put.
+    fn canvas_position(&self, layers: &[LayerImage]) -> egui::Vec2 {
+        match self.attached(layers) {
+            Some(layer) => layer.source_to_canvas(self.position),
+            None => self.position,
+        }
+    }
+
+    fn set_canvas_position(&mut self, canvas: egui::Vec2, layers: &[LayerImage]) {
+        self.position = match self.attached(layers) {
+            Some(layer) => layer.canvas_to_source(canvas),
+            None => canvas,
+        };
+    }
+
+    /// Attach to `layer` (or detach) without moving on the canvas
+    fn attach(&mut self, layer: Option<u64>, layers: &[LayerImage]) {
+        let canvas = self.canvas_position(layers);
+        self.layer = layer;
+        self.set_canvas_position(canvas, layers);
+    }
+}
+
+/// Screen distance within which dragged layer edges stick to a guide
+const GUIDE_SNAP_PX: f32 = 6.0;
+
+/// Shift that moves the nearest edge or center of `rect` onto a guide within `threshold`,
+/// per axis
+fn guide_snap(rect: egui::Rect, guides: &[Guide], threshold: f32) -> egui::Vec2 {
+    let axis = |vertical: bool, edges: [f32; 3]| {
+        guides
+            .iter()
+            .filter(|g| g.vertical == vertical)
+            .flat_map(|g| edges.map(|edge| g.position - edge))
+            .filter(|d| d.abs() <= threshold)
+            .min_by(|a, b| a.abs().total_cmp(&b.abs()))
+            .unwrap_or(0.0)
+    };
+    egui::vec2(
+        axis(true, [rect.min.x, rect.center().x, rect.max.x]),
+        axis(false, [rect.min.y, rect.center().y, rect.max.y]),
+    )
+}
+
+fn snap_to_grid(offset: egui::Vec2, grid: u32) -> egui::Vec2 {
+    let grid = grid.max(1) as f32;
+    (offset / grid).round() * grid
+}
+
+/// Project-wide constraint keeping layer offsets on even or odd pixels (e.g. for engines
+/// rendering at half resolution)
+#[derive(Clone, Copy, PartialEq)]
+enum Parity {
+    Even,
+    Odd,
+}
+
+impl Parity {
+    /// Nearest whole value with this parity
+    fn snap(self, value: f32) -> f32 {
+        let rounded = value.round();
+        if (rounded as i64).rem_euclid(2) == (self == Parity::Odd) as i64 {
+            rounded
+        } else if value >= rounded {
+            rounded + 1.0
+        } else {
+            rounded - 1.0
+        }
+    }
+
+    fn snap_vec(self, offset: egui::Vec2) -> egui::Vec2 {
+        egui::vec2(self.snap(offset.x), self.snap(offset.y))
+    }
+
+    fn holds(self, offset: egui::Vec2) -> bool {
+        self.snap_vec(offset) == offset
+    }
+}
+
+/// 64-bit FNV-1a, stable across Rust versions and platforms (unlike `DefaultHasher`)
+struct Fnv64(u64);
+
+impl Default for Fnv64 {
+    fn default() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+}
+
+impl Fnv64 {
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 = (self.0 ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write(&value.to_le_bytes());
+    }
+
+    fn finish(&self) -> u64 {
+        self.0
+    }
+}
+
+/// Byte range of the first case-insensitive occurrence of `needle` (already lowercase) in
+/// `text`, `Some(empty)` for an empty needle and `None` if it doesn't occur
+fn match_range(text: &str, needle: &str) -> Option<std::ops::Range<usize>> {
+    if needle.is_empty() {
+        return Some(0..0);
+    }
+    // Lowercasing can change byte lengths, so remember where each lowered byte came from
+    let mut lower = String::with_capacity(text.len());
+    let mut origin = Vec::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        for l in c.to_lowercase() {
+            origin.extend(std::iter::repeat_n(i, l.len_utf8()));
+            lower.push(l);
+        }
+    }
+    let found = lower.find(needle)?;
+    let end = origin
+        .get(found + needle.len())
+        .copied()
+        .unwrap_or(text.len());
+    Some(origin[found]..end)
+}
+
+/// `text` with `range` drawn highlighted, for search results
+fn highlighted_label(
+    ui: &egui::Ui,
+    text: &str,
+    range: Option<std::ops::Range<usize>>,
+) -> egui::WidgetText {
+    let Some(range) = range.filter(|r| !r.is_empty()) else {
+        return text.into();
+    };
+    let font_id = egui::TextStyle::Button.resolve(ui.style());
+    let normal = egui::TextFormat::simple(font_id.clone(), ui.visuals().text_color());
+    let highlight = egui::TextFormat {
+        background: ui.visuals().selection.bg_fill,
+        ..egui::TextFormat::simple(font_id, ui.visuals().strong_text_color())
+    };
+    let mut job = egui::text::LayoutJob::default();
+    job.append(&text[..range.start], 0.0, normal.clone());
+    job.append(&text[range.clone()], 0.0, highlight);
+    job.append(&text[range.end..], 0.0, normal);
+    job.into()
+}
+
+/// The image currently on the system clipboard
+#[cfg(not(target_arch = "wasm32"))]
+fn clipboard_image() -> Result<image::DynamicImage, String> {
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard unavailable: {}", e))?;
+    let data = clipboard
+        .get_image()
+        .map_err(|_| "Nothing pasted: the clipboard doesn't hold an image".to_owned())?;
+    RgbaImage::from_raw(
+        data.width as u32,
+        data.height as u32,
+        data.bytes.into_owned(),
+    )
+    .map(image::DynamicImage::ImageRgba8)
+    .ok_or_else(|| "Nothing pasted: unreadable clipboard image".to_owned())
+}
+
+/// Browsers only hand out clipboard images inside a paste event, which egui doesn't forward
+#[cfg(target_arch = "wasm32")]
+fn clipboard_image() -> Result<image::DynamicImage, String> {
+    Err("Pasting images isn't supported in the web version yet".to_owned())
+}
+
+/// Put `img` on the system clipboard as an image
+#[cfg(not(target_arch = "wasm32"))]
+fn set_clipboard_image(img: &RgbaImage) -> Result<(), String> {
+    let mut clipboard =
+        arboard::Clipboard::new().map_err(|e| format!("Clipboard unavailable: {}", e))?;
+    clipboard
+        .set_image(arboard::ImageData {
+            width: img.width() as usize,
+            height: img.height() as usize,
+            bytes: Cow::Borrowed(img.as_raw()),
+        })
+        .map_err(|e| format!("Copy failed: {}", e))
+}
+
+/// Start writing `img` to the clipboard as a PNG through the async Clipboard API. Only
+/// missing browser support is reported here; a rejected write is logged when it settles.
+#[cfg(target_arch = "wasm32")]
+fn set_clipboard_image(img: &RgbaImage) -> Result<(), String> {
+    use wasm_bindgen::{JsCast, JsValue};
+    use web_sys::{Blob, BlobPropertyBag};
+
+    let unsupported = || "This browser can't copy images to the clipboard".to_owned();
+    let lookup = |target: &JsValue, key: &str| {
+        js_sys::Reflect::get(target, &JsValue::from_str(key))
+            .ok()
+            .filter(|value| !value.is_undefined())
+            .ok_or_else(unsupported)
+    };
+    let window: JsValue = web_sys::window().ok_or_else(unsupported)?.into();
+    let clipboard = lookup(&lookup(&window, "navigator")?, "clipboard")?;
+    let write: js_sys::Function = lookup(&clipboard, "write")?
+        .dyn_into()
+        .map_err(|_| unsupported())?;
+    let item_class: js_sys::Function = lookup(&window, "ClipboardItem")?
+        .dyn_into()
+        .map_err(|_| unsupported())?;
+
+    let png = encode_png(img, "").map_err(|err| err.to_string())?;
+    let bytes = js_sys::Uint8Array::from(png.as_slice());
+    let props = BlobPropertyBag::new();
+    props.set_type("image/png");
+    let blob = Blob::new_with_u8_array_sequence_and_options(&js_sys::Array::of1(&bytes), &props)
+        .map_err(|_| unsupported())?;
+    let record = js_sys::Object::new();
+    js_sys::Reflect::set(&record, &JsValue::from_str("image/png"), &blob)
+        .map_err(|_| unsupported())?;
+    let item = js_sys::Reflect::construct(&item_class, &js_sys::Array::of1(&record))
+        .map_err(|_| unsupported())?;
+    let promise: js_sys::Promise = write
+        .call1(&clipboard, &js_sys::Array::of1(&item))
+        .and_then(|promise| promise.dyn_into())
+        .map_err(|_| unsupported())?;
+    wasm_bindgen_futures::spawn_local(async move {
+        if let Err(err) = wasm_bindgen_futures::JsFuture::from(promise).await {
+            log::error!("Copying to the clipboard failed: {:?}", err);
+        }
+    });
+    Ok(())
+}
+
+/// Quick pre-export summary shown in the Export section
+struct ExportEstimate {
+    contributing: usize, // Layers that can put pixels on the canvas
+    total: usize,
+    largest_source: Option<[u32; 2]>, // Among contributing layers
+    output_size: [u32; 2],
+    png_bytes: Option<usize>, // Estimated size of the composite PNG, `None` while computing
+    html_bytes: Option<usize>, // Estimated size of the HTML viewer, `None` while computing
+    skipped: Vec<String>,     // Non-contributing layers and why
+}
+
+/// Why `key` can't be added to a layer's user data, if it can't
+fn user_data_key_error(key: &str, existing: &BTreeMap<String, String>) -> Option<&'static str> {
+    let mut chars = key.chars();
+    let starts_ok = chars
+        .next()
+        .is_some_and(|c| c.is_ascii_alphabetic() || c == '_');
+    if !starts_ok || !chars.all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        Some("Keys are letters, digits and _, not starting with a digit")
+    } else if existing.contains_key(key) {
+        Some("Key already set")
+    } else {
+        None
+    }
+}
+
+/// Current UTC time as `YYYYMMDD-HHMMSS`, for export file names
+fn export_timestamp() -> String {
+    #[cfg(not(target_arch = "wasm32"))]
+    let secs = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_secs())
+        .unwrap_or(0);
+    #[cfg(target_arch = "wasm32")]
+    let secs = (js_sys::Date::now() / 1000.0) as u64;
+
+    // Days since 1970-01-01 to a civil date (Howard Hinnant's days_from_civil, inverted)
+    let z = secs / 86_400 + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    let time = secs % 86_400;
+    format!(
+        "{:04}{:02}{:02}-{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        time / 3600,
+        time / 60 % 60,
+        time % 60
+    )
+}
+
+/// Human-readable byte count
+fn format_bytes(bytes: usize) -> String {
+    match bytes {
+        0..1024 => format!("{} B", bytes),
+        1024..1_048_576 => format!("{:.0} KB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MB", bytes as f64 / 1_048_576.0),
+    }
+}
+
+/// Page for `CompositeSnapshot::build_html_viewer`; the model replaces `/*MODEL*/null`
+const HTML_VIEWER_TEMPLATE: &str = r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<title>Kitbash Viewer</title>
+<style>
+  body { font-family: sans-serif; background: #222; color: #ddd; display: flex; gap: 24px; padding: 16px; }
+  #stage { position: relative; flex: none; background-color: #555;
+    background-image: conic-gradient(#777 25%, transparent 0 50%, #777 0 75%, transparent 0);
+    background-size: 16px 16px; }
+  #stage img { position: absolute; image-rendering: pixelated; }
+  #parts label { display: block; margin: 2px 0; cursor: pointer; }
+</style>
+</head>
+<body>
+<div id="stage"></div>
+<div id="parts"></div>
+<script>
+const model = /*MODEL*/null;
+const stage = document.getElementById("stage");
+const list = document.getElementById("parts");
+stage.style.width = model.width + "px";
+stage.style.height = model.height + "px";
+const bg = document.createElement("div");
+bg.style.cssText = "position:absolute;inset:0;background:" + model.background;
+stage.appendChild(bg);
+// Parts are in draw order, so the list shows the topmost first
+const rows = [];
+for (const part of model.parts) {
+  const img = document.createElement("img");
+  img.src = part.src;
+  img.style.left = part.x + "px";
+  img.style.top = part.y + "px";
+  img.width = part.width;
+  img.height = part.height;
+  img.hidden = !part.visible;
+  stage.appendChild(img);
+
+  const row = document.createElement("label");
+  const box = document.createElement("input");
+  box.type = "checkbox";
+  box.checked = part.visible;
+  box.onchange = () => { img.hidden = !box.checked; };
+  row.append(box, " " + part.name);
+  rows.unshift(row);
+}
+list.append(...rows);
+</script>
+</body>
+</html>
+"#;
+
+/// Warn before writing an HTML viewer larger than this
+const HTML_VIEWER_WARN_BYTES: usize = 20 * 1024 * 1024;
+
+/// Export file names before placeholders are filled in; see `KitbashApp::export_stem`
+const DEFAULT_EXPORT_TEMPLATE: &str = "{project}_{timestamp}";
+
+/// Folder of the unpacked ZIP inside the Godot project, used in `.tres` paths
+const DEFAULT_GODOT_PREFIX: &str = "res://";
+
+/// Export scale combo entries; anything else up to `MAX_EXPORT_SCALE` is "Custom"
+const EXPORT_SCALE_PRESETS: [u32; 4] = [1, 2, 4, 8];
+const MAX_EXPORT_SCALE: u32 = 16;
+
+/// Standard base64 with padding
+fn base64_encode(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (b[0] as u32) << 16 | (b[1] as u32) << 8 | b[2] as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(ALPHABET[(n >> (18 - 6 * i)) as usize & 63] as char);
+            } else {
+                out.push('=');
+            }
+        }
+    }
+    out
+}
+
+/// Longest entry name stem kept in exported archives, in characters
+const MAX_ENTRY_STEM: usize = 80;
+
+/// Device names Windows reserves in any directory, with or without an extension
+const WINDOWS_RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turn user text into a safe archive entry stem: NFC-normalized (so visually equal names
+/// compare equal), no path separators, reserved or control characters, no leading/trailing
+/// dots or spaces, no Windows device name, and bounded length
+fn sanitize_entry_stem(stem: &str) -> String {
+    let stem = icu_normalizer::ComposingNormalizerBorrowed::new_nfc().normalize(stem);
+    let cleaned: String = stem
+        .chars()
+        .filter(|c| !c.is_control())
+        .map(|c| match c {
+            '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|' => '_',
+            c if c.is_whitespace() => ' ',
+            c => c,
+        })
+        .collect();
+    let mut trimmed: String = cleaned
+        .trim_matches(|c: char| c == '.' || c == ' ')
+        .chars()
+        .take(MAX_ENTRY_STEM)
+        .collect();
+    // "nul.png" and "con.old.png" are devices too, only the part before the first dot counts
+    let device = trimmed.split('.').next().unwrap_or_default().trim_end();
+    if WINDOWS_RESERVED_NAMES
+        .iter()
+        .any(|name| name.eq_ignore_ascii_case(device))
+    {
+        trimmed.insert(device.len(), '_');
+    }
+    if trimmed.is_empty() {
+        "layer".to_owned()
+    } else {
+        trimmed
+    }
+}
+
+/// Entry names for `stems` with `extension` added, sanitized and made unique (ignoring case,
+/// for case-insensitive file systems) with numeric suffixes. Also returns a line for every
+/// name that had to change.
+fn entry_names(stems: &[String], extension: &str) -> (Vec<String>, Vec<String>) {
+    let mut taken = HashSet::new();
+    let mut renames = Vec::new();
+    let names = stems
+        .iter()
+        .map(|stem| {
+            let base = sanitize_entry_stem(stem);
+            let mut name = format!("{}.{}", base, extension);
+            let mut n = 2;
+            while !taken.insert(name.to_lowercase()) {
+                name = format!("{}_{}.{}", base, n, extension);
+                n += 1;
+            }
+            let wanted = format!("{}.{}", stem, extension);
+            if name != wanted {
+                renames.push(format!("{} → {}", wanted, name));
+            }
+            name
+        })
+        .collect();
+    (names, renames)
+}
+
+/// Keyword of the `tEXt` chunk carrying the export stamp
+const PNG_TEXT_KEYWORD: &str = "kitbash";
+
+/// tEXt is Latin-1; keep it ASCII to be safe
+fn png_text(text: &str) -> String {
+    text.chars()
+        .map(|c| if c.is_ascii() { c } else { '?' })
+        .collect()
+}
+
+/// Encode as PNG with `text` stored in a `tEXt` chunk under `PNG_TEXT_KEYWORD`
+fn encode_png(img: &RgbaImage, text: &str) -> Result<Vec<u8>, KitbashError> {
+    let mut bytes = Vec::new();
+    let result = (|| {
+        let mut encoder = png::Encoder::new(&mut bytes, img.width(), img.height());
+        encoder.set_color(png::ColorType::Rgba);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.add_text_chunk(PNG_TEXT_KEYWORD.to_owned(), png_text(text))?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(img.as_raw())?;
+        writer.finish()
+    })();
+    result.map_err(|e| KitbashError::Export(format!("Failed to encode PNG: {}", e)))?;
+    Ok(bytes)
+}
+
+/// Encode the composite as `format`, as an indexed PNG when the palette is limited.
+/// JPEG has no alpha, so whatever alpha is left is flattened onto `matte`, and the stamp
+/// goes into a comment segment instead of a text chunk.
+fn encode_composite(
+    img: &RgbaImage,
+    format: CompositeFormat,
+    jpeg_quality: u8,
+    palette_limit: PaletteLimit,
+    matte: egui::Color32,
+    stamp: &str,
+) -> Result<Vec<u8>, KitbashError> {
+    let mut bytes = Vec::new();
+    let result = match format {
+        CompositeFormat::Png if palette_limit.enabled => {
+            let colors = palette::median_cut(img, palette_limit.colors);
+            let indices = palette::remap(img, &colors, palette_limit.dither);
+            let text = (PNG_TEXT_KEYWORD, png_text(stamp));
+            return palette::encode_png(img.width(), img.height(), &colors, &indices, text)
+                .map_err(|e| KitbashError::Export(format!("Failed to encode PNG: {}", e)));
+        }
+        CompositeFormat::Png => return encode_png(img, stamp),
+        CompositeFormat::WebP => image::codecs::webp::WebPEncoder::new_lossless(&mut bytes).encode(
+            img.as_raw(),
+            img.width(),
+            img.height(),
+            image::ExtendedColorType::Rgba8,
+        ),
+        CompositeFormat::Jpeg => {
+            let bg = [matte.r(), matte.g(), matte.b()];
+            let flat = image::RgbImage::from_fn(img.width(), img.height(), |x, y| {
+                let Rgba([r, g, b, a]) = *img.get_pixel(x, y);
+                let mix = |c: u8, bg: u8| {
+                    ((c as u32 * a as u32 + bg as u32 * (255 - a as u32) + 127) / 255) as u8
+                };
+                image::Rgb([mix(r, bg[0]), mix(g, bg[1]), mix(b, bg[2])])
+            });
+            image::codecs::jpeg::JpegEncoder::new_with_quality(&mut bytes, jpeg_quality)
+                .encode_image(&flat)
+                .map(|()| bytes = insert_jpeg_comment(std::mem::take(&mut bytes), stamp))
+        }
+    };
+    result.map_err(|source| KitbashError::Encode {
+        format: format.label(),
+        source,
+    })?;
+    Ok(bytes)
+}
+
+/// Insert a COM segment after the JFIF header (image's JPEG encoder has no comment option)
+fn insert_jpeg_comment(jpeg: Vec<u8>, text: &str) -> Vec<u8> {
+    const SOI: usize = 2;
+    // Longest payload a segment length (which counts its own two bytes) can describe
+    let text = &text.as_bytes()[..text.len().min(u16::MAX as usize - 2)];
+    let at = match jpeg.get(SOI..SOI + 4) {
+        Some([0xFF, 0xE0, hi, lo]) => SOI + 2 + u16::from_be_bytes([*hi, *lo]) as usize,
+        Some(_) => SOI,
+        None => return jpeg,
+    };
+    if at > jpeg.len() {
+        return jpeg;
+    }
+    let mut segment = vec![0xFF, 0xFE];
+    segment.extend_from_slice(&(text.len() as u16 + 2).to_be_bytes());
+    segment.extend_from_slice(text);
+    let mut out = jpeg;
+    out.splice(at..at, segment);
+    out
+}
+
+/// Multiply every pixel of `image` by `tint` (RGB and alpha)
+fn apply_tint(image: &mut RgbaImage, tint: egui::Color32) {
+    if tint == egui::Color32::WHITE {
+        return;
+    }
+    let tint = tint.to_srgba_unmultiplied();
+    for pixel in image.pixels_mut() {
+        for (channel, factor) in pixel.0.iter_mut().zip(tint) {
+            *channel = ((*channel as u16 * factor as u16 + 127) / 255) as u8;
+        }
+    }
+}
+
+/// Render a single layer to a buffer (full canvas size)
+fn render_single_layer(
+    canvas_size: [u32; 2],
+    layer: &LayerImage,
+    export_scale: u32,
+) -> Option<RgbaImage> {
+    if !layer.visible {
+        return None;
+    }
+
+    let width = canvas_size[0] * export_scale;
+    let height = canvas_size[1] * export_scale;
+
+    let mut buffer = RgbaImage::new(width, height);
+    // Note: Individual layers are transparent background by default
+    render_layer_into(&mut buffer, layer, export_scale);
+
+    Some(buffer)
+}
+
+/// A layer's final pixels at `export_scale`: scaled, adjusted, tinted, flipped and with
+/// effects applied. Their top-left sits `effect_origin` up/left of the layer's top-left.
+fn render_layer_pixels(layer: &LayerImage, export_scale: u32) -> Option<RgbaImage> {
+    let scale_f = export_scale as f32;
+
+    let src_width = layer.source_image.width();
+    let src_height = layer.source_image.height();
+
+    let target_width = (src_width as f32 * layer.transform.scale_x * scale_f).round() as u32;
+    let target_height = (src_height as f32 * layer.transform.scale_y * scale_f).round() as u32;
+
+    if target_width == 0 || target_height == 0 {
+        return None;
+    }
+
+    // Unscaled layers skip the resampling pass; `resize_exact` would only copy them
+    let mut resized = if [target_width, target_height] == [src_width, src_height] {
+        layer.source_image.to_rgba8()
+    } else {
+        layer
+            .source_image
+            .resize_exact(target_width, target_height, FilterType::Nearest)
+            .to_rgba8()
+    };
+    layer.adjust.apply(&mut resized);
+    layer.tone.apply(&mut resized);
+    apply_tint(&mut resized, layer.tint);
+    if layer.transform.flip_h {
+        image::imageops::flip_horizontal_in_place(&mut resized);
+    }
+
+    let outline_px = layer.outline.thickness * export_scale;
+    let outlined = layer.outline.apply(resized, outline_px);
+    Some(layer.shadow.apply(outlined, export_scale))
+}
+
+/// Draw a layer's final pixels onto `buffer` at its canvas position
+fn render_layer_into(buffer: &mut RgbaImage, layer: &LayerImage, export_scale: u32) {
+    let Some(pixels) = render_layer_pixels(layer, export_scale) else {
+        return;
+    };
+
+    let scale_f = export_scale as f32;
+    let [ox, oy] = layer.effect_origin(export_scale);
+    let top_left = layer.top_left();
+    let x = (top_left.x * scale_f).round() as i64 - ox as i64;
+    let y = (top_left.y * scale_f).round() as i64 - oy as i64;
+
+    image::imageops::overlay(buffer, &pixels, x, y);
+}
+
+/// For each position in `order`, the layer index a clipped layer is clipped to: the nearest
+/// non-clipping layer below it. The bottom layer has nothing to clip to and draws normally.
+fn clip_bases(layers: &[LayerImage], order: &[usize]) -> Vec<Option<usize>> {
+    let mut base = None;
+    order
+        .iter()
+        .map(|&idx| {
+            if layers[idx].is_reference {
+                // References are left out of the output, so they neither clip nor are clipped to
+                None
+            } else if layers[idx].clip_to_below && base.is_some() {
+                base
+            } else {
+                base = Some(idx);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Multiply `image`'s alpha by `mask`'s (both full canvas size)
+fn clip_alpha(image: &mut RgbaImage, mask: &RgbaImage) {
+    for (pixel, mask) in image.pixels_mut().zip(mask.pixels()) {
+        pixel[3] = (pixel[3] as u16 * mask[3] as u16 / 255) as u8;
+    }
+}
+
+/// Whole pixels for metadata; `-0.0` becomes `0.0` so JSON output doesn't depend on
+/// which side of zero a value was rounded from
+fn round_px(value: f32) -> f32 {
+    value.round() + 0.0
+}
+
+/// Entry options shared by every exported ZIP. The fixed (DOS epoch) timestamp keeps the
+/// same project exporting to byte-identical archives.
+fn zip_file_options() -> zip::write::FileOptions {
+    zip::write::FileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::default())
+}
+
+/// `@2x`-style file name suffix for an export scale, empty at 1x
+fn scale_suffix(export_scale: u32) -> String {
+    if export_scale > 1 {
+        format!("@{}x", export_scale)
+    } else {
+        String::new()
+    }
+}
+
+/// Blow up a finished native-size image by a whole factor, keeping pixel edges crisp
+fn upscale_nearest(img: RgbaImage, export_scale: u32) -> RgbaImage {
+    if export_scale <= 1 {
+        return img;
+    }
+    let (width, height) = img.dimensions();
+    image::imageops::resize(
+        &img,
+        width * export_scale,
+        height * export_scale,
+        FilterType::Nearest,
+    )
+}
+
+/// Render the layer at `order[pos]` for per-layer export, applying its clipping mask and
+/// the output effects. A clipped layer whose base is hidden is not rendered.
+fn render_export_layer(
+    canvas_size: [u32; 2],
+    layers: &[LayerImage],
+    order: &[usize],
+    pos: usize,
+    export_scale: u32,
+    effects: &OutputEffects,
+) -> Option<RgbaImage> {
+    let mut img = render_single_layer(canvas_size, &layers[order[pos]], 1)?;
+    if let Some(base) = clip_bases(layers, order)[pos] {
+        let mask = render_single_layer(canvas_size, &layers[base], 1)?;
+        clip_alpha(&mut img, &mask);
+    }
+    effects.apply(&mut img, 1);
+    Some(upscale_nearest(img, export_scale))
+}
+
+/// Merge every visible layer in draw order over `bg_color` (the single-image export),
+/// then apply the output effects. Everything is composited at native canvas size and the
+/// result upscaled by `export_scale`, so scaled exports are exact pixel blow-ups.
+fn composite_image(
+    canvas_size: [u32; 2],
+    layers: &[LayerImage],
+    export_scale: u32,
+    bg_color: egui::Color32,
+    effects: &OutputEffects,
+) -> RgbaImage {
+    let [width, height] = canvas_size;
+    // A new buffer is already transparent black, no need to fill it. Compared after
+    // unmultiplying: additive colors have zero alpha but still carry color.
+    let fill = bg_color.to_srgba_unmultiplied();
+    let mut buffer = if fill == [0; 4] {
+        RgbaImage::new(width, height)
+    } else {
+        RgbaImage::from_pixel(width, height, Rgba(fill))
+    };
+
+    let order: Vec<usize> = draw_order(layers)
+        .into_iter()
+        .filter(|&idx| !layers[idx].is_reference)
+        .collect();
+    let bases = clip_bases(layers, &order);
+    // Pixels of the current clipping base, kept only while layers above clip to it
+    let mut mask: Option<RgbaImage> = None;
+    for (pos, &idx) in order.iter().enumerate() {
+        let layer = &layers[idx];
+        if bases[pos].is_some() {
+            if let (Some(mask), true) = (&mask, layer.visible) {
+                if let Some(mut img) = render_single_layer(canvas_size, layer, 1) {
+                    clip_alpha(&mut img, mask);
+                    image::imageops::overlay(&mut buffer, &img, 0, 0);
+                }
+            }
+            continue;
+        }
+
+        let clips_above = bases.get(pos + 1).is_some_and(|b| b.is_some());
+        mask = None;
+        if !layer.visible {
+            continue;
+        }
+        if clips_above {
+            mask = render_single_layer(canvas_size, layer, 1);
+            if let Some(img) = &mask {
+                image::imageops::overlay(&mut buffer, img, 0, 0);
+            }
+        } else {
+            render_layer_into(&mut buffer, layer, 1);
+        }
+    }
+    effects.apply(&mut buffer, 1);
+    upscale_nearest(buffer, export_scale)
+}
+
+/// `[x, y, width, height]` of the pixels with non-zero alpha, `None` if fully transparent
+fn alpha_bounds(img: &RgbaImage) -> Option<[u32; 4]> {
+    let (mut min_x, mut min_y, mut max_x, mut max_y) = (u32::MAX, u32::MAX, 0, 0);
+    for (x, y, pixel) in img.enumerate_pixels() {
+        if pixel[3] > 0 {
+            min_x = min_x.min(x);
+            min_y = min_y.min(y);
+            max_x = max_x.max(x);
+            max_y = max_y.max(y);
+        }
+    }
+    (min_x <= max_x).then(|| [min_x, min_y, max_x - min_x + 1, max_y - min_y + 1])
+}
+
+/// Worker-side pause/skip/cancel state, updated from `ImportCommand`s
+#[derive(Default)]
+struct ImportControl {
+    paused: bool,
+    skip: bool,
+    cancelled: bool,
+}
+
+impl ImportControl {
+    fn apply(&mut self, command: ImportCommand) {
+        match command {
+            ImportCommand::Pause => self.paused = true,
+            ImportCommand::Resume => self.paused = false,
+            ImportCommand::SkipCurrent => self.skip = true,
+            ImportCommand::Cancel => self.cancelled = true,
+        }
+    }
+
+    /// Apply every command that is already waiting, without blocking
+    fn drain(&mut self, commands: &mut UnboundedReceiver<ImportCommand>) {
+        loop {
+            match commands.try_recv() {
+                Ok(command) => self.apply(command),
+                Err(TryRecvError::Closed) => {
+                    // The app dropped the queue, nobody is listening anymore
+                    self.cancelled = true;
+                    break;
+                }
+                Err(TryRecvError::Empty) => break,
+            }
+        }
+    }
+}
+
+/// Read and decode picked files one at a time, checking for pause/skip/cancel between
+/// files
+async fn run_import_queue(
+    handles: Vec<rfd::FileHandle>,
+    events: EventQueue,
+    mut commands: UnboundedReceiver<ImportCommand>,
+) {
+    let names = handles.iter().map(|h| h.file_name()).collect();
+    events.send(AppEvent::Import(ImportEvent::Queued(names)));
+
+    let mut control = ImportControl::default();
+    for (index, handle) in handles.iter().enumerate() {
+        // Wait here while paused; new commands wake us up
+        loop {
+            control.drain(&mut commands);
+            if control.cancelled || !control.paused {
+                break;
+            }
+            match commands.next().await {
+                Some(command) => control.apply(command),
+                None => control.cancelled = true,
+            }
+        }
+        if control.cancelled {
+            break;
+        }
+
+        // A skip only applies to the file that was being read when it was issued
+        control.skip = false;
+        events.send(AppEvent::Import(ImportEvent::Started(index)));
+        let data = handle.read().await;
+
+        // A skip or cancel issued while reading discards this file
+        control.drain(&mut commands);
+        if control.cancelled {
+            break;
+        }
+        if control.skip {
+            events.send(AppEvent::Import(ImportEvent::Skipped(index)));
+            continue;
+        }
+        let name = handle.file_name();
+        let event = match image::load_from_memory(&data) {
+            Ok(img) => ImportEvent::Loaded(name, img),
+            Err(source) => ImportEvent::Failed(KitbashError::Decode { name, source }.to_string()),
+        };
+        events.send(AppEvent::Import(event));
+    }
+
+    events.send(AppEvent::Import(ImportEvent::Finished));
+}
+
+/// The parts of `outer` above, below, left and right of `inner` (empty where `inner`
+/// reaches the edge)
+fn overflow_bands(outer: egui::Rect, inner: egui::Rect) -> [egui::Rect; 4] {
+    [
+        egui::Rect::from_x_y_ranges(outer.x_range(), outer.min.y..=inner.min.y),
+        egui::Rect::from_x_y_ranges(outer.x_range(), inner.max.y..=outer.max.y),
+        egui::Rect::from_x_y_ranges(outer.min.x..=inner.min.x, inner.y_range()),
+        egui::Rect::from_x_y_ranges(inner.max.x..=outer.max.x, inner.y_range()),
+    ]
+    .map(|band| band.intersect(outer))
+}
+
+/// Arrow just inside the canvas edge pointing at a layer lying entirely outside it
+fn draw_offscreen_arrow(painter: &egui::Painter, canvas_rect: egui::Rect, target: egui::Pos2) {
+    const LENGTH: f32 = 10.0;
+    let inset = canvas_rect.shrink((LENGTH + 2.0).min(canvas_rect.size().min_elem() / 2.0));
+    let dir = (target - canvas_rect.center()).normalized();
+    let tip = inset.clamp(target);
+    painter.arrow(
+        tip - dir * LENGTH,
+        dir * LENGTH,
+        egui::Stroke::new(2.0, egui::Color32::from_rgb(255, 160, 0)),
+    );
+}
+
+/// Canvas (0,0) marker: X grows right, Y grows down, as in data.json offsets
+fn draw_origin_marker(
+    painter: &egui::Painter,
+    origin: egui::Pos2,
+    view: egui::Rect,
+    axis_lines: bool,
+) {
+    let x_color = egui::Color32::from_rgb(230, 80, 80);
+    let y_color = egui::Color32::from_rgb(80, 200, 80);
+
+    if axis_lines {
+        let faint = |c: egui::Color32| egui::Stroke::new(1.0, c.gamma_multiply(0.3));
+        painter.hline(view.x_range(), origin.y, faint(x_color));
+        painter.vline(origin.x, view.y_range(), faint(y_color));
+    }
+
+    let len = 24.0;
+    painter.arrow(
+        origin,
+        egui::vec2(len, 0.0),
+        egui::Stroke::new(2.0, x_color),
+    );
+    painter.arrow(
+        origin,
+        egui::vec2(0.0, len),
+        egui::Stroke::new(2.0, y_color),
+    );
+
+    let font = egui::FontId::monospace(10.0);
+    painter.text(
+        origin + egui::vec2(len + 3.0, 0.0),
+        egui::Align2::LEFT_CENTER,
+        "+X",
+        font.clone(),
+        x_color,
+    );
+    painter.text(
+        origin + egui::vec2(0.0, len + 3.0),
+        egui::Align2::CENTER_TOP,
+        "+Y",
+        font.clone(),
+        y_color,
+    );
+    painter.text(
+        origin - egui::vec2(3.0, 3.0),
+        egui::Align2::RIGHT_BOTTOM,
+        "(0,0)",
+        font,
+        egui::Color32::WHITE,
+    );
+}
+
+/// egui's stock animation duration, restored when motion is allowed again
+const DEFAULT_ANIMATION_TIME: f32 = 1.0 / 12.0;
+
+#[cfg(target_arch = "wasm32")]
+fn system_prefers_reduced_motion() -> bool {
+    web_sys::window()
+        .and_then(|w| {
+            w.match_media("(prefers-reduced-motion: reduce)")
+                .ok()
+                .flatten()
+        })
+        .is_some_and(|query| query.matches())
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn system_prefers_reduced_motion() -> bool {
+    // No portable desktop query, the Reduce Motion setting covers native
+    false
+}
+
+#[cfg(target_arch = "wasm32")]
+fn trigger_download(filename: &str, data: &[u8]) -> Result<(), String> {
+    use wasm_bindgen::JsCast;
+    use web_sys::{Blob, BlobPropertyBag, HtmlAnchorElement, Url};
+
+    let failed = || format!("Failed to download {}", filename);
+    let document = web_sys::window()
+        .and_then(|window| window.document())
+        .ok_or_else(failed)?;
+    let body = document.body().ok_or_else(failed)?;
+
+    let array = js_sys::Uint8Array::from(data);
+    let parts = js_sys::Array::new();
+    parts.push(&array);
+
+    let props = BlobPropertyBag::new();
+    let extension = filename.rsplit_once('.').map_or("", |(_, ext)| ext);
+    props.set_type(match extension {
+        "zip" => "application/zip",
+        "json" => "application/json",
+        "html" => "text/html",
+        "png" => "image/png",
+        "webp" => "image/webp",
+        "jpg" => "image/jpeg",
+        "gif" => "image/gif",
+        _ => "application/octet-stream",
+    });
+
+    let blob =
+        Blob::new_with_u8_array_sequence_and_options(&parts, &props).map_err(|_| failed())?;
+    let url = Url::create_object_url_with_blob(&blob).map_err(|_| failed())?;
+
+    let link: HtmlAnchorElement = document
+        .create_element("a")
+        .ok()
+        .and_then(|link| link.dyn_into().ok())
+        .ok_or_else(failed)?;
+    link.set_href(&url);
+    link.set_download(filename);
+    let _ = link.style().set_property("display", "none");
+
+    body.append_child(&link).map_err(|_| failed())?;
+    link.click();
+    let _ = body.remove_child(&link);
+    let _ = Url::revoke_object_url(&url);
+    Ok(())
+}
+
+/// Ask where to save, then write every file there. A single file gets a save dialog
+/// prefilled with its name; several files go into one picked folder under their own names.
+#[cfg(not(target_arch = "wasm32"))]
+async fn save_files_task(
+    dialog: rfd::AsyncFileDialog,
+    files: Vec<(String, Vec<u8>)>,
+    backup: BackupPolicy,
+    events: EventQueue,
+) {
+    let paths: Vec<std::path::PathBuf> = match files.as_slice() {
+        [(filename, _)] => match dialog.set_file_name(filename).save_file().await {
+            Some(handle) => vec![handle.path().to_path_buf()],
+            None => return,
+        },
+        _ => match dialog.pick_folder().await {
+            Some(handle) => files
+                .iter()
+                .map(|(filename, _)| handle.path().join(filename))
+                .collect(),
+            None => return,
+        },
+    };
+    let dir = paths[0].parent().unwrap_or(std::path::Path::new("."));
+    events.send(AppEvent::Io(IoEvent::SaveRequested(dir.to_path_buf())));
+
+    let result = paths
+        .iter()
+        .zip(&files)
+        .try_for_each(|(path, (_, data))| write_export_file(path, data, backup))
+        .map(|()| match paths.as_slice() {
+            [path] => format!("Saved {}", path.display()),
+            _ => format!("Saved {} files to {}", paths.len(), dir.display()),
+        });
+    events.send(AppEvent::Io(IoEvent::SaveCompleted(result)));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn write_export_file(
+    path: &std::path::Path,
+    data: &[u8],
+    backup: BackupPolicy,
+) -> Result<(), String> {
+    let filename = path.display();
+    if backup.enabled && path.exists() {
+        // Never overwrite the old file unless it was safely moved aside first
+        backup_existing_file(path, backup.keep)
+            .map_err(|e| format!("Could not back up {}, export aborted: {}", filename, e))?;
+    }
+
+    let mut file =
+        std::fs::File::create(path).map_err(|e| format!("Failed to create {}: {}", filename, e))?;
+    file.write_all(data)
+        .map_err(|e| format!("Failed to write {}: {}", filename, e))?;
+    log::info!("Saved {} ({} bytes)", filename, data.len());
+    Ok(())
+}
+
+/// `dir/name.png` -> `dir/name.png.bak-{timestamp_ms}` (zero-padded so names sort by age),
+/// with `-{collision}` appended when an earlier backup took that name
+#[cfg(not(target_arch = "wasm32"))]
+fn backup_path(path: &std::path::Path, timestamp_ms: u128, collision: u32) -> std::path::PathBuf {
+    let mut name = path.file_name().unwrap_or_default().to_os_string();
+    name.push(format!(".bak-{:013}", timestamp_ms));
+    if collision > 0 {
+        name.push(format!("-{}", collision));
+    }
+    path.with_file_name(name)
+}
+
+/// Timestamp and collision counter of a backup name suffix written by `backup_path`
+#[cfg(not(target_arch = "wasm32"))]
+fn parse_backup_stamp(stamp: &str) -> Option<(u128, u32)> {
+    let (time, collision) = stamp.split_once('-').unwrap_or((stamp, "0"));
+    let digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    if !digits(time) || !digits(collision) {
+        return None;
+    }
+    Some((time.parse().ok()?, collision.parse().ok()?))
+}
+
+/// Existing backups of `path`, oldest first
+#[cfg(not(target_arch = "wasm32"))]
+fn list_backups(path: &std::path::Path) -> std::io::Result<Vec<std::path::PathBuf>> {
+    let dir = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => std::path::Path::new("."),
+    };
+    let prefix = format!(
+        "{}.bak-",
+        path.file_name().unwrap_or_default().to_string_lossy()
+    );
+
+    let mut backups: Vec<_> = std::fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            let stamp = parse_backup_stamp(name.strip_prefix(&prefix)?)?;
+            Some((stamp, entry.path()))
+        })
+        .collect();
+    backups.sort();
+    Ok(backups.into_iter().map(|(_, path)| path).collect())
+}
+
+/// Move `path` aside as a timestamped backup, then prune all but the newest `keep` backups
+#[cfg(not(target_arch = "wasm32"))]
+fn backup_existing_file(path: &std::path::Path, keep: usize) -> std::io::Result<()> {
+    let timestamp_ms = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|d| d.as_millis())
+        .unwrap_or(0);
+    backup_file_at(path, keep, timestamp_ms)
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn backup_file_at(path: &std::path::Path, keep: usize, timestamp_ms: u128) -> std::io::Result<()> {
+    // Two exports within the same millisecond get numbered backups instead of clobbering
+    let backup = (0..)
+        .map(|collision| backup_path(path, timestamp_ms, collision))
+        .find(|backup| !backup.exists())
+        .expect("some backup name is free");
+    std::fs::rename(path, backup)?;
+
+    // A failed prune only leaves extra backups behind, the export itself is safe
+    let backups = match list_backups(path) {
+        Ok(backups) => backups,
+        Err(err) => {
+            log::warn!("Failed to list backups of {}: {}", path.display(), err);
+            return Ok(());
+        }
+    };
+    let excess = backups.len().saturating_sub(keep);
+    for old in &backups[..excess] {
+        if let Err(err) = std::fs::remove_file(old) {
+            log::warn!("Failed to prune backup {}: {}", old.display(), err);
+        }
+    }
+    Ok(())
+}
+
+// ----------------------------------------------------------------------------
+// App Implementation
+// ----------------------------------------------------------------------------
+
+impl eframe::App for KitbashApp {
+    fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        self.ui(ctx);
+    }
+
+    fn save(&mut self, storage: &mut dyn eframe::Storage) {
+        eframe::set_value(storage, EXPORT_BACKGROUND_KEY, &self.export_background);
+        eframe::set_value(storage, BACKUP_POLICY_KEY, &self.backup_policy);
+        eframe::set_value(storage, BG_PALETTE_KEY, &self.bg_palette);
+        eframe::set_value(storage, JPEG_MATTE_KEY, &self.jpeg_matte);
+        eframe::set_value(storage, VIEWER_MODE_KEY, &self.viewer_mode);
+    }
+
+    // Only the settings written in `save` are kept, not window and panel state
+    fn persist_egui_memory(&self) -> bool {
+        false
+    }
+
+    // Lets the web entry point move the app into a new runner after a lost GPU context
+    #[cfg(target_arch = "wasm32")]
+    fn as_any_mut(&mut self) -> Option<&mut dyn std::any::Any> {
+        Some(&mut *self)
+    }
+}
+
+impl KitbashApp {
+    /// One frame of the whole app; independent of `eframe::Frame` so it can run headless
+    fn ui(&mut self, ctx: &egui::Context) {
+        // Handle background task events
+        for event in self.events.drain() {
+            self.handle_event(ctx, event);
+        }
+
+        #[cfg(not(target_arch = "wasm32"))]
+        {
+            let title = match self.project_label() {
+                "" => "Kitbash".to_owned(),
+                name => format!("{} - Kitbash", name),
+            };
+            if title != self.window_title {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Title(title.clone()));
+                self.window_title = title;
+            }
+        }
+
+        if GRAPHICS_CONTEXT_RESTORED.swap(false, Ordering::Relaxed) {
+            log::warn!("Graphics context restored, re-uploading textures");
+            self.drop_textures();
+            self.show_toast(ctx, "Reinitializing preview…");
+        }
+
+        let estimating = self
+            .export_estimate
+            .as_ref()
+            .is_some_and(|(_, estimate)| estimate.png_bytes.is_none())
+            || self
+                .export_preview
+                .as_ref()
+                .is_some_and(|preview| preview.building.is_some())
+            || self.palette_building.is_some();
+        if self.import_queue.is_some() || self.export_job.is_some() || estimating {
+            // Workers can't wake the UI, so keep polling while they run
+            ctx.request_repaint_after(std::time::Duration::from_millis(100));
+        }
+
+        let animation_time = if self.motion_enabled() {
+            DEFAULT_ANIMATION_TIME
+        } else {
+            0.0
+        };
+        if ctx.style().animation_time != animation_time {
+            ctx.style_mut(|style| style.animation_time = animation_time);
+        }
+
+        let toggle_console = egui::KeyboardShortcut::new(
+            egui::Modifiers::COMMAND | egui::Modifiers::SHIFT,
+            egui::Key::F12,
+        );
+        if ctx.input_mut(|i| i.consume_shortcut(&toggle_console)) {
+            self.debug_console = !self.debug_console;
+        }
+
+        // View zoom, taken before egui's end-of-frame Ctrl+=/-/0 UI scaling can see them
+        use egui::gui_zoom::kb_shortcuts;
+        let zoom_in = ctx.input_mut(|i| {
+            i.consume_shortcut(&kb_shortcuts::ZOOM_IN)
+                || i.consume_shortcut(&kb_shortcuts::ZOOM_IN_SECONDARY)
+        });
+        if zoom_in {
+            self.set_zoom(self.preview_zoom * 2.0);
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&kb_shortcuts::ZOOM_OUT)) {
+            self.set_zoom(self.preview_zoom / 2.0);
+        }
+        if ctx.input_mut(|i| i.consume_shortcut(&kb_shortcuts::ZOOM_RESET)) {
+            self.zoom_fit = true;
+        }
+
+        // Keyboard Shortcuts (all of them edit the document)
+        // Redo first: Ctrl+Shift+Z also matches the Ctrl+Z undo shortcut
+        if self.can_edit() {
+            if ctx.input_mut(|i| i.consume_shortcut(&self.keybindings.redo)) {
+                self.redo();
+            }
+            if ctx.input_mut(|i| i.consume_shortcut(&self.keybindings.undo)) {
+                self.undo();
+            }
+            if ctx.input_mut(|i| i.consume_shortcut(&self.keybindings.duplicate_layer)) {
+                if let Some(id) = self.selected_layer_id {
+                    self.duplicate_layer(id);
+                }
+            }
+            let place = !ctx.wants_keyboard_input()
+                && ctx.input_mut(|i| i.consume_shortcut(&self.keybindings.place_at_click));
+            if place {
+                self.placing = self.selected_layer_id;
+            }
+            if ctx.input_mut(|i| i.consume_shortcut(&self.keybindings.paste_replace)) {
+                self.paste_replace(ctx);
+            }
+            let delete = !ctx.wants_keyboard_input()
+                && ctx.input_mut(|i| i.consume_key(egui::Modifiers::NONE, egui::Key::Delete));
+            if delete && !self.selection.is_empty() {
+                self.delete_layers(&self.selected_ids());
+            }
+        }
+
+        let is_mobile = ctx.screen_rect().width() < 600.0;
+
+        // --------------------------------------------------------------------
+        // UI Components
+        // --------------------------------------------------------------------
+
+        // Scatter dialog
+        let mut scatter_decision = None;
+        if let Some(scatter) = &mut self.scatter {
+            egui::Window::new("Scatter")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    egui::Grid::new("scatter_grid")
+                        .num_columns(2)
+                        .show(ui, |ui| {
+                            ui.label("Count:");
+                            ui.add(egui::DragValue::new(&mut scatter.count).range(1..=500));
+                            ui.end_row();
+
+                            ui.label("Seed:");
+                            ui.add(egui::DragValue::new(&mut scatter.seed));
+                            ui.end_row();
+
+                            ui.label("Area Min:");
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut scatter.area.min.x).prefix("X: "));
+                                ui.add(egui::DragValue::new(&mut scatter.area.min.y).prefix("Y: "));
+                            });
+                            ui.end_row();
+
+                            ui.label("Area Max:");
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut scatter.area.max.x).prefix("X: "));
+                                ui.add(egui::DragValue::new(&mut scatter.area.max.y).prefix("Y: "));
+                            });
+                            ui.end_row();
+
+                            ui.label("Scale Range:");
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::DragValue::new(&mut scatter.scale_range[0])
+                                        .range(0.1..=5.0)
+                                        .speed(0.01),
+                                );
+                                ui.add(
+                                    egui::DragValue::new(&mut scatter.scale_range[1])
+                                        .range(0.1..=5.0)
+                                        .speed(0.01),
+                                );
+                            });
+                            ui.end_row();
+
+                            ui.label("Flip Chance:");
+                            ui.add(egui::Slider::new(&mut scatter.flip_chance, 0.0..=1.0));
+                            ui.end_row();
+                        });
+                    if ui.button("Use Whole Canvas").clicked() {
+                        scatter.area =
+                            ScatterSettings::new(scatter.source_id, self.canvas_size).area;
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Scatter").clicked() {
+                            scatter_decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            scatter_decision = Some(false);
+                        }
+                    });
+                });
+        }
+        if let Some(apply) = scatter_decision {
+            if let Some(scatter) = self.scatter.take() {
+                if apply {
+                    self.apply_scatter(&scatter);
+                }
+            }
+        }
+
+        // Mirrored pairs found by the last import
+        let mut pairs_decision = None;
+        if !self.pending_pairs.is_empty() {
+            egui::Window::new("Mirrored Pairs")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("Flip the right part and mirror it about the canvas center:");
+                    for pair in &mut self.pending_pairs {
+                        ui.checkbox(&mut pair.accepted, &pair.stem);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() {
+                            pairs_decision = Some(true);
+                        }
+                        if ui.button("Dismiss").clicked() {
+                            pairs_decision = Some(false);
+                        }
+                    });
+                });
+        }
+        if let Some(apply) = pairs_decision {
+            let pairs = std::mem::take(&mut self.pending_pairs);
+            if apply {
+                self.mirror_pairs_apply(&pairs);
+            }
+        }
+
+        // Toast notices
+        let now = ctx.input(|i| i.time);
+        self.toasts.retain(|(_, expires)| now < *expires);
+        if let Some((_, next_expiry)) = self.toasts.front() {
+            egui::Area::new(egui::Id::new("toast"))
+                .anchor(egui::Align2::CENTER_BOTTOM, [0.0, -24.0])
+                .order(egui::Order::Tooltip)
+                .interactable(false)
+                .show(ctx, |ui| {
+                    for (text, _) in &self.toasts {
+                        egui::Frame::popup(ui.style()).show(ui, |ui| {
+                            ui.label(text.as_str());
+                        });
+                    }
+                });
+            ctx.request_repaint_after(std::time::Duration::from_secs_f64(next_expiry - now));
+        }
+
+        // Debug console
+        if self.debug_console {
+            let mut open = true;
+            egui::Window::new("Debug Console")
+                .open(&mut open)
+                .default_size([560.0, 320.0])
+                .show(ctx, |ui| {
+                    let entries = logging::recent();
+                    let shown: Vec<_> = entries
+                        .iter()
+                        .filter(|e| e.level <= self.debug_console_level)
+                        .collect();
+                    ui.horizontal(|ui| {
+                        egui::ComboBox::from_id_salt("debug_console_level")
+                            .selected_text(self.debug_console_level.to_string())
+                            .show_ui(ui, |ui| {
+                                for level in [
+                                    log::LevelFilter::Error,
+                                    log::LevelFilter::Warn,
+                                    log::LevelFilter::Info,
+                                    log::LevelFilter::Debug,
+                                    log::LevelFilter::Trace,
+                                ] {
+                                    ui.selectable_value(
+                                        &mut self.debug_console_level,
+                                        level,
+                                        level.to_string(),
+                                    );
+                                }
+                            });
+                        if ui.button("Copy Logs").clicked() {
+                            let text: Vec<_> = shown.iter().map(|e| e.format()).collect();
+                            ctx.copy_text(text.join("\n"));
+                        }
+                        if ui.button("Clear").clicked() {
+                            logging::clear();
+                        }
+                        if ui
+                            .button("Simulate Texture Loss")
+                            .on_hover_text("Drop every cached texture, as after a lost GPU context")
+                            .clicked()
+                        {
+                            GRAPHICS_CONTEXT_RESTORED.store(true, Ordering::Relaxed);
+                        }
+                        ui.small(format!("{} of {}", shown.len(), entries.len()));
+                    });
+                    ui.separator();
+                    egui::ScrollArea::both()
+                        .stick_to_bottom(true)
+                        .auto_shrink([false, false])
+                        .show(ui, |ui| {
+                            for entry in &shown {
+                                let color = match entry.level {
+                                    log::Level::Error => egui::Color32::RED,
+                                    log::Level::Warn => egui::Color32::YELLOW,
+                                    _ => ui.visuals().text_color(),
+                                };
+                                ui.label(
+                                    egui::RichText::new(entry.format()).monospace().color(color),
+                                );
+                            }
+                        });
+                });
+            self.debug_console = open;
+        }
+
+        // Export preview
+        if self.export_preview.is_some() {
+            self.update_export_preview(ctx);
+            let mut open = true;
+            let mut decision = None;
+            egui::Window::new("Export Preview")
+                .open(&mut open)
+                .collapsible(false)
+                .default_size([480.0, 520.0])
+                .show(ctx, |ui| {
+                    ui.horizontal_wrapped(|ui| {
+                        ui.add(
+                            egui::DragValue::new(&mut self.export_scale)
+                                .range(1..=MAX_EXPORT_SCALE)
+                                .prefix("Scale: ")
+                                .suffix("x"),
+                        );
+                        self.composite_format_ui(ui);
+                    });
+                    let [w, h] = self.canvas_size.map(|n| n * self.export_scale);
+                    let Some(preview) = &mut self.export_preview else {
+                        return;
+                    };
+                    ui.horizontal(|ui| {
+                        ui.label(format!(
+                            "{}×{} px, {}",
+                            w,
+                            h,
+                            format_bytes(preview.encoded_bytes)
+                        ));
+                        ui.add(
+                            egui::Slider::new(&mut preview.zoom, 0.05..=8.0)
+                                .logarithmic(true)
+                                .text("Zoom"),
+                        );
+                    });
+                    ui.separator();
+                    egui::ScrollArea::both()
+                        .max_height(ui.available_height() - 36.0)
+                        .show(ui, |ui| {
+                            let Some((_, texture)) = &preview.image else {
+                                ui.spinner();
+                                return;
+                            };
+                            let size = egui::vec2(w as f32, h as f32) * preview.zoom;
+                            let (rect, _) = ui.allocate_exact_size(size, egui::Sense::hover());
+                            let painter = ui.painter_at(rect);
+                            self.preview_bg.paint(
+                                &painter,
+                                rect,
+                                [w, h],
+                                preview.zoom,
+                                ui.clip_rect(),
+                            );
+                            painter.image(
+                                texture.id(),
+                                rect,
+                                egui::Rect::from_min_max(
+                                    egui::pos2(0.0, 0.0),
+                                    egui::pos2(1.0, 1.0),
+                                ),
+                                egui::Color32::WHITE,
+                            );
+                        });
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        if ui
+                            .add_enabled(self.export_job.is_none(), egui::Button::new("Confirm"))
+                            .clicked()
+                        {
+                            decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            decision = Some(false);
+                        }
+                    });
+                });
+            if decision == Some(true) {
+                self.download_composite();
+            }
+            if !open || decision.is_some() {
+                self.export_preview = None;
+            }
+        }
+
+        // Permutation export setup
+        if let Some(mut dialog) = self.permutation_dialog.take() {
+            let members: Vec<usize> = LABEL_COLORS
+                .iter()
+                .map(|(_, color)| {
+                    self.layers
+                        .iter()
+                        .filter(|l| !l.is_reference && l.label_color == Some(*color))
+                        .count()
+                })
+                .collect();
+            let mut open = true;
+            let mut export = false;
+            egui::Window::new("Permutation Export")
+                .open(&mut open)
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(
+                        "Each checked label is a slot; every combination shows one layer per slot.",
+                    );
+                    for (i, (name, color)) in LABEL_COLORS.iter().enumerate() {
+                        let text =
+                            egui::RichText::new(format!("● {} ({} layers)", name, members[i]))
+                                .color(*color);
+                        if ui
+                            .add_enabled(
+                                members[i] > 0,
+                                egui::Checkbox::new(&mut dialog.slots[i], text),
+                            )
+                            .changed()
+                        {
+                            dialog.confirm = false;
+                        }
+                    }
+                    let mut used = dialog
+                        .slots
+                        .iter()
+                        .zip(&members)
+                        .filter(|(checked, n)| **checked && **n > 0)
+                        .map(|(_, n)| *n)
+                        .peekable();
+                    let count: usize = if used.peek().is_some() {
+                        used.product()
+                    } else {
+                        0
+                    };
+                    ui.label(format!("{} combination(s)", count));
+                    if dialog.confirm {
+                        ui.colored_label(
+                            egui::Color32::YELLOW,
+                            format!("⚠ This composites {} images and may take a while.", count),
+                        );
+                        ui.horizontal(|ui| {
+                            export = ui.button("Export Anyway").clicked();
+                            if ui.button("Cancel").clicked() {
+                                dialog.confirm = false;
+                            }
+                        });
+                    } else if ui
+                        .add_enabled(
+                            count > 0 && self.export_job.is_none(),
+                            egui::Button::new("Export ZIP"),
+                        )
+                        .clicked()
+                    {
+                        if count > PERMUTATION_CONFIRM {
+                            dialog.confirm = true;
+                        } else {
+                            export = true;
+                        }
+                    }
+                });
+            if export {
+                let snapshot = self.export_snapshot();
+                let slots = snapshot.variant_slots(&dialog.slots);
+                log::info!("Exporting permutations of {} slot(s)", slots.len());
+                let filename = format!("{}_permutations.zip", self.export_stem());
+                self.start_export("Permutations", move |progress| async move {
+                    let bytes = snapshot.build_permutation_zip(&slots, &progress).await?;
+                    Ok(vec![(filename, bytes)])
+                });
+            } else if open {
+                self.permutation_dialog = Some(dialog);
+            }
+        }
+
+        // Oversized HTML viewer
+        if let Some(size) = self.pending_html {
+            let mut decision = None;
+            egui::Window::new("Large HTML Viewer")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "The page would be about {}; browsers may be slow to open it. \
+                         Lowering the export scale makes it smaller.",
+                        format_bytes(size)
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Save Anyway").clicked() {
+                            decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            decision = Some(false);
+                        }
+                    });
+                });
+            if let Some(save) = decision {
+                self.pending_html = None;
+                if save {
+                    self.save_html_viewer();
+                }
+            }
+        }
+
+        // Resize Canvas dialog
+        if let Some((mut size, mut anchor)) = self.resize_dialog {
+            let mut decision = None;
+            let mut open = true;
+            egui::Window::new("Resize Canvas")
+                .collapsible(false)
+                .resizable(false)
+                .open(&mut open)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "Current size: {} × {}",
+                        self.canvas_size[0], self.canvas_size[1]
+                    ));
+                    ui.horizontal(|ui| {
+                        ui.label("W:");
+                        ui.add(
+                            egui::DragValue::new(&mut size[0])
+                                .range(MIN_CANVAS_SIZE..=MAX_CANVAS_SIZE),
+                        );
+                        ui.label("H:");
+                        ui.add(
+                            egui::DragValue::new(&mut size[1])
+                                .range(MIN_CANVAS_SIZE..=MAX_CANVAS_SIZE),
+                        );
+                    });
+                    ui.horizontal(|ui| {
+                        ui.label("Anchor:");
+                        egui::Grid::new("resize_anchor")
+                            .spacing([2.0, 2.0])
+                            .show(ui, |ui| {
+                                for y in 0..3 {
+                                    for x in 0..3 {
+                                        let cell = egui::vec2(x as f32 * 0.5, y as f32 * 0.5);
+                                        let selected = anchor == cell;
+                                        let icon = if selected { "●" } else { "○" };
+                                        if ui.selectable_label(selected, icon).clicked() {
+                                            anchor = cell;
+                                        }
+                                    }
+                                    ui.end_row();
+                                }
+                            });
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Resize").clicked() {
+                            decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            decision = Some(false);
+                        }
+                    });
+                });
+            self.resize_dialog = Some((size, anchor));
+            if decision == Some(true) && self.can_edit() {
+                self.resize_canvas(size, anchor);
+            }
+            if !open || decision.is_some() {
+                self.resize_dialog = None;
+            }
+        }
+
+        // Project notes
+        if self.notes_open {
+            let can_edit = self.can_edit();
+            egui::Window::new("Project Notes")
+                .open(&mut self.notes_open)
+                .default_size([360.0, 240.0])
+                .show(ctx, |ui| {
+                    ui.horizontal(|ui| {
+                        ui.add_enabled_ui(can_edit, |ui| {
+                            ui.toggle_value(&mut self.notes_editing, "✏ Edit");
+                            ui.checkbox(&mut self.notes.show_on_open, "Show on Open");
+                        });
+                        ui.small(format!(
+                            "{} / {}",
+                            format_bytes(self.notes.text.len()),
+                            format_bytes(ProjectNotes::MAX_BYTES)
+                        ));
+                    });
+                    ui.separator();
+                    egui::ScrollArea::vertical().show(ui, |ui| {
+                        if self.notes_editing && can_edit {
+                            ui.add(
+                                egui::TextEdit::multiline(&mut self.notes.text)
+                                    .hint_text("Notes for everyone working on this project")
+                                    .desired_width(f32::INFINITY),
+                            );
+                            self.notes.enforce_limit();
+                        } else if self.notes.text.is_empty() {
+                            ui.weak("No notes yet.");
+                        } else {
+                            self.notes.show_linked(ui);
+                        }
+                    });
+                });
+        }
+
+        // Move to Position dialog
+        if let Some((id, mut target)) = self.move_dialog {
+            let current = self.layers.iter().position(|l| l.id == id);
+            let mut decision = None;
+            let mut open = current.is_some();
+            if let Some(current) = current {
+                let count = self.layers.len();
+                egui::Window::new("Move to Position")
+                    .collapsible(false)
+                    .resizable(false)
+                    .open(&mut open)
+                    .show(ctx, |ui| {
+                        ui.label(format!(
+                            "{} is at position {} of {}",
+                            self.layers[current].name,
+                            current + 1,
+                            count
+                        ));
+                        ui.horizontal(|ui| {
+                            ui.label("Move to:");
+                            let mut position = target + 1;
+                            ui.add(egui::DragValue::new(&mut position).range(1..=count));
+                            target = position - 1;
+                            if ui.button("Top").clicked() {
+                                target = 0;
+                            }
+                            if ui.button("Bottom").clicked() {
+                                target = count - 1;
+                            }
+                        });
+                        ui.horizontal(|ui| {
+                            if ui.button("Move").clicked() {
+                                decision = Some(true);
+                            }
+                            if ui.button("Cancel").clicked() {
+                                decision = Some(false);
+                            }
+                        });
+                    });
+            }
+            self.move_dialog = Some((id, target));
+            if decision == Some(true) && self.can_edit() {
+                self.move_layer_to_index(id, target);
+            }
+            if !open || decision.is_some() {
+                self.move_dialog = None;
+            }
+        }
+
+        // Leaving viewer mode
+        if self.confirm_unlock {
+            let mut decision = None;
+            egui::Window::new("Unlock for Editing?")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label("This project was shared read-only. Make it editable?");
+                    ui.horizontal(|ui| {
+                        if ui.button("Unlock").clicked() {
+                            decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            decision = Some(false);
+                        }
+                    });
+                });
+            if let Some(unlock) = decision {
+                self.confirm_unlock = false;
+                if unlock {
+                    self.viewer_mode = false;
+                }
+            }
+        }
+
+        if let Some(index) = self.confirm_close_document {
+            let mut decision = None;
+            let name = self.documents.get(index).map_or("", |d| d.name.as_str());
+            egui::Window::new("Close Document?")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!(
+                        "Close \"{}\"? Its layers and undo history are discarded.",
+                        name
+                    ));
+                    ui.horizontal(|ui| {
+                        if ui.button("Close").clicked() {
+                            decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            decision = Some(false);
+                        }
+                    });
+                });
+            if let Some(close) = decision {
+                self.confirm_close_document = None;
+                if close {
+                    self.close_document(index);
+                }
+            }
+        }
+
+        // Canvas growth offered after dragging past the edge
+        let mut growth_decision = None;
+        if let Some(growth) = self.pending_growth {
+            let dragging = ctx.input(|i| i.pointer.any_down());
+            egui::Window::new("Grow Canvas?")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    ui.label(format!("Add {}?", growth.describe()));
+                    ui.horizontal(|ui| {
+                        ui.add_enabled_ui(!dragging, |ui| {
+                            if ui.button("Grow").clicked() {
+                                growth_decision = Some(true);
+                            }
+                            if ui.button("Dismiss").clicked() {
+                                growth_decision = Some(false);
+                            }
+                        });
+                    });
+                });
+        }
+        if let Some(apply) = growth_decision {
+            if let Some(growth) = self.pending_growth.take() {
+                if apply {
+                    self.grow_canvas(growth);
+                    self.amend_last_step();
+                }
+            }
+        }
+
+        // Settings profile import confirmation
+        let mut profile_decision = None;
+        if let Some(pending) = &self.pending_profile {
+            egui::Window::new("Import Settings Profile")
+                .collapsible(false)
+                .resizable(false)
+                .show(ctx, |ui| {
+                    if pending.changes.is_empty() {
+                        ui.label("No settings would change.");
+                    }
+                    for change in &pending.changes {
+                        ui.label(change);
+                    }
+                    for warning in &pending.warnings {
+                        ui.colored_label(egui::Color32::YELLOW, warning);
+                    }
+                    ui.horizontal(|ui| {
+                        if ui.button("Apply").clicked() {
+                            profile_decision = Some(true);
+                        }
+                        if ui.button("Cancel").clicked() {
+                            profile_decision = Some(false);
+                        }
+                    });
+                });
+        }
+        if let Some(apply) = profile_decision {
+            if let Some(pending) = self.pending_profile.take() {
+                if apply {
+                    self.apply_profile(pending.profile);
+                }
+            }
+        }
+
+        // Render UI Panels
+        if is_mobile {
+            egui::TopBottomPanel::bottom("bottom_panel")
+                .resizable(true)
+                .default_height(300.0)
+                .show(ctx, |ui| control_panel_ui(ui, self));
+        } else {
+            egui::SidePanel::right("right_panel")
+                .resizable(true)
+                .default_width(300.0)
+                .show(ctx, |ui| control_panel_ui(ui, self));
+        }
+
+        // Central Canvas Area
+        egui::CentralPanel::default().show(ctx, |ui| self.canvas_ui(ctx, ui));
+
+        self.finish_frame_history(ctx);
+    }
+
+    /// Tab bar of open documents: click to switch, double-click to rename
+    fn document_tabs_ui(&mut self, ui: &mut egui::Ui) {
+        let can_switch = self.can_switch_document();
+        let mut switch_to = None;
+        let mut close = None;
+        let mut rename = None;
+        let mut create = false;
+        ui.horizontal_wrapped(|ui| {
+            for (i, document) in self.documents.iter().enumerate() {
+                match &mut self.renaming_document {
+                    Some((index, text)) if *index == i => {
+                        let edit = ui.add(egui::TextEdit::singleline(text).desired_width(100.0));
+                        edit.request_focus();
+                        if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                            self.renaming_document = None;
+                        } else if edit.lost_focus() {
+                            let name = text.trim().to_owned();
+                            if !name.is_empty() {
+                                rename = Some((i, name));
+                            }
+                            self.renaming_document = None;
+                        }
+                    }
+                    _ => {
+                        let active = i == self.active_document;
+                        let tab = ui.add_enabled(
+                            active || can_switch,
+                            egui::SelectableLabel::new(active, &document.name),
+                        );
+                        if tab.clicked() {
+                            switch_to = Some(i);
+                        }
+                        if tab.double_clicked() {
+                            self.renaming_document = Some((i, document.name.clone()));
+                        }
+                    }
+                }
+                if self.documents.len() > 1 && ui.small_button("×").clicked() {
+                    close = Some(i);
+                }
+                ui.separator();
+            }
+            if ui
+                .add_enabled(can_switch, egui::Button::new("+"))
+                .on_hover_text("New document")
+                .clicked()
+            {
+                create = true;
+            }
+        });
+        if let Some((i, name)) = rename {
+            self.documents[i].name = name;
+        }
+        if let Some(i) = switch_to {
+            self.switch_document(i);
+        }
+        if create {
+            self.new_document();
+        }
+        if close.is_some() {
+            self.confirm_close_document = close;
+        }
+    }
+
+    /// Canvas view: layers, overlays and canvas interaction
+    fn canvas_ui(&mut self, ctx: &egui::Context, ui: &mut egui::Ui) {
+        self.document_tabs_ui(ui);
+        ui.horizontal(|ui| {
+            for zoom in ZOOM_PRESETS {
+                let selected = !self.zoom_fit && self.preview_zoom == zoom;
+                if ui
+                    .selectable_label(selected, format!("{}×", zoom))
+                    .clicked()
+                {
+                    self.set_zoom(zoom);
+                }
+            }
+            let fit_hint = format_shortcut(&egui::gui_zoom::kb_shortcuts::ZOOM_RESET);
+            if ui
+                .selectable_label(self.zoom_fit, "Fit")
+                .on_hover_text(fit_hint)
+                .clicked()
+            {
+                self.zoom_fit = true;
+            }
+            ui.label(format!("{:.0}%", self.preview_zoom * 100.0));
+            ui.separator();
+            ui.toggle_value(&mut self.eyedropper, "💧 Eyedropper")
+                .on_hover_text("Click the canvas to copy the output color there");
+            ui.add_enabled_ui(self.can_edit(), |ui| {
+                ui.toggle_value(&mut self.placing_marker, "📍 Marker")
+                    .on_hover_text("Click the canvas to add a named attachment point");
+            });
+        });
+        let available_rect = ui.available_rect_before_wrap();
+        let painter = ui.painter_at(available_rect);
+
+        // Handle Canvas Panning (Middle Mouse or Alt+Drag or Space+Drag logic)
+        // Or just drag on empty space.
+        let input = ui.input(|i| i.clone());
+
+        // Allow panning if middle mouse is dragging, or dragging while Space is held
+        let hovered = input
+            .pointer
+            .hover_pos()
+            .filter(|pos| available_rect.contains(*pos));
+        let space_pan = input.key_down(egui::Key::Space) && !ctx.wants_keyboard_input();
+        if input.pointer.button_down(egui::PointerButton::Middle)
+            || (space_pan && input.pointer.primary_down())
+        {
+            self.canvas_pan += input.pointer.delta();
+            self.zoom_fit &= input.pointer.delta() == egui::Vec2::ZERO;
+        }
+        if space_pan && hovered.is_some() {
+            ctx.set_cursor_icon(if input.pointer.primary_down() {
+                egui::CursorIcon::Grabbing
+            } else {
+                egui::CursorIcon::Grab
+            });
+        }
+
+        // Ctrl+scroll and pinch zoom around the pointer: the canvas point under it stays put
+        if let Some(pointer) = hovered {
+            let zoom = (self.preview_zoom * input.zoom_delta()).clamp(MIN_ZOOM, MAX_ZOOM);
+            if zoom != self.preview_zoom {
+                self.zoom_fit = false;
+                let from_center = pointer - (available_rect.center() + self.canvas_pan);
+                self.canvas_pan =
+                    pointer - available_rect.center() - from_center * (zoom / self.preview_zoom);
+                self.preview_zoom = zoom;
+            }
+        }
+
+        // Fit follows the view size, so panel resizes and layout switches refit
+        if self.zoom_fit {
+            let room = available_rect.size() - egui::Vec2::splat(2.0 * FIT_MARGIN);
+            let fit_x = room.x / self.canvas_size[0] as f32;
+            let fit_y = room.y / self.canvas_size[1] as f32;
+            self.preview_zoom = fit_x.min(fit_y).clamp(MIN_ZOOM, MAX_ZOOM);
+            self.canvas_pan = egui::Vec2::ZERO;
+        }
+
+        // Calculate Canvas Rect (Centered + Pan)
+        let canvas_w = self.canvas_size[0] as f32 * self.preview_zoom;
+        let canvas_h = self.canvas_size[1] as f32 * self.preview_zoom;
+
+        let center = available_rect.center() + self.canvas_pan;
+        let canvas_rect = egui::Rect::from_center_size(center, egui::vec2(canvas_w, canvas_h));
+
+        // Draw Background (Checkerboard)
+        self.preview_bg.paint(
+            &painter,
+            canvas_rect,
+            self.canvas_size,
+            self.preview_zoom,
+            available_rect,
+        );
+
+        if self.bg_color != egui::Color32::TRANSPARENT {
+            painter.rect_filled(canvas_rect, 0.0, self.bg_color);
+        }
+
+        // Overlays (selection, guides, borders) are skipped in clean view
+        let overlays = !self.clean_view_active(ctx);
+        let can_edit = self.can_edit();
+
+        // Output effects preview: one debounced composite texture replaces the layer meshes
+        let composite_preview = self.output_effects.preview && self.output_effects.is_active();
+        if composite_preview {
+            self.update_composite_preview(ctx);
+            if let Some((_, tex)) = &self.composite_preview {
+                painter.image(
+                    tex.id(),
+                    canvas_rect,
+                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
+                    egui::Color32::WHITE,
+                );
+            }
+        } else {
+            self.composite_preview = None;
+        }
+        let draw_meshes = self.composite_preview.is_none();
+
+        // Draw Layers
+        let mut drag_delta = egui::Vec2::ZERO;
+        let mut dragged_id = None;
+        let mut canvas_click = None;
+        let mut hits = Vec::new(); // Hit-testable layer rects, bottom to top
+
+        // Clipping masks are approximated in the preview by the base layer's rect
+        let order = draw_order(&self.layers);
+        let clip_rects: Vec<Option<Option<egui::Rect>>> = clip_bases(&self.layers, &order)
+            .into_iter()
+            .map(|base| {
+                base.map(|b| {
+                    let base = &self.layers[b];
+                    base.visible.then(|| {
+                        egui::Rect::from_min_size(
+                            canvas_rect.min + base.top_left() * self.preview_zoom,
+                            base.scaled_size() * self.preview_zoom,
+                        )
+                    })
+                })
+            })
+            .collect();
+
+        let mirror_axis = self.mirror_axis_x();
+        for (pos, idx) in order.into_iter().enumerate() {
+            let layer = &mut self.layers[idx];
+            // Clipped layers whose base is hidden aren't drawn at all
+            let hidden_reference = layer.is_reference && self.hide_references;
+            if !layer.visible || hidden_reference || clip_rects[pos] == Some(None) {
+                continue;
+            }
+
+            // Canvas effects are previewed from fully rendered 1:1 pixels, anything else
+            // from the source texture scaled by the mesh
+            let canvas_effects = layer.has_canvas_effects();
+
+            // Minified nearest sampling shimmers, so smooth layers shown below 1:1 (view only)
+            let screen_scale = if canvas_effects {
+                self.preview_zoom
+            } else {
+                layer.transform.scale_x.min(layer.transform.scale_y) * self.preview_zoom
+            };
+            let texture_options = if self.smooth_preview && screen_scale < 1.0 {
+                egui::TextureOptions::LINEAR
+            } else {
+                egui::TextureOptions::NEAREST
+            };
+
+            // Re-upload only when the baked effects or sampling changed since the last upload
+            let effects_key = layer.effects_key();
+            let texture_id = match &layer.texture {
+                Some(tex)
+                    if layer.texture_key == effects_key
+                        && layer.texture_options == texture_options =>
+                {
+                    tex.id()
+                }
+                _ => {
+                    log::debug!("Rebuilding preview texture for {}", layer.name);
+                    let pixels = layer.preview_pixels();
+                    let tex = ctx.load_texture(
+                        &layer.name,
+                        egui::ColorImage::from_rgba_unmultiplied(
+                            [pixels.width() as _, pixels.height() as _],
+                            pixels.as_flat_samples().as_slice(),
+                        ),
+                        texture_options,
+                    );
+                    let id = tex.id();
+                    layer.texture = Some(tex);
+                    layer.texture_key = effects_key;
+                    layer.texture_options = texture_options;
+                    id
+                }
+            };
+
+            let top_left = layer.top_left();
+            let aligned_pos = egui::pos2(top_left.x.round(), top_left.y.round());
+            let part_screen_pos = canvas_rect.min + (aligned_pos.to_vec2() * self.preview_zoom);
+            let part_w =
+                layer.source_image.width() as f32 * layer.transform.scale_x * self.preview_zoom;
+            let part_h =
+                layer.source_image.height() as f32 * layer.transform.scale_y * self.preview_zoom;
+
+            let part_rect = egui::Rect::from_min_size(part_screen_pos, egui::vec2(part_w, part_h));
+
+            // Locked layers are not hit-tested so input reaches layers below
+            let picking =
+                self.placing.is_some() || self.placing_marker || self.eyedropper || space_pan;
+            if can_edit && !layer.locked && !picking {
+                hits.push((layer.id, part_rect));
+            }
+
+            if overlays && self.selection.contains(&layer.id) {
+                painter.rect_stroke(
+                    part_rect,
+                    0.0,
+                    egui::Stroke::new(2.0, egui::Color32::YELLOW),
+                );
+            }
+
+            let (mesh_rect, flip, vertex_color) = match &layer.texture {
+                Some(tex) if canvas_effects => {
+                    // Flip and tint are already baked into the rendered pixels
+                    let [ox, oy] = layer.effect_origin(1);
+                    let [w, h] = tex.size();
+                    let rect = egui::Rect::from_min_size(
+                        part_rect.min - egui::vec2(ox as f32, oy as f32) * self.preview_zoom,
+                        egui::vec2(w as f32, h as f32) * self.preview_zoom,
+                    );
+                    (rect, false, egui::Color32::WHITE)
+                }
+                _ => (part_rect, layer.transform.flip_h, layer.tint),
+            };
+            let vertex_color = if layer.is_reference {
+                vertex_color.gamma_multiply(REFERENCE_OPACITY)
+            } else {
+                vertex_color
+            };
+            let (u0, u1) = if flip { (1.0, 0.0) } else { (0.0, 1.0) };
+            let mut mesh = egui::Mesh::with_texture(texture_id);
+            mesh.add_rect_with_uv(
+                mesh_rect,
+                egui::Rect::from_min_max(egui::pos2(u0, 0.0), egui::pos2(u1, 1.0)),
+                vertex_color,
+            );
+            if overlays && layer.is_reference {
+                let r = part_rect;
+                painter.extend(egui::Shape::dashed_line(
+                    &[
+                        r.left_top(),
+                        r.right_top(),
+                        r.right_bottom(),
+                        r.left_bottom(),
+                        r.left_top(),
+                    ],
+                    egui::Stroke::new(1.0, egui::Color32::LIGHT_GRAY),
+                    4.0,
+                    3.0,
+                ));
+            }
+            // References aren't in the composite preview, so they keep their mesh
+            if draw_meshes || layer.is_reference {
+                let clip = match clip_rects[pos] {
+                    Some(Some(clip)) => clip.intersect(painter.clip_rect()),
+                    _ => painter.clip_rect(),
+                };
+                if self.show_overflow {
+                    let mut dimmed = mesh.clone();
+                    for vertex in &mut dimmed.vertices {
+                        vertex.color = vertex.color.gamma_multiply(OVERFLOW_OPACITY);
+                    }
+                    for band in overflow_bands(clip, canvas_rect) {
+                        if band.is_positive() {
+                            painter.with_clip_rect(band).add(dimmed.clone());
+                        }
+                    }
+                }
+                if self.mirror_preview && !layer.is_reference {
+                    // Mirror across the axis on screen and swap the U coordinates
+                    let axis = canvas_rect.min.x + mirror_axis * self.preview_zoom;
+                    let mut ghost = egui::Mesh::with_texture(texture_id);
+                    ghost.add_rect_with_uv(
+                        egui::Rect::from_x_y_ranges(
+                            2.0 * axis - mesh_rect.max.x..=2.0 * axis - mesh_rect.min.x,
+                            mesh_rect.y_range(),
+                        ),
+                        egui::Rect::from_min_max(egui::pos2(u1, 0.0), egui::pos2(u0, 1.0)),
+                        vertex_color.gamma_multiply(MIRROR_GHOST_OPACITY),
+                    );
+                    painter.with_clip_rect(canvas_rect).add(ghost);
+                }
+                painter
+                    .with_clip_rect(clip.intersect(canvas_rect))
+                    .add(mesh);
+            }
+            if overlays && !part_rect.intersects(canvas_rect) {
+                draw_offscreen_arrow(&painter, canvas_rect, part_rect.center());
+            }
+        }
+
+        // Interaction: one hit test over every layer under the pointer, so overlapped
+        // layers stay reachable. Clicks cycle down the stack, drags keep the selection.
+        if !hits.is_empty() {
+            let response = ui.interact(
+                available_rect,
+                ui.id().with("canvas_layers"),
+                egui::Sense::click_and_drag(),
+            );
+            // Where the press began (while held), so a drag picks what was under it at first
+            let pointer = ui
+                .input(|i| i.pointer.press_origin())
+                .or(response.interact_pointer_pos());
+            let mut under: Vec<u64> = pointer
+                .map(|pointer| {
+                    hits.iter()
+                        .rev()
+                        .filter(|(_, rect)| rect.contains(pointer))
+                        .map(|(id, _)| *id)
+                        .collect()
+                })
+                .unwrap_or_default();
+            // Prefer layers with visible pixels there; all-transparent falls back to rects
+            if let (true, Some(pointer)) = (self.pick_by_pixel, pointer) {
+                const PICK_ALPHA: u8 = 8;
+                let point = (pointer - canvas_rect.min) / self.preview_zoom;
+                let opaque: Vec<u64> = under
+                    .iter()
+                    .copied()
+                    .filter(|&id| {
+                        self.layers
+                            .iter()
+                            .find(|l| l.id == id)
+                            .is_some_and(|l| l.alpha_at(point) > PICK_ALPHA)
+                    })
+                    .collect();
+                if !opaque.is_empty() {
+                    under = opaque;
+                }
+            }
+
+            if response.drag_started() {
+                let selected_under = self
+                    .selected_layer_id
+                    .into_iter()
+                    .chain(self.selected_ids())
+                    .find(|id| under.contains(id));
+                self.canvas_drag_target = selected_under.or(under.first().copied());
+                if selected_under.is_none() {
+                    if let Some(&top) = under.first() {
+                        canvas_click = Some((top, egui::Modifiers::NONE));
+                    }
+                }
+            }
+            if response.dragged() {
+                dragged_id = self.canvas_drag_target;
+                drag_delta = response.drag_delta() / self.preview_zoom;
+            } else {
+                self.canvas_drag_target = None;
+            }
+            if response.clicked() && !under.is_empty() {
+                let modifiers = ui.input(|i| i.modifiers);
+                let next = match self
+                    .selected_layer_id
+                    .and_then(|id| under.iter().position(|&u| u == id))
+                {
+                    // Plain clicks on the selected layer move one down the stack, wrapping
+                    Some(pos) if modifiers.is_none() => under[(pos + 1) % under.len()],
+                    _ => under[0],
+                };
+                canvas_click = Some((next, modifiers));
+            }
+        }
+
+        if overlays && self.mirror_preview {
+            let x = canvas_rect.min.x + mirror_axis * self.preview_zoom;
+            painter.extend(egui::Shape::dashed_line(
+                &[
+                    egui::pos2(x, canvas_rect.min.y),
+                    egui::pos2(x, canvas_rect.max.y),
+                ],
+                egui::Stroke::new(1.0, egui::Color32::from_rgb(255, 0, 255)),
+                6.0,
+                4.0,
+            ));
+        }
+
+        // Guides sit above the layers: drag to move, drop outside the canvas to delete
+        if overlays {
+            let mut remove = None;
+            for (i, guide) in self.guides.iter_mut().enumerate() {
+                let axis = if guide.vertical { 0 } else { 1 };
+                let at = canvas_rect.min[axis] + guide.position * self.preview_zoom;
+                let (line, cursor) = if guide.vertical {
+                    let line = [
+                        egui::pos2(at, canvas_rect.min.y),
+                        egui::pos2(at, canvas_rect.max.y),
+                    ];
+                    (line, egui::CursorIcon::ResizeHorizontal)
+                } else {
+                    let line = [
+                        egui::pos2(canvas_rect.min.x, at),
+                        egui::pos2(canvas_rect.max.x, at),
+                    ];
+                    (line, egui::CursorIcon::ResizeVertical)
+                };
+                let mut color = egui::Color32::from_rgb(0, 255, 255);
+                if can_edit && !space_pan {
+                    let grab = egui::Rect::from_two_pos(line[0], line[1]).expand(3.0);
+                    let response =
+                        ui.interact(grab, ui.id().with(("guide", i)), egui::Sense::drag());
+                    if response.hovered() || response.dragged() {
+                        ui.ctx().set_cursor_icon(cursor);
+                    }
+                    if let (true, Some(pointer)) =
+                        (response.dragged(), response.interact_pointer_pos())
+                    {
+                        let position = (pointer[axis] - canvas_rect.min[axis]) / self.preview_zoom;
+                        guide.position = position.round();
+                    }
+                    if !(0.0..=self.canvas_size[axis] as f32).contains(&guide.position) {
+                        color = egui::Color32::RED;
+                        if response.drag_stopped() {
+                            remove = Some(i);
+                        }
+                    }
+                }
+                painter.line_segment(line, egui::Stroke::new(1.0, color));
+            }
+            if let Some(i) = remove {
+                self.guides.remove(i);
+            }
+        }
+
+        // Markers: crosshairs with their names, draggable like guides
+        if overlays {
+            let color = egui::Color32::from_rgb(255, 140, 0);
+            for (i, marker) in self.markers.iter_mut().enumerate() {
+                let at = canvas_rect.min + marker.canvas_position(&self.layers) * self.preview_zoom;
+                if can_edit && !space_pan {
+                    let grab = egui::Rect::from_center_size(at, egui::Vec2::splat(12.0));
+                    let response =
+                        ui.interact(grab, ui.id().with(("marker", i)), egui::Sense::drag());
+                    if response.hovered() || response.dragged() {
+                        ui.ctx().set_cursor_icon(egui::CursorIcon::Move);
+                    }
+                    if let (true, Some(pointer)) =
+                        (response.dragged(), response.interact_pointer_pos())
+                    {
+                        let canvas = ((pointer - canvas_rect.min) / self.preview_zoom).round();
+                        marker.set_canvas_position(canvas, &self.layers);
+                    }
+                }
+                let stroke = egui::Stroke::new(1.0, color);
+                painter.line_segment(
+                    [at - egui::vec2(6.0, 0.0), at + egui::vec2(6.0, 0.0)],
+                    stroke,
+                );
+                painter.line_segment(
+                    [at - egui::vec2(0.0, 6.0), at + egui::vec2(0.0, 6.0)],
+                    stroke,
+                );
+                painter.circle_stroke(at, 3.0, stroke);
+                painter.text(
+                    at + egui::vec2(6.0, -6.0),
+                    egui::Align2::LEFT_BOTTOM,
+                    &marker.name,
+                    egui::FontId::proportional(11.0),
+                    color,
+                );
+            }
+        }
+
+        // Scatter preview: ghost rects of the pending placements
+        if let Some(scatter) = self.scatter.as_ref().filter(|_| overlays) {
+            if let Some(source) = self.layers.iter().find(|l| l.id == scatter.source_id) {
+                let to_screen = |p: egui::Vec2| canvas_rect.min + p * self.preview_zoom;
+                painter.rect_stroke(
+                    egui::Rect::from_min_max(
+                        to_screen(scatter.area.min.to_vec2()),
+                        to_screen(scatter.area.max.to_vec2()),
+                    ),
+                    0.0,
+                    egui::Stroke::new(1.0, egui::Color32::LIGHT_BLUE),
+                );
+                for placement in scatter.placements(source) {
+                    let mut ghost = source.clone();
+                    placement.apply(&mut ghost);
+                    let size = ghost.source_size()
+                        * egui::vec2(ghost.transform.scale_x, ghost.transform.scale_y);
+                    let min = to_screen(ghost.top_left());
+                    painter.rect_stroke(
+                        egui::Rect::from_min_size(min, size * self.preview_zoom),
+                        0.0,
+                        egui::Stroke::new(
+                            1.0,
+                            egui::Color32::from_rgba_unmultiplied(255, 255, 255, 120),
+                        ),
+                    );
+                }
+            }
+        }
+
+        if let Some((id, modifiers)) = canvas_click {
+            self.click_select(id, modifiers);
+        }
+
+        // "Place at next click": the click sets where the layer's pivot (anchor) goes
+        if let Some(id) = self.placing {
+            let background = ui.interact(
+                available_rect,
+                ui.id().with("place_at_click"),
+                egui::Sense::click(),
+            );
+            if input.key_pressed(egui::Key::Escape) || !can_edit {
+                self.placing = None;
+            } else if let Some(pointer) = background.hover_pos() {
+                ui.ctx().set_cursor_icon(egui::CursorIcon::Crosshair);
+                let mut target = (pointer - canvas_rect.min) / self.preview_zoom;
+                match self.snap_grid {
+                    Some(grid) if !input.modifiers.command => {
+                        target = snap_to_grid(target, grid);
+                    }
+                    _ => target = target.round(),
+                }
+                painter.text(
+                    pointer + egui::vec2(14.0, 14.0),
+                    egui::Align2::LEFT_TOP,
+                    format!("Place at {}, {}", target.x, target.y),
+                    egui::FontId::monospace(12.0),
+                    egui::Color32::WHITE,
+                );
+                if background.clicked() {
+                    self.begin_transaction("Place layer");
+                    let target_layer = self.layers.iter_mut().find(|l| l.id == id && !l.locked);
+                    if let Some(layer) = target_layer {
+                        let pivot = layer.transform.anchor * layer.scaled_size();
+                        layer.set_top_left(target - pivot);
+                        if let Some(parity) = self.offset_parity {
+                            layer.transform.offset = parity.snap_vec(layer.transform.offset);
+                        }
+                    }
+                    self.commit_transaction();
+                    self.placing = None;
+                }
+            }
+        }
+
+        // One undo step per canvas drag, open from the first moved frame until release
+        let dragging = self
+            .history
+            .transaction
+            .as_ref()
+            .is_some_and(|t| t.spans_frames);
+        if dragged_id.is_some() && !dragging {
+            self.pending_growth = None;
+            self.begin_long_transaction("Move layer");
+        } else if dragged_id.is_none() && dragging {
+            self.commit_transaction();
+        }
+
+        if let Some(id) = dragged_id {
+            let bypass_snap = ui.input(|i| i.modifiers.command);
+            let mut moved = egui::Vec2::ZERO;
+            if let Some(layer) = self.layers.iter_mut().find(|l| l.id == id) {
+                // Track the unsnapped position so slow drags still cross grid lines
+                let raw = match self.drag_raw_offset {
+                    Some((raw_id, raw)) if raw_id == id => raw,
+                    _ => layer.transform.offset,
+                } + drag_delta;
+                self.drag_raw_offset = Some((id, raw));
+                let before = layer.transform.offset;
+                layer.transform.offset = match self.snap_grid {
+                    Some(grid) if !bypass_snap => snap_to_grid(raw, grid),
+                    _ => raw,
+                };
+                if self.snap_guides && !bypass_snap {
+                    let rect =
+                        egui::Rect::from_min_size(layer.top_left().to_pos2(), layer.scaled_size());
+                    let threshold = GUIDE_SNAP_PX / self.preview_zoom;
+                    layer.transform.offset += guide_snap(rect, &self.guides, threshold);
+                }
+                if let Some(parity) = self.offset_parity {
+                    layer.transform.offset = parity.snap_vec(layer.transform.offset);
+                }
+                moved = layer.transform.offset - before;
+            }
+            // The rest of the selection follows the (snapped) dragged layer
+            for layer in &mut self.layers {
+                if layer.id != id && !layer.locked && self.selection.contains(&layer.id) {
+                    layer.transform.offset += moved;
+                }
+            }
+
+            // Part of the drag's undo step, whether applied now or accepted later
+            if self.auto_expand.enabled {
+                let rect = self
+                    .layers
+                    .iter()
+                    .filter(|l| l.id == id || self.selection.contains(&l.id))
+                    .map(|l| egui::Rect::from_min_size(l.top_left().to_pos2(), l.scaled_size()))
+                    .reduce(|a, b| a.union(b));
+                if let Some(rect) = rect {
+                    let growth = self.needed_growth(rect);
+                    if !self.auto_expand.auto_apply {
+                        self.pending_growth = (!growth.is_empty()).then_some(growth);
+                    } else if !growth.is_empty() {
+                        self.grow_canvas(growth);
+                        self.drag_raw_offset = None;
+                    }
+                }
+            }
+        } else {
+            self.drag_raw_offset = None;
+        }
+
+        // Output pixel under the pointer, read from the cached composite
+        let pixel = hovered
+            .map(|pos| ((pos - canvas_rect.min) / self.preview_zoom).floor())
+            .filter(|p| p.x >= 0.0 && p.y >= 0.0)
+            .map(|p| [p.x as u32, p.y as u32])
+            .filter(|&[x, y]| x < self.canvas_size[0] && y < self.canvas_size[1]);
+        let color = pixel.and_then(|[x, y]| {
+            let img = self.composite_sample(ctx)?;
+            img.get_pixel_checked(x, y).map(|p| p.0)
+        });
+        let hex = color.map(|[r, g, b, a]| format!("#{:02x}{:02x}{:02x}{:02x}", r, g, b, a));
+
+        if self.eyedropper {
+            let picker = ui.interact(
+                available_rect,
+                ui.id().with("eyedropper"),
+                egui::Sense::click(),
+            );
+            if picker.hovered() {
+                ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+            }
+            if input.key_pressed(egui::Key::Escape) {
+                self.eyedropper = false;
+            } else if let (true, Some(hex)) = (picker.clicked(), &hex) {
+                ctx.copy_text(hex.clone());
+                self.show_toast(ctx, format!("Copied {}", hex));
+                self.eyedropper = false;
+            }
+        }
+
+        if self.placing_marker {
+            let placer = ui.interact(
+                available_rect,
+                ui.id().with("place_marker"),
+                egui::Sense::click(),
+            );
+            if placer.hovered() {
+                ctx.set_cursor_icon(egui::CursorIcon::Crosshair);
+            }
+            if input.key_pressed(egui::Key::Escape) || !can_edit {
+                self.placing_marker = false;
+            } else if let (true, Some(pointer)) = (placer.clicked(), placer.interact_pointer_pos())
+            {
+                let position = ((pointer - canvas_rect.min) / self.preview_zoom).round();
+                let name = (1..)
+                    .map(|n| format!("point_{}", n))
+                    .find(|name| self.markers.iter().all(|m| &m.name != name))
+                    .unwrap_or_default();
+                self.markers.push(Marker {
+                    name,
+                    position,
+                    layer: None,
+                });
+                self.placing_marker = false;
+            }
+        }
+
+        if let (true, Some([x, y])) = (overlays, pixel) {
+            let text = match &hex {
+                Some(hex) => format!("{}, {}  {}", x, y, hex),
+                None => format!("{}, {}", x, y),
+            };
+            let galley =
+                painter.layout_no_wrap(text, egui::FontId::monospace(12.0), egui::Color32::WHITE);
+            let pos = available_rect.left_bottom() + egui::vec2(8.0, -8.0 - galley.size().y);
+            let back = egui::Rect::from_min_size(pos, galley.size()).expand(4.0);
+            painter.rect_filled(back, 2.0, egui::Color32::from_black_alpha(160));
+            painter.galley(pos, galley, egui::Color32::WHITE);
+            if let Some([r, g, b, a]) = color {
+                let chip = egui::Rect::from_min_size(
+                    back.right_top() + egui::vec2(4.0, 0.0),
+                    egui::Vec2::splat(back.height()),
+                );
+                painter.rect_filled(chip, 2.0, egui::Color32::from_rgba_unmultiplied(r, g, b, a));
+                painter.rect_stroke(chip, 2.0, egui::Stroke::new(1.0, egui::Color32::GRAY));
+            }
+        }
+
+        if overlays {
+            if self.show_origin {
+                draw_origin_marker(
+                    &painter,
+                    canvas_rect.min,
+                    available_rect,
+                    self.show_axis_lines,
+                );
+            }
+
+            painter.rect_stroke(
+                canvas_rect,
+                0.0,
+                egui::Stroke::new(1.0, egui::Color32::WHITE),
+            );
+        }
+    }
+}
+
+/// Right (desktop) or bottom (mobile) panel: config, layers, properties and export
+fn control_panel_ui(ui: &mut egui::Ui, app: &mut KitbashApp) {
+    egui::ScrollArea::vertical().show(ui, |ui| {
+        ui.heading("Kitbash Config");
+        ui.horizontal(|ui| {
+            ui.label("Project:");
+            let tab_name = app.documents[app.active_document].name.clone();
+            ui.add(
+                egui::TextEdit::singleline(&mut app.project_name)
+                    .hint_text(tab_name)
+                    .desired_width(160.0),
+            );
+        });
+        let can_edit = app.can_edit();
+        ui.horizontal(|ui| {
+            if can_edit {
+                if ui
+                    .button("👁 Viewer Mode")
+                    .on_hover_text("Make the project read-only")
+                    .clicked()
+                {
+                    app.viewer_mode = true;
+                }
+            } else if ui.button("✏ Unlock for Editing").clicked() {
+                app.confirm_unlock = true;
+            }
+            let notes = if app.notes.text.is_empty() {
+                "📝 Notes"
+            } else {
+                "📝 Notes •"
+            };
+            ui.toggle_value(&mut app.notes_open, notes);
+        });
+        ui.add_enabled_ui(can_edit, |ui| {
+            ui.horizontal(|ui| {
+                let undo_label = app.history.undo.last().map(|e| e.label.clone());
+                let redo_label = app.history.redo.last().map(|e| e.label.clone());
+                let undo = ui.add_enabled(undo_label.is_some(), egui::Button::new("Undo"));
+                if undo.on_hover_text(undo_label.unwrap_or_default()).clicked() {
+                    app.undo();
+                }
+                let redo = ui.add_enabled(redo_label.is_some(), egui::Button::new("Redo"));
+                if redo.on_hover_text(redo_label.unwrap_or_default()).clicked() {
+                    app.redo();
+                }
+                let (_, held) = app.history.image_bytes(&app.layers);
+                let budget = ui.add(
+                    egui::DragValue::new(&mut app.undo_budget_mb)
+                        .range(16..=4096)
+                        .prefix(format!("History {} / ", format_bytes(held)))
+                        .suffix(" MB"),
+                );
+                if budget.on_hover_text("Image memory kept for undo").changed() {
+                    let budget = app.undo_budget_mb as usize * 1024 * 1024;
+                    app.history.enforce_budget(&app.layers, budget);
+                }
+            });
+        });
+        ui.separator();
+
+        // Canvas Settings
+        ui.collapsing("Canvas Setup", |ui| {
+            ui.add_enabled_ui(can_edit, |ui| {
+                ui.horizontal(|ui| {
+                    let [old_w, old_h] = app.canvas_size;
+                    let aspect = old_h as f32 / old_w as f32;
+                    ui.label("Base W:");
+                    let w_changed = ui
+                        .add(
+                            egui::DragValue::new(&mut app.canvas_size[0])
+                                .range(MIN_CANVAS_SIZE..=MAX_CANVAS_SIZE),
+                        )
+                        .changed();
+                    ui.label("Base H:");
+                    let h_changed = ui
+                        .add(
+                            egui::DragValue::new(&mut app.canvas_size[1])
+                                .range(MIN_CANVAS_SIZE..=MAX_CANVAS_SIZE),
+                        )
+                        .changed();
+                    if app.canvas_aspect_lock {
+                        let clamp =
+                            |v: f32| (v.round() as u32).clamp(MIN_CANVAS_SIZE, MAX_CANVAS_SIZE);
+                        if w_changed {
+                            app.canvas_size[1] = clamp(app.canvas_size[0] as f32 * aspect);
+                        } else if h_changed {
+                            app.canvas_size[0] = clamp(app.canvas_size[1] as f32 / aspect);
+                        }
+                    }
+                    ui.toggle_value(&mut app.canvas_aspect_lock, "🔒")
+                        .on_hover_text("Lock aspect ratio");
+                    if ui
+                        .button("Resize…")
+                        .on_hover_text("Resize keeping content anchored")
+                        .clicked()
+                    {
+                        app.resize_dialog = Some((app.canvas_size, egui::vec2(0.5, 0.5)));
+                    }
+                });
+                ui.horizontal(|ui| {
+                    ui.label("Preset:");
+                    let [w, h] = app.canvas_size;
+                    let current = CANVAS_PRESETS
+                        .iter()
+                        .find(|&&n| [n, n] == app.canvas_size)
+                        .map_or_else(|| "Custom".to_owned(), |n| format!("{}×{}", n, n));
+                    let mut picked = None;
+                    egui::ComboBox::from_id_salt("canvas_preset")
+                        .selected_text(current)
+                        .show_ui(ui, |ui| {
+                            for n in CANVAS_PRESETS {
+                                let label = format!("{}×{}", n, n);
+                                if ui.selectable_label([w, h] == [n, n], label).clicked() {
+                                    picked = Some(n);
+                                }
+                            }
+                            let custom = !CANVAS_PRESETS.iter().any(|&n| [n, n] == [w, h]);
+                            ui.add_enabled(false, egui::SelectableLabel::new(custom, "Custom"));
+                        });
+                    // Presets keep content centered, like the Resize dialog
+                    if let Some(n) = picked.filter(|&n| [n, n] != [w, h]) {
+                        app.resize_canvas([n, n], egui::vec2(0.5, 0.5));
+                    }
+                });
+                if app.canvas_size.iter().any(|&edge| edge > LARGE_CANVAS_SIZE) {
+                    ui.colored_label(
+                        ui.visuals().warn_fg_color,
+                        format!(
+                            "⚠ Canvases over {} px may be slow to preview and export",
+                            LARGE_CANVAS_SIZE
+                        ),
+                    );
+                }
+                ui.horizontal(|ui| {
+                    ui.checkbox(&mut app.auto_expand.enabled, "Grow When Dragging Past Edge");
+                    ui.add_enabled(
+                        app.auto_expand.enabled,
+                        egui::Checkbox::new(&mut app.auto_expand.auto_apply, "Without Asking"),
+                    );
+                });
+                if app.auto_expand.enabled {
+                    ui.horizontal(|ui| {
+                        ui.label("Threshold:");
+                        ui.add(
+                            egui::DragValue::new(&mut app.auto_expand.threshold)
+                                .range(0..=256)
+                                .suffix(" px"),
+                        );
+                        ui.label("Step:");
+                        ui.add(
+                            egui::DragValue::new(&mut app.auto_expand.increment)
+                                .range(1..=512)
+                                .suffix(" px"),
+                        );
+                    });
+                }
+                app.bg_palette.ui(ui, &mut app.bg_color);
+            });
+            app.preview_bg.ui(ui);
+            ui.horizontal(|ui| {
+                ui.label("View Zoom:");
+                let slider = egui::Slider::new(&mut app.preview_zoom, MIN_ZOOM..=MAX_ZOOM);
+                if ui.add(slider).changed() {
+                    app.zoom_fit = false;
+                }
+            });
+            ui.checkbox(&mut app.hide_references, "Hide Reference Layers");
+            ui.checkbox(&mut app.show_overflow, "Show Overflow")
+                .on_hover_text("Show layer pixels outside the canvas, faded");
+            ui.checkbox(&mut app.pick_by_pixel, "Select by Pixel")
+                .on_hover_text("Canvas clicks ignore transparent parts of a layer");
+            ui.horizontal(|ui| {
+                let mut snapping = app.snap_grid.is_some();
+                if ui.checkbox(&mut snapping, "Snap to Grid:").changed() {
+                    app.snap_grid = snapping.then_some(8);
+                }
+                if let Some(grid) = &mut app.snap_grid {
+                    ui.add(egui::DragValue::new(grid).range(1..=256).suffix(" px"));
+                }
+            });
+            ui.add_enabled_ui(can_edit, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Guides:");
+                    let [w, h] = app.canvas_size;
+                    if ui.button("Add Vertical").clicked() {
+                        app.guides.push(Guide {
+                            vertical: true,
+                            position: (w / 2) as f32,
+                        });
+                    }
+                    if ui.button("Add Horizontal").clicked() {
+                        app.guides.push(Guide {
+                            vertical: false,
+                            position: (h / 2) as f32,
+                        });
+                    }
+                    ui.checkbox(&mut app.snap_guides, "Snap");
+                });
+                let mut remove = None;
+                for (i, guide) in app.guides.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        let (label, max) = if guide.vertical {
+                            ("X:", app.canvas_size[0])
+                        } else {
+                            ("Y:", app.canvas_size[1])
+                        };
+                        ui.label(label);
+                        ui.add(
+                            egui::DragValue::new(&mut guide.position)
+                                .range(0.0..=max as f32)
+                                .suffix(" px"),
+                        );
+                        if ui.small_button("🗑").on_hover_text("Remove guide").clicked() {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    app.guides.remove(i);
+                }
+
+                ui.horizontal(|ui| {
+                    ui.label("Markers:");
+                    ui.toggle_value(&mut app.placing_marker, "📍 Place")
+                        .on_hover_text("Click the canvas to add a named attachment point");
+                });
+                let mut remove = None;
+                for (i, marker) in app.markers.iter_mut().enumerate() {
+                    ui.horizontal(|ui| {
+                        ui.add(egui::TextEdit::singleline(&mut marker.name).desired_width(80.0));
+                        let mut at = marker.canvas_position(&app.layers);
+                        let x = ui.add(egui::DragValue::new(&mut at.x).prefix("X: "));
+                        let y = ui.add(egui::DragValue::new(&mut at.y).prefix("Y: "));
+                        if x.changed() || y.changed() {
+                            marker.set_canvas_position(at, &app.layers);
+                        }
+                        let attached = marker.attached(&app.layers).map(|l| l.name.clone());
+                        let mut layer = marker.layer.filter(|_| attached.is_some());
+                        egui::ComboBox::from_id_salt(("marker_layer", i))
+                            .width(90.0)
+                            .selected_text(attached.unwrap_or_else(|| "Canvas".to_owned()))
+                            .show_ui(ui, |ui| {
+                                ui.selectable_value(&mut layer, None, "Canvas");
+                                for l in &app.layers {
+                                    ui.selectable_value(&mut layer, Some(l.id), &l.name);
+                                }
+                            })
+                            .response
+                            .on_hover_text("Layer the marker follows");
+                        if layer != marker.layer {
+                            marker.attach(layer, &app.layers);
+                        }
+                        if ui
+                            .small_button("🗑")
+                            .on_hover_text("Remove marker")
+                            .clicked()
+                        {
+                            remove = Some(i);
+                        }
+                    });
+                }
+                if let Some(i) = remove {
+                    app.markers.remove(i);
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut app.mirror_preview, "Mirror Preview");
+                let mut custom = app.mirror_axis.is_some();
+                if ui.checkbox(&mut custom, "Axis X:").changed() {
+                    app.mirror_axis = custom.then(|| app.canvas_size[0] as f32 / 2.0);
+                }
+                if let Some(axis) = &mut app.mirror_axis {
+                    ui.add(
+                        egui::DragValue::new(axis)
+                            .range(0.0..=app.canvas_size[0] as f32)
+                            .speed(0.5)
+                            .suffix(" px"),
+                    );
+                }
+                if ui
+                    .add_enabled(can_edit, egui::Button::new("Apply Mirror"))
+                    .on_hover_text("Add a mirrored copy of every visible layer")
+                    .clicked()
+                {
+                    app.apply_mirror();
+                }
+            });
+            ui.horizontal(|ui| {
+                ui.label("Offset Parity:");
+                ui.selectable_value(&mut app.offset_parity, None, "Off");
+                ui.selectable_value(&mut app.offset_parity, Some(Parity::Even), "Even");
+                ui.selectable_value(&mut app.offset_parity, Some(Parity::Odd), "Odd");
+            });
+            if let Some(parity) = app.offset_parity {
+                let violating: Vec<(u64, String)> = app
+                    .layers
+                    .iter()
+                    .filter(|l| !parity.holds(l.transform.offset))
+                    .map(|l| (l.id, l.name.clone()))
+                    .collect();
+                let mut fix = Vec::new();
+                if violating.is_empty() {
+                    ui.small("All layer offsets match the parity.");
+                } else {
+                    ui.collapsing(format!("{} layer(s) off parity", violating.len()), |ui| {
+                        for (id, name) in &violating {
+                            ui.horizontal(|ui| {
+                                ui.label(name);
+                                if ui.small_button("Fix").clicked() {
+                                    fix.push(*id);
+                                }
+                            });
+                        }
+                        if ui.button("Fix All").clicked() {
+                            fix = violating.iter().map(|(id, _)| *id).collect();
+                        }
+                    });
+                }
+                if !fix.is_empty() {
+                    app.begin_transaction("Fix parity");
+                    for layer in app.layers.iter_mut().filter(|l| fix.contains(&l.id)) {
+                        layer.transform.offset = parity.snap_vec(layer.transform.offset);
+                    }
+                    app.commit_transaction();
+                }
+            }
+            if app.snap_grid.is_some() {
+                ui.checkbox(&mut app.snap_offset_fields, "Snap offset fields too");
+                ui.small("Hold Ctrl while dragging to bypass snapping.");
+            }
+            ui.checkbox(&mut app.smooth_preview, "Smooth Zoomed-Out Preview")
+                .on_hover_text(
+                    "Bilinear sampling for layers drawn smaller than 1:1 (export is unaffected)",
+                );
+            ui.checkbox(&mut app.reduce_motion, "Reduce Motion")
+                .on_hover_text("Disable UI animations (also follows the system setting)");
+            ui.checkbox(&mut app.clean_view, "Clean View")
+                .on_hover_text("Hide all overlays. Hold ` to peek without toggling.");
+            ui.horizontal(|ui| {
+                ui.checkbox(&mut app.show_origin, "Show Origin");
+                ui.add_enabled(
+                    app.show_origin,
+                    egui::Checkbox::new(&mut app.show_axis_lines, "Axis Lines"),
+                );
+            });
+
+            if ui.button("Reset View").clicked() {
+                app.canvas_pan = egui::Vec2::ZERO;
+                app.preview_zoom = 4.0;
+                app.zoom_fit = false;
+            }
+        });
+
+        // Settings Profile
+        ui.collapsing("Settings Profile", |ui| {
+            for (action, shortcut) in app.keybindings.entries_mut() {
+                ui.small(format!("{}: {}", action, format_shortcut(shortcut)));
+            }
+            ui.horizontal(|ui| {
+                if ui.button("Export Profile").clicked() {
+                    let profile = app.settings_profile();
+                    let json_str = serde_json::to_string_pretty(&profile).unwrap();
+                    app.save_files(vec![(
+                        "kitbash_profile.json".to_owned(),
+                        json_str.into_bytes(),
+                    )]);
+                }
+                if ui.button("Import Profile...").clicked() {
+                    let events = app.events.clone();
+                    let task = async move {
+                        if let Some(handle) = rfd::AsyncFileDialog::new()
+                            .add_filter("Kitbash Profile", &["json"])
+                            .pick_file()
+                            .await
+                        {
+                            let bytes = handle.read().await;
+                            events.send(AppEvent::Io(IoEvent::ProfileLoaded(bytes)));
+                        }
+                    };
+
+                    #[cfg(target_arch = "wasm32")]
+                    wasm_bindgen_futures::spawn_local(task);
+                    #[cfg(not(target_arch = "wasm32"))]
+                    std::thread::spawn(move || {
+                        futures::executor::block_on(task);
+                    });
+                }
+            });
+            if let Some(err) = &app.profile_error {
+                ui.colored_label(egui::Color32::RED, err);
+            }
+        });
+
+        ui.separator();
+
+        // Asset Pipeline
+        ui.heading("Layers");
+        let import_clicked = ui
+            .add_enabled(
+                can_edit && app.import_queue.is_none(),
+                egui::Button::new("Import Images (Batch)..."),
+            )
+            .clicked();
+        if import_clicked {
+            let events = app.events.clone();
+            let (commands, command_receiver) = unbounded();
+            app.import_queue = Some(ImportQueue {
+                files: Vec::new(),
+                current: None,
+                finished: 0,
+                skipped: Vec::new(),
+                paused: false,
+                commands,
+                imported: Vec::new(),
+            });
+            let task = async move {
+                if let Some(handles) = rfd::AsyncFileDialog::new()
+                    .add_filter("Image", &["png", "jpg", "jpeg", "webp"])
+                    .pick_files()
+                    .await
+                {
+                    run_import_queue(handles, events, command_receiver).await;
+                } else {
+                    events.send(AppEvent::Import(ImportEvent::Finished));
+                }
+            };
+
+            #[cfg(target_arch = "wasm32")]
+            wasm_bindgen_futures::spawn_local(task);
+            #[cfg(not(target_arch = "wasm32"))]
+            std::thread::spawn(move || {
+                futures::executor::block_on(task);
+            });
+        }
+
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut app.mirror_pairs.enabled, "Detect Mirrored Pairs");
+            ui.add_enabled(
+                app.mirror_pairs.enabled,
+                egui::TextEdit::singleline(&mut app.mirror_pairs.suffixes).desired_width(120.0),
+            )
+            .on_hover_text("Left/right suffix pairs, comma separated");
+        });
+
+        // Import Queue
+        if let Some(queue) = &mut app.import_queue {
+            if !queue.files.is_empty() {
+                let total = queue.files.len();
+                let current = queue.current.and_then(|i| queue.files.get(i));
+                ui.label(format!(
+                    "Importing {}/{}: {}",
+                    queue.finished.min(total),
+                    total,
+                    current.map(String::as_str).unwrap_or("-")
+                ));
+                ui.add(egui::ProgressBar::new(queue.finished as f32 / total as f32));
+                if !queue.skipped.is_empty() {
+                    ui.small(format!("Skipped {} file(s)", queue.skipped.len()));
+                }
+
+                ui.horizontal(|ui| {
+                    if queue.paused {
+                        if ui.button("Resume").clicked() {
+                            queue.paused = false;
+                            queue.send(ImportCommand::Resume);
+                        }
+                    } else if ui.button("Pause").clicked() {
+                        queue.paused = true;
+                        queue.send(ImportCommand::Pause);
+                    }
+                    if ui.button("Skip Current").clicked() {
+                        queue.send(ImportCommand::SkipCurrent);
+                    }
+                    if ui.button("Cancel Remaining").clicked() {
+                        queue.send(ImportCommand::Cancel);
+                    }
+                });
+
+                ui.collapsing(
+                    format!("Queue ({} left)", total - queue.finished.min(total)),
+                    |ui| {
+                        for name in queue.files.iter().skip(queue.finished) {
+                            ui.small(name);
+                        }
+                    },
+                );
+            }
+        }
+
+        ui.separator();
+
+        // Layer List (Reorderable)
+        ui.horizontal(|ui| {
+            ui.label("🔍");
+            ui.add(
+                egui::TextEdit::singleline(&mut app.layer_filter)
+                    .hint_text("Filter layers")
+                    .desired_width(160.0),
+            );
+            if !app.layer_filter.is_empty() && ui.small_button("X").clicked() {
+                app.layer_filter.clear();
+            }
+            let label_name = |color: Option<egui::Color32>| {
+                LABEL_COLORS
+                    .iter()
+                    .find(|(_, c)| Some(*c) == color)
+                    .map_or("Any Label", |(name, _)| *name)
+            };
+            egui::ComboBox::from_id_salt("label_filter")
+                .selected_text(label_name(app.label_filter))
+                .show_ui(ui, |ui| {
+                    ui.selectable_value(&mut app.label_filter, None, "Any Label");
+                    for (name, color) in LABEL_COLORS {
+                        ui.selectable_value(
+                            &mut app.label_filter,
+                            Some(color),
+                            egui::RichText::new(format!("● {}", name)).color(color),
+                        );
+                    }
+                });
+        });
+        // Visibility stays available in viewer mode, like the per-row eye toggles
+        ui.horizontal(|ui| {
+            if ui.small_button("Show All").clicked() {
+                app.layers.iter_mut().for_each(|l| l.visible = true);
+            }
+            if ui.small_button("Hide All").clicked() {
+                app.layers.iter_mut().for_each(|l| l.visible = false);
+            }
+            if ui.small_button("Invert").clicked() {
+                app.layers.iter_mut().for_each(|l| l.visible = !l.visible);
+            }
+            ui.toggle_value(&mut app.compact_rows, "Compact")
+                .on_hover_text("Hide the row buttons; right-click a layer for its actions");
+        });
+        let filter = app.layer_filter.to_lowercase();
+        // Reordering among hidden rows would be confusing, so it pauses while filtering
+        let can_reorder = can_edit && filter.is_empty() && app.label_filter.is_none();
+
+        let mut row_action = None;
+        let layers_len = app.layers.len();
+
+        let mut drop_op = None;
+        let mut click_op = None;
+        let mut visibility_op = None;
+        for (idx, layer) in app.layers.iter_mut().enumerate() {
+            let matched = match_range(&layer.name, &filter);
+            let label_hidden = app
+                .label_filter
+                .is_some_and(|color| layer.label_color != Some(color));
+            if matched.is_none() || label_hidden {
+                continue;
+            }
+            let row = ui
+                .horizontal(|ui| {
+                    let handle = egui::Label::new("☰").sense(egui::Sense::drag());
+                    ui.add_enabled(can_reorder, handle)
+                        .on_hover_text("Drag to reorder")
+                        .dnd_set_drag_payload(idx);
+                    let thumb = layer.thumbnail(ui.ctx());
+                    ui.image((thumb, egui::vec2(24.0, 24.0)));
+                    let is_selected = app.selection.contains(&layer.id);
+                    if layer.clip_to_below {
+                        ui.add_space(8.0);
+                        ui.label("↳").on_hover_text("Clipped to the layer below");
+                    }
+                    let (dot, _) =
+                        ui.allocate_exact_size(egui::vec2(8.0, 8.0), egui::Sense::hover());
+                    if let Some(color) = layer.label_color {
+                        ui.painter().circle_filled(dot.center(), 4.0, color);
+                    }
+                    let name = match &mut app.renaming {
+                        Some((id, text)) if *id == layer.id => {
+                            let edit =
+                                ui.add(egui::TextEdit::singleline(text).desired_width(120.0));
+                            if !edit.has_focus() && !edit.lost_focus() {
+                                edit.request_focus();
+                            }
+                            if ui.input(|i| i.key_pressed(egui::Key::Escape)) {
+                                app.renaming = None;
+                            } else if edit.lost_focus() {
+                                row_action = Some(RowAction::Rename(layer.id, text.clone()));
+                            }
+                            edit
+                        }
+                        _ => {
+                            let label = highlighted_label(ui, &layer.name, matched);
+                            let name = ui.selectable_label(is_selected, label);
+                            if name.clicked() {
+                                click_op = Some((layer.id, ui.input(|i| i.modifiers)));
+                            }
+                            if name.double_clicked() && can_edit {
+                                app.renaming = Some((layer.id, layer.name.clone()));
+                            }
+                            name
+                        }
+                    };
+                    name.context_menu(|ui| {
+                        ui.add_enabled_ui(can_edit, |ui| {
+                            if ui.button("Rename").clicked() {
+                                app.renaming = Some((layer.id, layer.name.clone()));
+                                ui.close_menu();
+                            }
+                            if ui.button("Duplicate").clicked() {
+                                row_action = Some(RowAction::Duplicate(layer.id));
+                                ui.close_menu();
+                            }
+                            if ui.button("Delete").clicked() {
+                                row_action = Some(RowAction::Delete(layer.id));
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            ui.add_enabled_ui(can_reorder, |ui| {
+                                let up = ui.add_enabled(idx > 0, egui::Button::new("Move Up"));
+                                if up.clicked() {
+                                    row_action = Some(RowAction::Move(idx, idx - 1));
+                                    ui.close_menu();
+                                }
+                                let last = idx + 1 == layers_len;
+                                let down = ui.add_enabled(!last, egui::Button::new("Move Down"));
+                                if down.clicked() {
+                                    row_action = Some(RowAction::Move(idx, idx + 1));
+                                    ui.close_menu();
+                                }
+                            });
+                            if ui.button("Move to Position…").clicked() {
+                                row_action = Some(RowAction::MoveToPosition(layer.id, idx));
+                                ui.close_menu();
+                            }
+                            ui.separator();
+                            let lock = if layer.locked { "Unlock" } else { "Lock" };
+                            if ui.button(lock).clicked() {
+                                row_action = Some(RowAction::ToggleLock(layer.id));
+                                ui.close_menu();
+                            }
+                            ui.menu_button("Color Label", |ui| {
+                                for (label_name, color) in LABEL_COLORS {
+                                    let text = egui::RichText::new(format!("● {}", label_name))
+                                        .color(color);
+                                    if ui.button(text).clicked() {
+                                        layer.label_color = Some(color);
+                                        ui.close_menu();
+                                    }
+                                }
+                                if ui.button("None").clicked() {
+                                    layer.label_color = None;
+                                    ui.close_menu();
+                                }
+                            });
+                        });
+                    });
+
+                    if ui.checkbox(&mut layer.visible, "").changed() && is_selected {
+                        visibility_op = Some(layer.visible);
+                    }
+                    let lock_icon = if layer.locked { "🔒" } else { "🔓" };
+                    ui.add_enabled_ui(can_edit, |ui| {
+                        ui.toggle_value(&mut layer.locked, lock_icon)
+                            .on_hover_text("Lock against dragging and editing");
+                    });
+
+                    if let Some(z) = layer.z_override {
+                        ui.small(format!("z{:+}", z))
+                            .on_hover_text("Draw order overridden");
+                    }
+
+                    if !app.compact_rows {
+                        ui.add_enabled_ui(can_edit, |ui| {
+                            let up = ui.add_enabled(can_reorder, egui::Button::new("⬆"));
+                            if up.clicked() && idx > 0 {
+                                row_action = Some(RowAction::Move(idx, idx - 1));
+                            }
+                            let down = ui.add_enabled(can_reorder, egui::Button::new("⬇"));
+                            if down.clicked() && idx < layers_len - 1 {
+                                row_action = Some(RowAction::Move(idx, idx + 1));
+                            }
+                            if ui.button("X").clicked() {
+                                row_action = Some(RowAction::Delete(layer.id));
+                            }
+                        });
+                    }
+                })
+                .response;
+            if app.reveal_layer == Some(layer.id) {
+                row.scroll_to_me(Some(egui::Align::Center));
+            }
+
+            // Drop target: insert above or below this row depending on the pointer
+            if row.dnd_hover_payload::<usize>().is_some() {
+                if let Some(pointer) = ui.ctx().pointer_interact_pos() {
+                    let below = pointer.y > row.rect.center().y;
+                    let y = if below {
+                        row.rect.bottom()
+                    } else {
+                        row.rect.top()
+                    };
+                    ui.painter().hline(
+                        row.rect.x_range(),
+                        y,
+                        egui::Stroke::new(2.0, ui.visuals().selection.stroke.color),
+                    );
+                    if let Some(from) = row.dnd_release_payload::<usize>() {
+                        drop_op = Some((*from, idx + below as usize));
+                    }
+                }
+            }
+        }
 
-                    #[cfg(target_arch = "wasm32")]
-                    wasm_bindgen_futures::spawn_local(task);
-                    #[cfg(not(target_arch = "wasm32"))]
-                    std::thread::spawn(move || { futures::executor::block_on(task); });
+        if let Some((from, to)) = drop_op {
+            // `to` is an insertion index into the list before removal
+            let to = if to > from { to - 1 } else { to };
+            if from != to && from < app.layers.len() {
+                app.begin_transaction("Reorder layer");
+                let layer = app.layers.remove(from);
+                app.layers.insert(to.min(app.layers.len()), layer);
+                app.commit_transaction();
+            }
+        }
+
+        // One-shot: a row hidden by the filter is not revealed later
+        app.reveal_layer = None;
+        match row_action {
+            Some(RowAction::Rename(id, name)) => {
+                app.renaming = None;
+                let name = name.trim();
+                if let Some(layer) = app.layers.iter_mut().find(|l| l.id == id) {
+                    if !name.is_empty() && name != layer.name {
+                        layer.name = name.to_owned();
+                    }
+                }
+            }
+            Some(RowAction::Duplicate(id)) => {
+                app.duplicate_layer(id);
+            }
+            Some(RowAction::Delete(id)) => {
+                if app.selection.contains(&id) {
+                    app.delete_layers(&app.selected_ids());
+                } else {
+                    app.delete_layers(&[id]);
+                }
+            }
+            Some(RowAction::Move(from, to)) => {
+                app.begin_transaction("Move layer");
+                app.layers.swap(from, to);
+                app.commit_transaction();
+            }
+            Some(RowAction::MoveToPosition(id, idx)) => app.move_dialog = Some((id, idx)),
+            Some(RowAction::ToggleLock(id)) => {
+                if let Some(layer) = app.layers.iter_mut().find(|l| l.id == id) {
+                    layer.locked = !layer.locked;
+                }
+            }
+            None => {}
+        }
+        if let Some((id, modifiers)) = click_op {
+            app.click_select(id, modifiers);
+        }
+        // Visibility on a selected row applies to the whole selection
+        if let Some(visible) = visibility_op {
+            for layer in &mut app.layers {
+                if app.selection.contains(&layer.id) {
+                    layer.visible = visible;
                 }
+            }
+        }
 
-                ui.separator();
+        ui.separator();
 
-                // Layer List (Reorderable)
-                let mut move_op = None;
-                let mut delete_op = None;
-                let layers_len = app.layers.len();
+        // Properties Panel
+        let mut bake_op = None;
+        let mut paste_op = false;
+        let mut user_data_op: Option<(String, Option<String>)> = None; // Applied to the selection
+        ui.add_enabled_ui(can_edit, |ui| {
+            if let Some(selected_id) = app.selected_layer_id {
+                if let Some(layer) = app.layers.iter_mut().find(|l| l.id == selected_id) {
+                    ui.heading(format!("Properties: {}", layer.name));
+                    let editable = !layer.locked;
 
-                for (idx, layer) in app.layers.iter_mut().enumerate() {
-                    ui.horizontal(|ui| {
-                        let is_selected = Some(layer.id) == app.selected_layer_id;
-                        if ui.selectable_label(is_selected, &layer.name).clicked() {
-                            app.selected_layer_id = Some(layer.id);
+                    ui.add_enabled_ui(editable, |ui| {
+                        let transform = &mut layer.transform;
+                        ui.horizontal(|ui| {
+                            ui.label("Scale:");
+                            ui.toggle_value(&mut app.scale_linked, "🔗")
+                                .on_hover_text("Link X and Y scale");
+                        });
+                        if app.scale_linked {
+                            // Drive both axes from X, keeping any existing X/Y ratio
+                            let old_x = transform.scale_x;
+                            let response =
+                                ui.add(egui::Slider::new(&mut transform.scale_x, 0.1..=5.0));
+                            if response.changed() && old_x != 0.0 {
+                                transform.scale_y *= transform.scale_x / old_x;
+                            }
+                        } else {
+                            ui.add(egui::Slider::new(&mut transform.scale_x, 0.1..=5.0).text("X"));
+                            ui.add(egui::Slider::new(&mut transform.scale_y, 0.1..=5.0).text("Y"));
                         }
+                        ui.horizontal(|ui| {
+                            ui.label("Anchor:");
+                            egui::Grid::new("anchor_picker")
+                                .spacing([2.0, 2.0])
+                                .show(ui, |ui| {
+                                    for y in 0..3 {
+                                        for x in 0..3 {
+                                            let anchor = egui::vec2(x as f32 * 0.5, y as f32 * 0.5);
+                                            let selected = layer.transform.anchor == anchor;
+                                            if ui
+                                                .selectable_label(
+                                                    selected,
+                                                    if selected { "●" } else { "○" },
+                                                )
+                                                .clicked()
+                                            {
+                                                layer.set_anchor(anchor);
+                                            }
+                                        }
+                                        ui.end_row();
+                                    }
+                                });
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Offset:");
+                            let x = ui.add(
+                                egui::DragValue::new(&mut layer.transform.offset.x)
+                                    .speed(1.0)
+                                    .prefix("X: "),
+                            );
+                            let y = ui.add(
+                                egui::DragValue::new(&mut layer.transform.offset.y)
+                                    .speed(1.0)
+                                    .prefix("Y: "),
+                            );
+                            if let Some(grid) = app.snap_grid.filter(|_| app.snap_offset_fields) {
+                                if x.changed() || y.changed() {
+                                    layer.transform.offset =
+                                        snap_to_grid(layer.transform.offset, grid);
+                                }
+                            }
+                            if let Some(parity) = app.offset_parity {
+                                if x.changed() || y.changed() {
+                                    layer.transform.offset =
+                                        parity.snap_vec(layer.transform.offset);
+                                }
+                            }
+                        });
+                        ui.checkbox(&mut layer.transform.flip_h, "Flip Horizontal");
 
-                        ui.checkbox(&mut layer.visible, "");
+                        let (sx, sy) = (layer.transform.scale_x, layer.transform.scale_y);
+                        ui.horizontal(|ui| {
+                            let unscaled = sx == 1.0 && sy == 1.0;
+                            if ui
+                                .add_enabled(!unscaled, egui::Button::new("Bake Transform"))
+                                .on_hover_text(
+                                    "Resample the image at its current scale and reset scale to 1",
+                                )
+                                .clicked()
+                            {
+                                bake_op = Some(layer.id);
+                            }
+                            if sx < 1.0 || sy < 1.0 {
+                                ui.colored_label(
+                                    egui::Color32::YELLOW,
+                                    "Scale below 1: detail will be lost",
+                                );
+                            }
+                        });
+                        ui.checkbox(&mut layer.clip_to_below, "Clip to Layer Below")
+                            .on_hover_text("Only show where the layer below is opaque");
+                        ui.checkbox(&mut layer.is_reference, "Reference Layer")
+                            .on_hover_text("Shown faded for tracing, never exported");
+                        ui.horizontal(|ui| {
+                            if ui
+                                .button("Paste Replace")
+                                .on_hover_text(format!(
+                                    "Replace the image with the clipboard image ({})",
+                                    format_shortcut(&app.keybindings.paste_replace)
+                                ))
+                                .clicked()
+                            {
+                                paste_op = true;
+                            }
+                            ui.checkbox(&mut app.replace_keeps_center, "Keep Center");
+                        });
+                    });
 
-                        if ui.button("⬆").clicked() && idx > 0 {
-                            move_op = Some((idx, idx - 1));
-                        }
-                        if ui.button("⬇").clicked() && idx < layers_len - 1 {
-                            move_op = Some((idx, idx + 1));
+                    ui.horizontal(|ui| {
+                        let mut has_override = layer.z_override.is_some();
+                        if ui
+                            .checkbox(&mut has_override, "Z Override:")
+                            .on_hover_text("Layers without an override count as 0")
+                            .changed()
+                        {
+                            layer.z_override = if has_override { Some(0) } else { None };
                         }
-                        if ui.button("X").clicked() {
-                            delete_op = Some(idx);
+                        if let Some(z) = &mut layer.z_override {
+                            ui.add(egui::DragValue::new(z).speed(0.1));
                         }
                     });
-                }
 
-                if let Some((from, to)) = move_op {
-                    app.layers.swap(from, to);
-                }
-                if let Some(idx) = delete_op {
-                    let id = app.layers[idx].id;
-                    app.layers.remove(idx);
-                    if app.selected_layer_id == Some(id) {
-                        app.selected_layer_id = None;
-                    }
-                }
+                    ui.collapsing("Adjustments", |ui| {
+                        ui.add(
+                            egui::Slider::new(&mut layer.adjust.hue, -180.0..=180.0)
+                                .text("Hue")
+                                .suffix("°"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut layer.adjust.saturation, -100.0..=100.0)
+                                .text("Saturation"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut layer.adjust.lightness, -100.0..=100.0)
+                                .text("Lightness"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut layer.tone.brightness, -100.0..=100.0)
+                                .text("Brightness"),
+                        );
+                        ui.add(
+                            egui::Slider::new(&mut layer.tone.contrast, -100.0..=100.0)
+                                .text("Contrast"),
+                        );
+                        ui.checkbox(&mut layer.tone.preserve_endpoints, "Keep pure black/white")
+                            .on_hover_text("Pixels exactly #000 or #fff are left unchanged");
+                        if ui.button("Reset Adjustments").clicked() {
+                            layer.adjust = ColorAdjust::default();
+                            layer.tone = ToneAdjust::default();
+                        }
+                    });
 
-                ui.separator();
+                    ui.collapsing("Outline", |ui| {
+                        let outline = &mut layer.outline;
+                        ui.checkbox(&mut outline.enabled, "Enabled");
+                        ui.add_enabled_ui(outline.enabled, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Color:");
+                                ui.color_edit_button_srgba(&mut outline.color);
+                                ui.label("Thickness:");
+                                ui.add(
+                                    egui::DragValue::new(&mut outline.thickness)
+                                        .range(1..=16)
+                                        .suffix(" px"),
+                                );
+                            });
+                            ui.horizontal(|ui| {
+                                ui.selectable_value(&mut outline.inside, false, "Outside");
+                                ui.selectable_value(&mut outline.inside, true, "Inside");
+                            });
+                        });
+                    });
 
-                // Properties Panel
-                if let Some(selected_id) = app.selected_layer_id {
-                    if let Some(layer) = app.layers.iter_mut().find(|l| l.id == selected_id) {
-                        ui.heading(format!("Properties: {}", layer.name));
+                    ui.collapsing("Drop Shadow", |ui| {
+                        let shadow = &mut layer.shadow;
+                        ui.checkbox(&mut shadow.enabled, "Enabled");
+                        ui.add_enabled_ui(shadow.enabled, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.label("Offset X:");
+                                ui.add(egui::DragValue::new(&mut shadow.offset[0]).range(-64..=64));
+                                ui.label("Y:");
+                                ui.add(egui::DragValue::new(&mut shadow.offset[1]).range(-64..=64));
+                            });
+                            ui.horizontal(|ui| {
+                                ui.label("Color:");
+                                ui.color_edit_button_srgba(&mut shadow.color);
+                                ui.label("Opacity:");
+                                ui.add(egui::Slider::new(&mut shadow.opacity, 0.0..=1.0));
+                            });
+                            ui.checkbox(&mut shadow.include_in_bounds, "Include shadow in bounds");
+                        });
+                    });
 
-                        ui.horizontal(|ui| {
-                            ui.label("Scale:");
-                            ui.add(egui::Slider::new(&mut layer.transform.scale, 0.1..=5.0));
+                    ui.collapsing("User Data", |ui| {
+                        let multi = app.selection.len() > 1;
+                        let mut remove = None;
+                        egui::Grid::new("user_data").num_columns(3).show(ui, |ui| {
+                            for (key, value) in &mut layer.user_data {
+                                ui.label(key);
+                                ui.add(egui::TextEdit::singleline(value).desired_width(120.0));
+                                ui.horizontal(|ui| {
+                                    if ui.small_button("🗑").on_hover_text("Remove").clicked() {
+                                        remove = Some(key.clone());
+                                    }
+                                    if multi
+                                        && ui
+                                            .small_button("⇉")
+                                            .on_hover_text("Set on every selected layer")
+                                            .clicked()
+                                    {
+                                        user_data_op = Some((key.clone(), Some(value.clone())));
+                                    }
+                                });
+                                ui.end_row();
+                            }
                         });
+                        if let Some(key) = remove {
+                            layer.user_data.remove(&key);
+                        }
+
+                        let (key, value) = &mut app.user_data_draft;
+                        let error = user_data_key_error(key, &layer.user_data);
                         ui.horizontal(|ui| {
-                            ui.label("Offset:");
-                            ui.add(egui::DragValue::new(&mut layer.transform.offset.x).speed(1.0).prefix("X: "));
-                            ui.add(egui::DragValue::new(&mut layer.transform.offset.y).speed(1.0).prefix("Y: "));
+                            ui.add(
+                                egui::TextEdit::singleline(key)
+                                    .hint_text("key")
+                                    .desired_width(80.0),
+                            );
+                            ui.add(
+                                egui::TextEdit::singleline(value)
+                                    .hint_text("value")
+                                    .desired_width(120.0),
+                            );
+                            let add = if multi { "Add to Selected" } else { "Add" };
+                            if ui
+                                .add_enabled(error.is_none(), egui::Button::new(add))
+                                .clicked()
+                            {
+                                if multi {
+                                    user_data_op = Some((key.clone(), Some(value.clone())));
+                                } else {
+                                    layer.user_data.insert(key.clone(), value.clone());
+                                }
+                                key.clear();
+                                value.clear();
+                            }
                         });
+                        if let Some(error) = error.filter(|_| !key.is_empty()) {
+                            ui.small(error);
+                        }
+                        if multi {
+                            ui.horizontal(|ui| {
+                                ui.add(
+                                    egui::TextEdit::singleline(&mut app.user_data_remove_key)
+                                        .hint_text("key")
+                                        .desired_width(80.0),
+                                );
+                                if ui
+                                    .add_enabled(
+                                        !app.user_data_remove_key.is_empty(),
+                                        egui::Button::new("Remove from Selected"),
+                                    )
+                                    .clicked()
+                                {
+                                    let key = std::mem::take(&mut app.user_data_remove_key);
+                                    user_data_op = Some((key, None));
+                                }
+                            });
+                        }
+                    });
 
+                    ui.horizontal(|ui| {
+                        ui.label("Tint:");
+                        ui.color_edit_button_srgba(&mut layer.tint);
+                        if ui.button("Reset Tint").clicked() {
+                            layer.tint = egui::Color32::WHITE;
+                        }
+                    });
+
+                    ui.add_enabled_ui(editable, |ui| {
                         if ui.button("Snap to Pixel").clicked() {
                             layer.transform.offset.x = layer.transform.offset.x.round();
                             layer.transform.offset.y = layer.transform.offset.y.round();
+                            if let Some(parity) = app.offset_parity {
+                                layer.transform.offset = parity.snap_vec(layer.transform.offset);
+                            }
                         }
 
                         if ui.button("Reset").clicked() {
-                            layer.transform.scale = 1.0;
+                            layer.transform.scale_x = 1.0;
+                            layer.transform.scale_y = 1.0;
                             layer.transform.offset = egui::Vec2::ZERO;
                         }
+
+                        let canvas =
+                            egui::vec2(app.canvas_size[0] as f32, app.canvas_size[1] as f32);
+                        ui.horizontal(|ui| {
+                            if ui.button("Center").clicked() {
+                                layer.center_on(canvas);
+                            }
+                            if ui.button("Fit Canvas").clicked() {
+                                layer.fit_to(canvas, app.fit_cover);
+                            }
+                            ui.checkbox(&mut app.fit_cover, "Cover").on_hover_text(
+                                "Fill the whole canvas instead of fitting inside it",
+                            );
+                        });
+                    });
+                    if layer.locked {
+                        ui.small("Layer is locked.");
                     }
-                } else {
-                    ui.label("Select a layer to edit.");
+                    if app.placing == Some(selected_id) {
+                        ui.colored_label(
+                            egui::Color32::LIGHT_BLUE,
+                            "Click the canvas to place the pivot (Esc cancels)",
+                        );
+                    }
+
+                    let duplicate_hint = format_shortcut(&app.keybindings.duplicate_layer);
+                    ui.horizontal(|ui| {
+                        if ui
+                            .button("Duplicate")
+                            .on_hover_text(duplicate_hint)
+                            .clicked()
+                        {
+                            app.duplicate_layer(selected_id);
+                        }
+                        let place_hint = format_shortcut(&app.keybindings.place_at_click);
+                        if ui
+                            .button("Place at Click")
+                            .on_hover_text(place_hint)
+                            .clicked()
+                        {
+                            app.placing = Some(selected_id);
+                        }
+                        if ui.button("Scatter...").clicked() {
+                            app.scatter = Some(ScatterSettings::new(selected_id, app.canvas_size));
+                        }
+                    });
                 }
+            } else {
+                ui.label("Select a layer to edit.");
+            }
+        });
 
-                ui.separator();
+        if let Some(id) = bake_op {
+            app.begin_transaction("Bake transform");
+            if let Some(layer) = app.layers.iter_mut().find(|l| l.id == id) {
+                layer.bake_scale();
+            }
+            app.commit_transaction();
+        }
+        if paste_op {
+            app.paste_replace(ui.ctx());
+        }
+        if let Some((key, value)) = user_data_op {
+            app.set_user_data(&app.selected_ids(), &key, value.as_deref());
+        }
 
-                // Export System
-                ui.heading("Export (Scattered)");
+        // Align Tools
+        let align_ids = app.selected_ids();
+        if can_edit && !align_ids.is_empty() {
+            ui.collapsing("Align", |ui| {
                 ui.horizontal(|ui| {
-                    ui.label("Export Scale:");
-                    ui.add(egui::DragValue::new(&mut app.export_scale).range(1..=10).speed(0.1));
+                    ui.label("Relative to:");
+                    ui.selectable_value(&mut app.align_to_canvas, true, "Canvas");
+                    ui.add_enabled_ui(align_ids.len() > 1, |ui| {
+                        ui.selectable_value(&mut app.align_to_canvas, false, "Selection");
+                    });
                 });
-
-                let current_res = format!("{} x {}",
-                    app.canvas_size[0] * app.export_scale,
-                    app.canvas_size[1] * app.export_scale
-                );
-                ui.label(format!("Output Res: {}", current_res));
-
-                ui.horizontal(|ui| {
-                    if ui.button("Download Individual PNGs").clicked() {
-                        for (i, layer) in app.layers.iter().enumerate() {
-                            if let Some(img) = render_single_layer(app.canvas_size, layer, app.export_scale) {
-                                let mut bytes: Vec<u8> = Vec::new();
-                                img.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
-                                let filename = format!("{}_{}.png", i, layer.name);
-                                trigger_download(&filename, &bytes);
-                            }
+                let to_canvas = app.align_to_canvas || align_ids.len() < 2;
+                ui.horizontal_wrapped(|ui| {
+                    for (align, label) in Align::ALL {
+                        if ui.button(label).clicked() {
+                            app.align_layers(&align_ids, align, to_canvas);
                         }
                     }
+                });
+                ui.add_enabled_ui(align_ids.len() >= 3, |ui| {
+                    ui.horizontal(|ui| {
+                        if ui.button("Distribute H").clicked() {
+                            app.distribute_layers(&align_ids, true);
+                        }
+                        if ui.button("Distribute V").clicked() {
+                            app.distribute_layers(&align_ids, false);
+                        }
+                    });
+                });
+            });
 
-                    if ui.button("Download ZIP").clicked() {
-                        let mut zip_buffer = Vec::new();
-                        {
-                            let mut zip = zip::ZipWriter::new(Cursor::new(&mut zip_buffer));
-                            let options = zip::write::FileOptions::default()
-                                .compression_method(zip::CompressionMethod::Deflated);
-
-                            // 1. Export each visible layer as PNG
-                            for (i, layer) in app.layers.iter().enumerate() {
-                                if let Some(img) = render_single_layer(app.canvas_size, layer, app.export_scale) {
-                                    let mut bytes = Vec::new();
-                                    img.write_to(&mut Cursor::new(&mut bytes), image::ImageFormat::Png).unwrap();
-
-                                    let filename = format!("{}_{}.png", i, layer.name);
-                                    zip.start_file(filename, options).unwrap();
-                                    zip.write_all(&bytes).unwrap();
-                                }
-                            }
-
-                            // 2. Export Metadata
-                            let meta: Vec<serde_json::Value> = app.layers.iter().map(|l| {
-                                serde_json::json!({
-                                    "name": l.name,
-                                    "visible": l.visible,
-                                    "scale": l.transform.scale,
-                                    "offset": { "x": l.transform.offset.x.round(), "y": l.transform.offset.y.round() },
-                                })
-                            }).collect();
-                            let json_str = serde_json::to_string_pretty(&meta).unwrap();
+            ui.horizontal(|ui| {
+                let merge_selected = ui
+                    .add_enabled(align_ids.len() > 1, egui::Button::new("Merge Selected"))
+                    .on_hover_text("Bake the visible selected layers into one");
+                if merge_selected.clicked() {
+                    app.merge_layers(&align_ids);
+                }
+                if let Some(id) = app.selected_layer_id {
+                    if ui
+                        .button("Merge Down")
+                        .on_hover_text("Merge with the layer drawn below")
+                        .clicked()
+                    {
+                        app.merge_down(id);
+                    }
+                }
+            });
+        }
 
-                            zip.start_file("data.json", options).unwrap();
-                            zip.write_all(json_str.as_bytes()).unwrap();
+        ui.horizontal(|ui| {
+            if ui
+                .add_enabled(!align_ids.is_empty(), egui::Button::new("Copy Layers"))
+                .on_hover_text("Copy the selected layers, to paste into any open document")
+                .clicked()
+            {
+                app.copy_layers();
+            }
+            let count = app.layer_clipboard.len();
+            let paste = egui::Button::new(format!("Paste {} Layer(s)", count));
+            if ui.add_enabled(can_edit && count > 0, paste).clicked() {
+                app.paste_layers();
+            }
+        });
 
-                            zip.finish().unwrap();
-                        }
+        ui.separator();
 
-                        trigger_download("kitbash_layers.zip", &zip_buffer);
+        // Export System
+        ui.heading("Export (Scattered)");
+        app.update_export_estimate(ui.ctx());
+        if let Some((_, estimate)) = &app.export_estimate {
+            let largest = estimate
+                .largest_source
+                .map(|[w, h]| format!(", largest source {}×{}", w, h))
+                .unwrap_or_default();
+            let summary = format!(
+                "Will composite {} of {} layers{}, output {}×{} PNG ≈ {}",
+                estimate.contributing,
+                estimate.total,
+                largest,
+                estimate.output_size[0],
+                estimate.output_size[1],
+                estimate.png_bytes.map_or("…".to_owned(), format_bytes),
+            );
+            egui::CollapsingHeader::new(summary)
+                .id_salt("export_estimate")
+                .show(ui, |ui| {
+                    if estimate.skipped.is_empty() {
+                        ui.small("Every layer contributes.");
+                    }
+                    for line in &estimate.skipped {
+                        ui.small(line);
                     }
                 });
+        }
+        ui.horizontal(|ui| {
+            ui.label("Export Scale:");
+            let custom = !EXPORT_SCALE_PRESETS.contains(&app.export_scale);
+            egui::ComboBox::from_id_salt("export_scale")
+                .selected_text(if custom {
+                    "Custom".to_owned()
+                } else {
+                    format!("{}×", app.export_scale)
+                })
+                .show_ui(ui, |ui| {
+                    for scale in EXPORT_SCALE_PRESETS {
+                        ui.selectable_value(&mut app.export_scale, scale, format!("{}×", scale));
+                    }
+                    if ui.selectable_label(custom, "Custom").clicked() && !custom {
+                        app.export_scale = 3;
+                    }
+                });
+            if custom {
+                ui.add(
+                    egui::DragValue::new(&mut app.export_scale)
+                        .range(1..=MAX_EXPORT_SCALE)
+                        .speed(0.1)
+                        .suffix("×"),
+                );
+            }
+        });
+        let bg = &mut app.export_background;
+        if ui
+            .checkbox(&mut bg.transparent, "Export with Transparent Background")
+            .on_hover_text("Ignore the canvas background color in every export")
+            .changed()
+            && bg.transparent
+        {
+            bg.solid = false;
+        }
+        ui.horizontal(|ui| {
+            if ui
+                .checkbox(&mut bg.solid, "Export on Solid Color")
+                .on_hover_text("Composite every export on this color instead, e.g. for thumbnails")
+                .changed()
+                && bg.solid
+            {
+                bg.transparent = false;
+            }
+            if bg.solid {
+                ui.color_edit_button_srgba(&mut bg.color);
+            }
+        });
+        ui.checkbox(&mut app.export_scaled_metadata, "Scale data.json Offsets")
+            .on_hover_text("Write offsets, bounds and scales in output pixels (× export scale)");
+
+        let current_res = format!(
+            "{} x {}",
+            app.canvas_size[0] * app.export_scale,
+            app.canvas_size[1] * app.export_scale
+        );
+        ui.label(format!("Output Res: {}", current_res));
+
+        ui.collapsing("Output Effects", |ui| {
+            let effects = &mut app.output_effects;
+            ui.horizontal(|ui| {
+                ui.label("Pixelate:");
+                ui.add(
+                    egui::DragValue::new(&mut effects.pixelate)
+                        .range(1..=64)
+                        .suffix(" px"),
+                );
+                ui.label("Posterize:");
+                ui.add(
+                    egui::DragValue::new(&mut effects.posterize)
+                        .range(0..=64)
+                        .suffix(" levels"),
+                )
+                .on_hover_text("Levels per channel, 0 = off");
             });
-        };
+            ui.checkbox(&mut effects.preview, "Preview on Canvas");
+        });
 
-        // Render UI Panels
-        if is_mobile {
-            egui::TopBottomPanel::bottom("bottom_panel")
-                .resizable(true)
-                .default_height(300.0)
-                .show(ctx, |ui| control_panel_ui(ui, self));
-        } else {
-            egui::SidePanel::right("right_panel")
-                .resizable(true)
-                .default_width(300.0)
-                .show(ctx, |ui| control_panel_ui(ui, self));
-        }
+        ui.horizontal(|ui| {
+            ui.label("File Name:");
+            ui.add(
+                egui::TextEdit::singleline(&mut app.export_filename_template)
+                    .hint_text(DEFAULT_EXPORT_TEMPLATE)
+                    .desired_width(160.0),
+            )
+            .on_hover_text("{project} and {timestamp} are filled in; the extension is added");
+            if app.export_filename_template.trim().is_empty() {
+                app.export_filename_template = DEFAULT_EXPORT_TEMPLATE.to_owned();
+            }
+        });
+        ui.small(format!("Next export: {}", app.export_stem()));
 
-        // Central Canvas Area
-        egui::CentralPanel::default().show(ctx, |ui| {
-            let available_rect = ui.available_rect_before_wrap();
-            let painter = ui.painter_at(available_rect);
-
-            // Handle Canvas Panning (Middle Mouse or Alt+Drag or Space+Drag logic)
-            // Or just drag on empty space.
-            let input = ui.input(|i| i.clone());
-
-            // Allow panning if middle mouse is dragging
-            if input.pointer.button_down(egui::PointerButton::Middle) {
-                self.canvas_pan += input.pointer.delta();
-            }
-
-            // Calculate Canvas Rect (Centered + Pan)
-            let canvas_w = self.canvas_size[0] as f32 * self.preview_zoom;
-            let canvas_h = self.canvas_size[1] as f32 * self.preview_zoom;
-
-            let center = available_rect.center() + self.canvas_pan;
-            let canvas_rect = egui::Rect::from_center_size(center, egui::vec2(canvas_w, canvas_h));
-
-            // Draw Background (Checkerboard)
-            painter.rect_filled(canvas_rect, 0.0, egui::Color32::from_gray(50));
-
-            let check_size = 8.0 * self.preview_zoom;
-            let cols = (self.canvas_size[0] as f32 / 8.0).ceil() as u32;
-            let rows = (self.canvas_size[1] as f32 / 8.0).ceil() as u32;
-
-            for r in 0..rows {
-                for c in 0..cols {
-                    if (r + c) % 2 == 0 {
-                        let x = canvas_rect.min.x + c as f32 * check_size;
-                        let y = canvas_rect.min.y + r as f32 * check_size;
-                        // Clip to canvas size
-                        let rect = egui::Rect::from_min_size(
-                            egui::pos2(x, y),
-                            egui::vec2(check_size, check_size),
-                        )
-                        .intersect(canvas_rect);
+        #[cfg(not(target_arch = "wasm32"))]
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut app.backup_policy.enabled, "Back up overwritten files");
+            ui.add_enabled(
+                app.backup_policy.enabled,
+                egui::DragValue::new(&mut app.backup_policy.keep)
+                    .range(1..=50)
+                    .prefix("Keep: "),
+            );
+        });
+
+        if let Some(job) = &app.export_job {
+            ui.horizontal(|ui| {
+                ui.add(
+                    egui::ProgressBar::new(job.progress)
+                        .desired_width(200.0)
+                        .text(format!("{} {:.0}%", job.label, job.progress * 100.0)),
+                );
+                if ui.button("Cancel").clicked() {
+                    job.cancel.store(true, Ordering::Relaxed);
+                }
+            });
+        }
 
-                        if rect.is_positive() {
-                            painter.rect_filled(rect, 0.0, egui::Color32::from_gray(100));
+        ui.horizontal_wrapped(|ui| {
+            if app.export_job.is_some() {
+                ui.disable();
+            }
+            if ui.button("Download Individual PNGs").clicked() {
+                log::info!("Exporting individual PNGs at {}x", app.export_scale);
+                let snapshot = app.export_snapshot();
+                let stem = app.export_stem();
+                app.start_export("PNGs", move |progress| async move {
+                    let stamp = snapshot.export_stamp().to_string();
+                    let order = draw_order(&snapshot.layers);
+                    let files = snapshot.layer_export_files(&order);
+                    let mut pngs = Vec::new();
+                    for (i, file) in files.iter().enumerate() {
+                        progress.step(i, files.len()).await?;
+                        let Some(file) = file else { continue };
+                        if let Some((img, _)) = snapshot.export_layer_image(&order, i) {
+                            let filename = format!("{}_{}", stem, file);
+                            pngs.push((filename, encode_png(&img, &stamp)?));
                         }
                     }
-                }
+                    Ok(pngs)
+                });
             }
 
-            if self.bg_color != egui::Color32::TRANSPARENT {
-                painter.rect_filled(canvas_rect, 0.0, self.bg_color);
+            // The size check runs on the estimate, before anything is built
+            let html_bytes = app
+                .export_estimate
+                .as_ref()
+                .and_then(|(_, estimate)| estimate.html_bytes);
+            if ui
+                .add_enabled(
+                    html_bytes.is_some(),
+                    egui::Button::new("Download HTML Viewer"),
+                )
+                .on_hover_text("One self-contained page to toggle the parts in a browser")
+                .on_disabled_hover_text("Estimating the page size…")
+                .clicked()
+            {
+                match html_bytes {
+                    Some(size) if size > HTML_VIEWER_WARN_BYTES => app.pending_html = Some(size),
+                    _ => app.save_html_viewer(),
+                }
             }
 
-            // Draw Layers
-            let mut drag_delta = egui::Vec2::ZERO;
-            let mut dragged_id = None;
+            if ui
+                .button("Download Aseprite")
+                .on_hover_text("One .aseprite file with a layer per visible layer")
+                .clicked()
+            {
+                let snapshot = app.export_snapshot();
+                let filename = format!(
+                    "{}{}.aseprite",
+                    app.export_stem(),
+                    scale_suffix(app.export_scale)
+                );
+                app.start_export("Aseprite", move |progress| async move {
+                    Ok(vec![(filename, snapshot.build_aseprite(&progress).await?)])
+                });
+            }
 
-            for layer in &mut self.layers {
-                if !layer.visible {
-                    continue;
+            if ui
+                .button("📋 Copy to Clipboard")
+                .on_hover_text("Copy the composited image, to paste it into another app")
+                .clicked()
+            {
+                let ctx = ui.ctx().clone();
+                match set_clipboard_image(&app.export_snapshot().composite()) {
+                    Ok(()) => app.show_toast(&ctx, "Copied composite to the clipboard"),
+                    Err(err) => {
+                        log::warn!("{}", err);
+                        app.show_toast(&ctx, err);
+                    }
                 }
+            }
 
-                let texture_id = if let Some(tex) = &layer.texture {
-                    tex.id()
-                } else {
-                    let tex = ctx.load_texture(
-                        &layer.name,
-                        egui::ColorImage::from_rgba_unmultiplied(
-                            [
-                                layer.source_image.width() as _,
-                                layer.source_image.height() as _,
-                            ],
-                            layer.source_image.to_rgba8().as_flat_samples().as_slice(),
-                        ),
-                        egui::TextureOptions::NEAREST,
-                    );
-                    let id = tex.id();
-                    layer.texture = Some(tex);
-                    id
-                };
-
-                let aligned_pos = egui::pos2(
-                    layer.transform.offset.x.round(),
-                    layer.transform.offset.y.round(),
-                );
-                let part_screen_pos = canvas_rect.min + (aligned_pos.to_vec2() * self.preview_zoom);
-                let part_w =
-                    layer.source_image.width() as f32 * layer.transform.scale * self.preview_zoom;
-                let part_h =
-                    layer.source_image.height() as f32 * layer.transform.scale * self.preview_zoom;
-
-                let part_rect =
-                    egui::Rect::from_min_size(part_screen_pos, egui::vec2(part_w, part_h));
+            if ui
+                .button("Permutation Export...")
+                .on_hover_text("Export every combination of color-labeled variants")
+                .clicked()
+            {
+                app.permutation_dialog = Some(PermutationDialog::default());
+            }
 
-                // Interaction
-                let interact_response =
-                    ui.interact(part_rect, egui::Id::new(layer.id), egui::Sense::drag());
+            let variants = app.selection.len();
+            if ui
+                .add_enabled(variants >= 2, egui::Button::new("Download GIF Preview"))
+                .on_hover_text("Cycle through the selected layers, one frame each")
+                .on_disabled_hover_text("Select the alternative layers to cycle through")
+                .clicked()
+            {
+                log::info!("Exporting a {}-frame GIF preview", variants);
+                let snapshot = app.export_snapshot();
+                let (selection, delay_ms) = (app.selection.clone(), app.gif_delay_ms);
+                let filename = format!("{}_preview.gif", app.export_stem());
+                app.start_export("GIF", move |progress| async move {
+                    let bytes = snapshot.build_gif(&selection, delay_ms, &progress).await?;
+                    Ok(vec![(filename, bytes)])
+                });
+            }
+            ui.add(
+                egui::DragValue::new(&mut app.gif_delay_ms)
+                    .range(20..=5000)
+                    .suffix(" ms"),
+            )
+            .on_hover_text("GIF frame time");
 
-                if interact_response.dragged() {
-                    dragged_id = Some(layer.id);
-                    drag_delta = interact_response.drag_delta() / self.preview_zoom;
-                    self.selected_layer_id = Some(layer.id);
-                }
-                if interact_response.clicked() {
-                    self.selected_layer_id = Some(layer.id);
-                }
+            if ui
+                .button("Download Image")
+                .on_hover_text("The composited image, in the format picked next to it")
+                .clicked()
+            {
+                app.download_composite();
+            }
+            if ui
+                .button("Preview Image...")
+                .on_hover_text("Check the composited image and its file size before saving")
+                .clicked()
+            {
+                app.export_preview = Some(ExportPreview::default());
+            }
+            app.composite_format_ui(ui);
 
-                if Some(layer.id) == self.selected_layer_id {
-                    painter.rect_stroke(
-                        part_rect,
-                        0.0,
-                        egui::Stroke::new(2.0, egui::Color32::YELLOW),
+            if ui.button("Download ZIP").clicked() {
+                let snapshot = app.export_snapshot();
+                let stem = app.export_stem();
+                let suffix = scale_suffix(app.export_scale);
+                app.start_export("ZIP", move |progress| async move {
+                    let zips = snapshot.build_layer_zips(&progress).await?;
+                    log::info!(
+                        "Exporting {} ZIP pack(s) at {}x",
+                        zips.len(),
+                        snapshot.export_scale
                     );
-                }
-
-                let mut mesh = egui::Mesh::with_texture(texture_id);
-                mesh.add_rect_with_uv(
-                    part_rect,
-                    egui::Rect::from_min_max(egui::pos2(0.0, 0.0), egui::pos2(1.0, 1.0)),
-                    egui::Color32::WHITE,
-                );
-                painter.add(mesh);
+                    let count = zips.len();
+                    Ok(zips
+                        .into_iter()
+                        .enumerate()
+                        .map(|(i, zip_buffer)| {
+                            let filename = if count > 1 {
+                                format!("{}_pack_{}{}.zip", stem, i + 1, suffix)
+                            } else {
+                                format!("{}{}.zip", stem, suffix)
+                            };
+                            (filename, zip_buffer)
+                        })
+                        .collect())
+                });
             }
+        });
 
-            if let Some(id) = dragged_id {
-                if let Some(layer) = self.layers.iter_mut().find(|l| l.id == id) {
-                    layer.transform.offset += drag_delta;
-                }
+        if app.composite_format == CompositeFormat::Png && app.palette_limit.enabled {
+            app.update_palette_preview(ui.ctx());
+            if let Some((_, colors)) = &app.palette_preview {
+                ui.horizontal_wrapped(|ui| {
+                    ui.small(format!("{} colors + transparent:", colors.len()));
+                    for &[r, g, b] in colors {
+                        let color = egui::Color32::from_rgb(r, g, b);
+                        swatch(ui, color).on_hover_text(color.to_hex());
+                    }
+                });
             }
+        }
 
-            painter.rect_stroke(
-                canvas_rect,
-                0.0,
-                egui::Stroke::new(1.0, egui::Color32::WHITE),
+        ui.horizontal(|ui| {
+            let mut split = app.zip_max_entries > 0;
+            if ui.checkbox(&mut split, "Split ZIP Every").changed() {
+                app.zip_max_entries = if split { 100 } else { 0 };
+            }
+            if split {
+                ui.add(
+                    egui::DragValue::new(&mut app.zip_max_entries)
+                        .range(1..=10_000)
+                        .suffix(" images"),
+                );
+            }
+        });
+        ui.checkbox(&mut app.export_tight, "Tight Layers")
+            .on_hover_text(
+                "Crop each layer image to its opaque pixels; data.json records the crop \
+                 position. Layers left empty are listed but not written.",
             );
+        ui.checkbox(&mut app.export_atlas, "Sprite Atlas")
+            .on_hover_text(
+                "Also pack the trimmed layers into atlas.png with a TexturePacker \
+                 JSON (Hash) atlas.json in the ZIP",
+            );
+        ui.checkbox(&mut app.export_sources, "Include Sources")
+            .on_hover_text(
+                "Also write every layer's untouched source image to sources/ in the ZIP; \
+                 data.json names each layer's source_file. Identical images are stored once.",
+            );
+        ui.horizontal(|ui| {
+            ui.checkbox(&mut app.export_godot, "Godot Resources")
+                .on_hover_text(
+                    "Also write godot/<layer>.tres AtlasTextures for the atlas regions \
+                     (adds the atlas to the ZIP)",
+                );
+            if app.export_godot {
+                ui.add(
+                    egui::TextEdit::singleline(&mut app.godot_res_prefix)
+                        .hint_text(DEFAULT_GODOT_PREFIX)
+                        .desired_width(120.0),
+                )
+                .on_hover_text("Folder of the unpacked ZIP in the Godot project");
+            }
         });
-    }
+        let renames = app.export_snapshot().export_renames();
+        if !renames.is_empty() {
+            ui.collapsing(
+                format!("⚠ {} file name(s) will be changed", renames.len()),
+                |ui| {
+                    for rename in &renames {
+                        ui.small(rename);
+                    }
+                },
+            );
+        }
+
+        if let Some(err) = &app.export_error {
+            ui.colored_label(egui::Color32::RED, err);
+        }
+
+        ui.horizontal(|ui| {
+            if ui
+                .button("Verify ZIP...")
+                .on_hover_text("Check whether this project reproduces an exported ZIP")
+                .clicked()
+            {
+                let events = app.events.clone();
+                let task = async move {
+                    if let Some(handle) = rfd::AsyncFileDialog::new()
+                        .add_filter("Kitbash Export", &["zip"])
+                        .pick_file()
+                        .await
+                    {
+                        let bytes = handle.read().await;
+                        events.send(AppEvent::Io(IoEvent::VerifyZipLoaded(bytes)));
+                    }
+                };
+
+                #[cfg(target_arch = "wasm32")]
+                wasm_bindgen_futures::spawn_local(task);
+                #[cfg(not(target_arch = "wasm32"))]
+                std::thread::spawn(move || {
+                    futures::executor::block_on(task);
+                });
+            }
+            ui.checkbox(&mut app.verify_recomposite, "Recomposite");
+        });
+        if let Some(report) = &app.verify_report {
+            for line in report {
+                ui.small(line);
+            }
+        }
+    });
 }
 
 // ----------------------------------------------------------------------------
@@ -547,7 +9136,13 @@ impl eframe::App for KitbashApp {
 
 #[cfg(not(target_arch = "wasm32"))]
 fn main() -> eframe::Result<()> {
-    env_logger::init();
+    logging::init();
+
+    if std::env::args().any(|arg| arg == "--serve-stdio") {
+        headless::serve_stdio();
+        return Ok(());
+    }
+
     let native_options = eframe::NativeOptions {
         viewport: eframe::egui::ViewportBuilder::default()
             .with_inner_size([1024.0, 768.0])
@@ -557,7 +9152,7 @@ fn main() -> eframe::Result<()> {
     eframe::run_native(
         "Kitbash",
         native_options,
-        Box::new(|_cc| Ok(Box::new(KitbashApp::default()))),
+        Box::new(|cc| Ok(Box::new(KitbashApp::restored(cc.storage)))),
     )
 }
 
@@ -565,10 +9160,8 @@ fn main() -> eframe::Result<()> {
 fn main() {
     use wasm_bindgen::JsCast;
 
-    // Redirect `log` message to `console.log` and friends:
-    eframe::WebLogger::init(log::LevelFilter::Debug).ok();
-
-    let web_options = eframe::WebOptions::default();
+    // Redirect `log` message to `console.log` and friends, and to the debug console:
+    logging::init();
 
     wasm_bindgen_futures::spawn_local(async {
         let document = web_sys::window()
@@ -581,11 +9174,59 @@ fn main() {
             .dyn_into::<web_sys::HtmlCanvasElement>()
             .expect("Element is not a canvas");
 
-        eframe::WebRunner::new()
+        let runner = eframe::WebRunner::new();
+
+        // A lost WebGL context only comes back if the loss event is cancelled
+        let on_lost = wasm_bindgen::closure::Closure::<dyn FnMut(web_sys::Event)>::new(
+            |event: web_sys::Event| event.prevent_default(),
+        );
+        // The old painter's GL objects are gone with the context, so start a new runner on
+        // the same canvas and move the document over; its textures re-upload lazily
+        let on_restored = {
+            let (runner, canvas) = (runner.clone(), canvas.clone());
+            wasm_bindgen::closure::Closure::<dyn FnMut()>::new(move || {
+                let (runner, canvas) = (runner.clone(), canvas.clone());
+                wasm_bindgen_futures::spawn_local(async move {
+                    // `start` shuts the old runner down before its first await, so the
+                    // emptied app left behind never runs a frame or saves
+                    let Some(app) = runner
+                        .app_mut::<KitbashApp>()
+                        .map(|mut app| std::mem::take(&mut *app))
+                    else {
+                        return; // Crashed or shut down, nothing to recover
+                    };
+                    GRAPHICS_CONTEXT_RESTORED.store(true, Ordering::Relaxed);
+                    if let Err(err) = runner
+                        .start(
+                            canvas,
+                            eframe::WebOptions::default(),
+                            Box::new(move |_| Ok(Box::new(app))),
+                        )
+                        .await
+                    {
+                        log::error!("Failed to restart after context loss: {:?}", err);
+                    }
+                });
+            })
+        };
+        canvas
+            .add_event_listener_with_callback("webglcontextlost", on_lost.as_ref().unchecked_ref())
+            .ok();
+        canvas
+            .add_event_listener_with_callback(
+                "webglcontextrestored",
+                on_restored.as_ref().unchecked_ref(),
+            )
+            .ok();
+        // The listeners live as long as the page
+        on_lost.forget();
+        on_restored.forget();
+
+        runner
             .start(
                 canvas,
-                web_options,
-                Box::new(|_cc| Ok(Box::new(KitbashApp::default()))),
+                eframe::WebOptions::default(),
+                Box::new(|cc| Ok(Box::new(KitbashApp::restored(cc.storage)))),
             )
             .await
             .expect("failed to start eframe");
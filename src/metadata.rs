@@ -0,0 +1,152 @@
+// ----------------------------------------------------------------------------
+// Export metadata (data.json)
+// ----------------------------------------------------------------------------
+//
+// Typed shape of the per-layer part of `data.json`. The root keeps the provenance
+// stamp (app version, export settings, content hash) next to:
+//
+//   "schema_version": 2,
+//   "layers": [ LayerMeta, ... ]   flat render list, draw order, absolute transforms
+//   "tree":   [ MetaNode, ... ]    the same layers as a hierarchy, bottom to top
+//   "markers": [ MarkerMeta, ... ] named attachment points
+//
+// Kitbash has no layer folders, so the only nesting in the tree is clipping: layers
+// clipped to a base sit in that base's `children`, with offsets relative to the base.
+// Bump `SCHEMA_VERSION` whenever a consumer would have to change how it reads the file.
+
+use serde::Serialize;
+
+/// Version 1 was the flat `layers` list without this field
+pub const SCHEMA_VERSION: u32 = 2;
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Point {
+    pub x: f32,
+    pub y: f32,
+}
+
+/// Whole-pixel offset
+#[derive(Serialize, Clone, Copy)]
+pub struct PixelOffset {
+    pub x: i32,
+    pub y: i32,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+/// Box in whole output pixels
+#[derive(Serialize, Clone, Copy)]
+pub struct PixelRect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Size {
+    pub width: u32,
+    pub height: u32,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Hsl {
+    pub hue: f32,
+    pub saturation: f32,
+    pub lightness: f32,
+}
+
+#[derive(Serialize, Clone, Copy)]
+pub struct Tone {
+    pub brightness: f32,
+    pub contrast: f32,
+    pub preserve_endpoints: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Outline {
+    pub enabled: bool,
+    pub color: String,
+    pub thickness: u32,
+    pub inside: bool,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Shadow {
+    pub enabled: bool,
+    pub offset: PixelOffset,
+    pub color: String,
+    pub opacity: f32,
+    pub include_in_bounds: bool,
+}
+
+/// Entry of the flat `layers` list
+#[derive(Serialize, Clone)]
+pub struct LayerMeta {
+    pub name: String,
+    pub file: Option<String>, // `None` when the layer isn't exported
+    pub draw_order: usize,
+    pub z_override: Option<i32>,
+    pub tint: String,
+    pub locked: bool,
+    pub visible: bool,
+    pub scale: Point,
+    pub anchor: Point,
+    pub flip_h: bool,
+    pub hsl: Hsl,
+    pub tone: Tone,
+    pub outline: Outline,
+    pub clip_to_below: bool,
+    pub label: Option<String>,
+    pub user_data: std::collections::BTreeMap<String, String>,
+    pub shadow: Shadow,
+    pub offset: Point,
+    pub bounds: Rect,
+    pub source: Size, // Source image pixels, before scaling
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub crop: Option<PixelRect>, // Tight exports: the image's box on the export canvas
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>, // Why `file` is `None` although the layer is visible
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub pack: Option<usize>, // Split exports: 1-based ZIP part holding `file`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>, // "sources/<name>.png" when sources are included
+}
+
+#[derive(Serialize, Clone)]
+#[serde(rename_all = "lowercase")]
+pub enum NodeType {
+    Image,
+}
+
+/// Entry of `markers`: a named attachment point in the same units as layer offsets
+#[derive(Serialize, Clone)]
+pub struct MarkerMeta {
+    pub name: String,
+    pub x: f32,
+    pub y: f32,
+    pub layer: Option<String>, // Name of the layer the point follows, if any
+}
+
+/// Node of the `tree`: local transform only, with clipped layers nested under their base
+#[derive(Serialize, Clone)]
+pub struct MetaNode {
+    #[serde(rename = "type")]
+    pub node_type: NodeType,
+    pub name: String,
+    pub file: Option<String>,
+    pub visible: bool,
+    pub offset: Point, // Relative to the parent node, or the canvas at the top level
+    pub scale: Point,
+    pub source: Size,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_file: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub children: Vec<MetaNode>,
+}
@@ -0,0 +1,205 @@
+// ----------------------------------------------------------------------------
+// Palette reduction (indexed PNG)
+// ----------------------------------------------------------------------------
+//
+// Median cut over the opaque colors, weighted by how many pixels use them: the box of
+// colors spanning the widest channel range is split at its weighted median until there
+// are enough boxes, and each box becomes its average color. The result only depends on
+// the pixels, so nearest-neighbor upscaling (which repeats every pixel equally) keeps the
+// same palette as the 1x composite.
+//
+// Indexed output has no partial alpha: pixels below `ALPHA_THRESHOLD` become the reserved
+// transparent entry, index 0, and everything else is treated as opaque.
+
+use image::RgbaImage;
+use std::collections::HashMap;
+
+/// Alpha below this maps to the transparent entry
+pub const ALPHA_THRESHOLD: u8 = 128;
+
+/// Most colors besides the transparent entry (an 8-bit palette holds 256)
+pub const MAX_COLORS: usize = 255;
+
+#[derive(Clone, Copy, Default, PartialEq)]
+pub enum Dither {
+    #[default]
+    None,
+    Ordered,
+    FloydSteinberg,
+}
+
+impl Dither {
+    pub const ALL: [Self; 3] = [Self::None, Self::Ordered, Self::FloydSteinberg];
+
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::None => "No Dithering",
+            Self::Ordered => "Ordered",
+            Self::FloydSteinberg => "Floyd-Steinberg",
+        }
+    }
+}
+
+/// Range of `channel` within a box
+fn channel_range(colors: &[([u8; 3], u64)], channel: usize) -> u8 {
+    let (min, max) = colors.iter().fold((255, 0), |(min, max), (c, _)| {
+        (c[channel].min(min), c[channel].max(max))
+    });
+    max.saturating_sub(min)
+}
+
+/// Up to `colors` colors for the opaque pixels of `img`; empty when nothing is opaque
+pub fn median_cut(img: &RgbaImage, colors: usize) -> Vec<[u8; 3]> {
+    let mut histogram: HashMap<[u8; 3], u64> = HashMap::new();
+    for p in img.pixels().filter(|p| p[3] >= ALPHA_THRESHOLD) {
+        *histogram.entry([p[0], p[1], p[2]]).or_default() += 1;
+    }
+    let mut entries: Vec<_> = histogram.into_iter().collect();
+    if entries.is_empty() {
+        return Vec::new();
+    }
+    // HashMap order is random, the palette must not be
+    entries.sort_unstable();
+
+    let mut boxes = vec![entries];
+    while boxes.len() < colors.clamp(1, MAX_COLORS) {
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|(_, colors)| colors.len() > 1)
+            .flat_map(|(i, colors)| (0..3).map(move |ch| (channel_range(colors, ch), i, ch)))
+            .max_by_key(|&(range, i, ch)| (range, std::cmp::Reverse((i, ch))));
+        let Some((_, i, channel)) = widest else {
+            break; // Every box is down to one color
+        };
+        let mut lower = boxes.remove(i);
+        lower.sort_by_key(|(c, _)| c[channel]);
+        let total: u64 = lower.iter().map(|(_, n)| n).sum();
+        let mut seen = 0;
+        let median = lower
+            .iter()
+            .position(|(_, n)| {
+                seen += n;
+                seen * 2 >= total
+            })
+            .unwrap_or(0);
+        let upper = lower.split_off((median + 1).min(lower.len() - 1));
+        boxes.insert(i, upper);
+        boxes.insert(i, lower);
+    }
+
+    boxes
+        .iter()
+        .map(|colors| {
+            let total: u64 = colors.iter().map(|(_, n)| n).sum();
+            let mean = |ch: usize| {
+                let sum: u64 = colors.iter().map(|(c, n)| c[ch] as u64 * n).sum();
+                ((sum + total / 2) / total) as u8
+            };
+            [mean(0), mean(1), mean(2)]
+        })
+        .collect()
+}
+
+/// Closest palette entry by squared RGB distance, as a 1-based index
+fn nearest(palette: &[[u8; 3]], rgb: [u8; 3], cache: &mut HashMap<[u8; 3], u8>) -> u8 {
+    *cache.entry(rgb).or_insert_with(|| {
+        let distance = |p: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|ch| (p[ch] as i32 - rgb[ch] as i32).pow(2))
+                .sum()
+        };
+        let best = (0..palette.len())
+            .min_by_key(|&i| distance(&palette[i]))
+            .unwrap_or(0);
+        best as u8 + 1
+    })
+}
+
+/// Palette index of every pixel of `img`: 0 for transparent, `i + 1` for `palette[i]`
+pub fn remap(img: &RgbaImage, palette: &[[u8; 3]], dither: Dither) -> Vec<u8> {
+    const BAYER: [[f32; 4]; 4] = [
+        [0.0, 8.0, 2.0, 10.0],
+        [12.0, 4.0, 14.0, 6.0],
+        [3.0, 11.0, 1.0, 9.0],
+        [15.0, 7.0, 13.0, 5.0],
+    ];
+    let (width, height) = img.dimensions();
+    let mut cache = HashMap::new();
+    let mut indices = vec![0; (width * height) as usize];
+    if palette.is_empty() {
+        return indices;
+    }
+    let to_u8 = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    // Ordered dithering nudges colors by about the gap between palette entries
+    let spread = 256.0 / (palette.len() as f32).cbrt();
+    // Floyd-Steinberg keeps the error carried into each pixel, never into transparent ones
+    let mut carried = vec![[0.0f32; 3]; (width * height) as usize];
+
+    for y in 0..height {
+        for x in 0..width {
+            let i = (y * width + x) as usize;
+            let p = img.get_pixel(x, y);
+            if p[3] < ALPHA_THRESHOLD {
+                continue;
+            }
+            let mut rgb = [p[0] as f32, p[1] as f32, p[2] as f32];
+            match dither {
+                Dither::None => {}
+                Dither::Ordered => {
+                    let offset = (BAYER[(y % 4) as usize][(x % 4) as usize] + 0.5) / 16.0 - 0.5;
+                    rgb.iter_mut().for_each(|c| *c += offset * spread);
+                }
+                Dither::FloydSteinberg => {
+                    (0..3).for_each(|ch| rgb[ch] += carried[i][ch]);
+                }
+            }
+            let index = nearest(palette, rgb.map(to_u8), &mut cache);
+            indices[i] = index;
+
+            if dither == Dither::FloydSteinberg {
+                let chosen = palette[index as usize - 1];
+                let error: [f32; 3] = std::array::from_fn(|ch| rgb[ch] - chosen[ch] as f32);
+                let x = x as i64;
+                for (dx, dy, weight) in [(1, 0, 7.0), (-1, 1, 3.0), (0, 1, 5.0), (1, 1, 1.0)] {
+                    let (nx, ny) = (x + dx, y as i64 + dy);
+                    if nx < 0 || nx >= width as i64 || ny >= height as i64 {
+                        continue;
+                    }
+                    if img.get_pixel(nx as u32, ny as u32)[3] < ALPHA_THRESHOLD {
+                        continue;
+                    }
+                    let n = (ny * width as i64 + nx) as usize;
+                    (0..3).for_each(|ch| carried[n][ch] += error[ch] * weight / 16.0);
+                }
+            }
+        }
+    }
+    indices
+}
+
+/// 8-bit indexed PNG: entry 0 fully transparent, then `palette`, with `text` as a
+/// `tEXt` chunk (keyword, Latin-1 text)
+pub fn encode_png(
+    width: u32,
+    height: u32,
+    palette: &[[u8; 3]],
+    indices: &[u8],
+    text: (&str, String),
+) -> Result<Vec<u8>, png::EncodingError> {
+    let mut plte = vec![0; 3];
+    plte.extend(palette.iter().flatten());
+    let mut bytes = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut bytes, width, height);
+        encoder.set_color(png::ColorType::Indexed);
+        encoder.set_depth(png::BitDepth::Eight);
+        encoder.set_palette(plte);
+        encoder.set_trns(vec![0]); // Entries past the tRNS list stay opaque
+        encoder.add_text_chunk(text.0.to_owned(), text.1)?;
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(indices)?;
+        writer.finish()?;
+    }
+    Ok(bytes)
+}
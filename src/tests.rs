@@ -0,0 +1,1328 @@
+use super::*;
+
+fn layer(id: u64) -> LayerImage {
+    LayerImage::new(
+        id,
+        format!("layer {id}"),
+        image::DynamicImage::new_rgba8(1, 1),
+    )
+}
+
+fn layers_with_z(z: &[Option<i32>]) -> Vec<LayerImage> {
+    z.iter()
+        .enumerate()
+        .map(|(i, &z)| LayerImage {
+            z_override: z,
+            ..layer(i as u64)
+        })
+        .collect()
+}
+
+#[test]
+fn draw_order_keeps_list_order_without_overrides() {
+    let layers = layers_with_z(&[None, None, None]);
+    assert_eq!(draw_order(&layers), vec![0, 1, 2]);
+}
+
+#[test]
+fn draw_order_moves_overrides_around_the_rest() {
+    let layers = layers_with_z(&[Some(1), None, Some(-1), None]);
+    assert_eq!(draw_order(&layers), vec![2, 1, 3, 0]);
+}
+
+#[test]
+fn draw_order_is_stable_for_equal_overrides() {
+    let layers = layers_with_z(&[Some(2), Some(-3), Some(2), Some(-3)]);
+    assert_eq!(draw_order(&layers), vec![1, 3, 0, 2]);
+}
+
+#[test]
+fn draw_order_treats_no_override_as_zero() {
+    // An explicit 0 ties with unset layers, so list position decides between them
+    let layers = layers_with_z(&[Some(0), None, Some(0), None]);
+    assert_eq!(draw_order(&layers), vec![0, 1, 2, 3]);
+}
+
+#[test]
+fn settings_profile_round_trips_export_settings() {
+    let mut app = KitbashApp {
+        export_tight: true,
+        zip_max_entries: 40,
+        export_sources: true,
+        export_filename_template: "{name}_{scale}x".to_owned(),
+        export_background: ExportBackground {
+            transparent: false,
+            solid: true,
+            color: egui::Color32::from_rgb(12, 34, 56),
+        },
+        ..Default::default()
+    };
+    let json = serde_json::to_string(&app.settings_profile()).unwrap();
+
+    let mut other = KitbashApp::default();
+    let pending = other.review_profile(serde_json::from_str(&json).unwrap());
+    assert!(pending.warnings.is_empty());
+    assert!(pending
+        .changes
+        .iter()
+        .any(|c| c == "Export background: canvas color → #0c2238ff"));
+    other.apply_profile(pending.profile);
+
+    assert!(other.export_tight);
+    assert_eq!(other.zip_max_entries, 40);
+    assert!(other.export_sources);
+    assert_eq!(other.export_filename_template, "{name}_{scale}x");
+    assert!(other.export_background == app.export_background);
+}
+
+#[test]
+fn backup_path_appends_timestamp_and_collision() {
+    let path = std::path::Path::new("out/pack.zip");
+    assert_eq!(
+        backup_path(path, 42, 0),
+        std::path::Path::new("out/pack.zip.bak-0000000000042")
+    );
+    assert_eq!(
+        backup_path(path, 42, 3),
+        std::path::Path::new("out/pack.zip.bak-0000000000042-3")
+    );
+    assert_eq!(parse_backup_stamp("0000000000042-3"), Some((42, 3)));
+    assert_eq!(parse_backup_stamp("0000000000042"), Some((42, 0)));
+    assert_eq!(parse_backup_stamp("42-"), None);
+    assert_eq!(parse_backup_stamp("old"), None);
+}
+
+/// Fresh directory under the system temp dir, removed again by the caller
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("kitbash-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn backups_in_the_same_millisecond_do_not_clobber() {
+    let dir = scratch_dir("backup-collision");
+    let path = dir.join("pack.zip");
+    for content in ["first", "second", "third"] {
+        std::fs::write(&path, content).unwrap();
+        backup_file_at(&path, 10, 7).unwrap();
+        assert!(!path.exists());
+    }
+
+    let backups = list_backups(&path).unwrap();
+    let contents: Vec<_> = backups
+        .iter()
+        .map(|p| std::fs::read_to_string(p).unwrap())
+        .collect();
+    assert_eq!(contents, ["first", "second", "third"]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn backups_are_pruned_to_the_newest() {
+    let dir = scratch_dir("backup-prune");
+    let path = dir.join("pack.zip");
+    std::fs::write(dir.join("pack.zip.bak-notes"), "unrelated").unwrap();
+    for (i, timestamp_ms) in [5, 9, 9, 12].into_iter().enumerate() {
+        std::fs::write(&path, i.to_string()).unwrap();
+        backup_file_at(&path, 2, timestamp_ms).unwrap();
+    }
+
+    let kept: Vec<_> = list_backups(&path)
+        .unwrap()
+        .iter()
+        .map(|p| std::fs::read_to_string(p).unwrap())
+        .collect();
+    assert_eq!(kept, ["2", "3"]);
+    assert!(dir.join("pack.zip.bak-notes").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn encode_png_stores_the_stamp_as_text() {
+    let img = RgbaImage::from_fn(3, 2, |x, y| Rgba([x as u8 * 80, y as u8 * 120, 7, 255]));
+    let bytes = encode_png(&img, r#"{"content_hash":"ü"}"#).unwrap();
+
+    let decoder = png::Decoder::new(Cursor::new(&bytes));
+    let reader = decoder.read_info().unwrap();
+    let text = &reader.info().uncompressed_latin1_text;
+    assert_eq!(text.len(), 1);
+    assert_eq!(text[0].keyword, PNG_TEXT_KEYWORD);
+    assert_eq!(text[0].text, r#"{"content_hash":"?"}"#);
+    assert_eq!(image::load_from_memory(&bytes).unwrap().to_rgba8(), img);
+}
+
+#[test]
+fn content_hash_tells_color_types_apart() {
+    let rgb = image::DynamicImage::ImageRgb8(image::RgbImage::from_pixel(2, 2, image::Rgb([9; 3])));
+    let rgba = image::DynamicImage::ImageRgba8(rgb.to_rgba8());
+    let hash = |img: &image::DynamicImage| {
+        let app = KitbashApp {
+            canvas_size: [2, 2],
+            layers: vec![LayerImage::new(0, "layer".to_owned(), img.clone())],
+            ..Default::default()
+        };
+        app.export_snapshot().content_hash()
+    };
+    assert_eq!(hash(&rgb), hash(&rgb));
+    assert_ne!(hash(&rgb), hash(&rgba));
+}
+
+fn progress() -> ExportProgress {
+    ExportProgress {
+        events: EventQueue::default(),
+        cancel: Arc::default(),
+    }
+}
+
+/// Every entry of a ZIP by name
+fn layer_zips(snapshot: &CompositeSnapshot) -> Vec<Vec<u8>> {
+    futures::executor::block_on(snapshot.build_layer_zips(&progress())).unwrap()
+}
+
+fn zip_entries(bytes: &[u8]) -> BTreeMap<String, Vec<u8>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes)).unwrap();
+    (0..archive.len())
+        .map(|i| {
+            let mut file = archive.by_index(i).unwrap();
+            let mut data = Vec::new();
+            std::io::Read::read_to_end(&mut file, &mut data).unwrap();
+            (file.name().to_owned(), data)
+        })
+        .collect()
+}
+
+fn solid_layer(id: u64, size: u32, color: [u8; 4]) -> LayerImage {
+    let img = RgbaImage::from_pixel(size, size, Rgba(color));
+    LayerImage::new(
+        id,
+        format!("layer{id}"),
+        image::DynamicImage::ImageRgba8(img),
+    )
+}
+
+#[test]
+fn layers_clipped_to_a_hidden_base_are_skipped() {
+    let app = KitbashApp {
+        canvas_size: [4, 4],
+        layers: vec![
+            LayerImage {
+                visible: false,
+                ..solid_layer(0, 4, [255, 0, 0, 255])
+            },
+            LayerImage {
+                clip_to_below: true,
+                ..solid_layer(1, 4, [0, 255, 0, 255])
+            },
+            solid_layer(2, 2, [0, 0, 255, 255]),
+        ],
+        ..Default::default()
+    };
+    let zips = layer_zips(&app.export_snapshot());
+    let entries = zip_entries(&zips[0]);
+    let data: serde_json::Value = serde_json::from_slice(&entries["data.json"]).unwrap();
+    let layers = data["layers"].as_array().unwrap();
+
+    assert_eq!(layers[0]["file"], serde_json::Value::Null);
+    assert!(
+        layers[0].get("skipped").is_none(),
+        "hidden layers aren't skipped"
+    );
+    assert_eq!(layers[1]["file"], serde_json::Value::Null);
+    assert_eq!(layers[1]["skipped"], "clip base hidden");
+    assert_eq!(layers[2]["file"], "2_layer2.png");
+    let names: Vec<_> = entries.keys().map(String::as_str).collect();
+    assert_eq!(names, ["2_layer2.png", "data.json"]);
+}
+
+fn pixelate(size: u32) -> OutputEffects {
+    OutputEffects {
+        pixelate: size,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn pixelate_averages_each_block() {
+    let mut img = RgbaImage::from_fn(4, 2, |x, _| Rgba([x as u8 * 10, 100, 200, 255]));
+    pixelate(2).apply(&mut img, 1);
+    let expected = RgbaImage::from_fn(4, 2, |x, _| {
+        Rgba([if x < 2 { 5 } else { 25 }, 100, 200, 255])
+    });
+    assert_eq!(img, expected);
+}
+
+#[test]
+fn pixelate_weights_color_by_alpha() {
+    // Half-transparent block: the transparent black pixels must not darken the red
+    let mut img = RgbaImage::from_fn(2, 2, |x, _| {
+        if x == 0 {
+            Rgba([255, 0, 0, 255])
+        } else {
+            Rgba([0, 0, 0, 0])
+        }
+    });
+    pixelate(2).apply(&mut img, 1);
+    assert_eq!(img, RgbaImage::from_pixel(2, 2, Rgba([255, 0, 0, 127])));
+
+    let mut empty = RgbaImage::new(2, 2);
+    pixelate(2).apply(&mut empty, 1);
+    assert_eq!(empty, RgbaImage::new(2, 2));
+}
+
+#[test]
+fn pixelate_clips_blocks_at_the_edge() {
+    let mut img = RgbaImage::from_fn(3, 3, |x, y| Rgba([(x + 3 * y) as u8 * 10, 0, 0, 255]));
+    pixelate(2).apply(&mut img, 1);
+    let values: Vec<u8> = img.pixels().map(|p| p[0]).collect();
+    #[rustfmt::skip]
+    assert_eq!(values, [
+        20, 20, 35,
+        20, 20, 35,
+        65, 65, 80,
+    ]);
+}
+
+#[test]
+fn pixelate_blocks_grow_with_export_scale() {
+    // A 2px block at 2x covers 4 output pixels, matching the upscaled 1x result
+    let source = RgbaImage::from_fn(4, 1, |x, _| Rgba([x as u8 * 20, 0, 0, 255]));
+    let mut at_1x = source.clone();
+    pixelate(2).apply(&mut at_1x, 1);
+    let mut at_2x = upscale_nearest(source, 2);
+    pixelate(2).apply(&mut at_2x, 2);
+    assert_eq!(at_2x, upscale_nearest(at_1x, 2));
+}
+
+fn posterize(levels: u32) -> OutputEffects {
+    OutputEffects {
+        posterize: levels,
+        ..Default::default()
+    }
+}
+
+#[test]
+fn posterize_rounds_channels_to_even_levels() {
+    let source = RgbaImage::from_fn(4, 1, |x, _| {
+        let c = [0, 63, 64, 200][x as usize];
+        Rgba([c, c, c, 77])
+    });
+    let channels = |levels| {
+        let mut img = source.clone();
+        posterize(levels).apply(&mut img, 1);
+        assert!(img
+            .pixels()
+            .all(|p| p[0] == p[1] && p[1] == p[2] && p[3] == 77));
+        img.pixels().map(|p| p[0]).collect::<Vec<u8>>()
+    };
+    assert_eq!(channels(2), [0, 0, 0, 255]);
+    assert_eq!(channels(3), [0, 0, 128, 255]);
+    assert_eq!(channels(5), [0, 64, 64, 191]);
+    // One level would flatten everything, so it counts as off
+    assert_eq!(channels(1), [0, 63, 64, 200]);
+}
+
+#[test]
+fn posterize_runs_after_pixelate() {
+    // Posterizing first would give (0 + 128) / 2 = 64 instead
+    let mut img = RgbaImage::from_fn(2, 1, |x, _| Rgba([[60, 140][x as usize], 255, 0, 255]));
+    OutputEffects {
+        pixelate: 2,
+        ..posterize(3)
+    }
+    .apply(&mut img, 1);
+    assert_eq!(img, RgbaImage::from_pixel(2, 1, Rgba([128, 255, 0, 255])));
+}
+
+#[test]
+fn composite_key_follows_source_revisions() {
+    let mut app = KitbashApp {
+        canvas_size: [4, 4],
+        layers: vec![solid_layer(0, 2, [1, 2, 3, 255])],
+        ..Default::default()
+    };
+    let key = app.composite_key();
+    let duplicate = app.layers[0].clone();
+    assert_eq!(duplicate.source_revision, app.layers[0].source_revision);
+
+    // Same pixels in a new image still count as a change; the key can't know they match
+    let same_pixels = (*app.layers[0].source_image).clone();
+    app.layers[0].replace_source(same_pixels, false);
+    assert_ne!(app.composite_key(), key);
+}
+
+#[test]
+fn entry_stems_are_nfc_normalized() {
+    let decomposed = "cafe\u{301}";
+    assert_eq!(sanitize_entry_stem(decomposed), "caf\u{e9}");
+
+    // Both spellings of the same name must not land on one file
+    let (names, _) = entry_names(&["caf\u{e9}".to_owned(), decomposed.to_owned()], "png");
+    assert_eq!(names, ["caf\u{e9}.png", "caf\u{e9}_2.png"]);
+}
+
+#[test]
+fn windows_device_names_get_a_suffix() {
+    assert_eq!(sanitize_entry_stem("CON"), "CON_");
+    assert_eq!(sanitize_entry_stem("nul "), "nul_");
+    assert_eq!(sanitize_entry_stem("lpt9.old"), "lpt9_.old");
+    assert_eq!(sanitize_entry_stem("Aux .bak"), "Aux_ .bak");
+    assert_eq!(sanitize_entry_stem("COM10"), "COM10");
+    assert_eq!(sanitize_entry_stem("console"), "console");
+    assert_eq!(sanitize_entry_stem("com0"), "com0");
+}
+
+/// Fully transparent pixels carry no color
+fn normalize_transparent(mut img: RgbaImage) -> RgbaImage {
+    for pixel in img.pixels_mut().filter(|p| p[3] == 0) {
+        *pixel = Rgba([0; 4]);
+    }
+    img
+}
+
+#[test]
+fn tight_parts_reassemble_into_the_composite() {
+    let at = |mut layer: LayerImage, x: f32, y: f32| {
+        layer.transform.offset = egui::vec2(x, y);
+        layer
+    };
+    let faint = RgbaImage::from_pixel(1, 1, Rgba([200, 10, 10, 3]));
+    let app = KitbashApp {
+        canvas_size: [8, 8],
+        export_scale: 2,
+        export_tight: true,
+        output_effects: OutputEffects {
+            pixelate: 4,
+            posterize: 4,
+            ..Default::default()
+        },
+        layers: vec![
+            at(solid_layer(0, 4, [10, 120, 250, 255]), 0.0, 0.0),
+            at(solid_layer(1, 2, [250, 200, 0, 128]), 5.0, 1.0),
+            // Averaged over its 4x4 block, a single faint pixel becomes fully transparent
+            at(
+                LayerImage::new(
+                    2,
+                    "faint".to_owned(),
+                    image::DynamicImage::ImageRgba8(faint),
+                ),
+                1.0,
+                5.0,
+            ),
+        ],
+        ..Default::default()
+    };
+    let snapshot = app.export_snapshot();
+    let zips = layer_zips(&snapshot);
+    let entries = zip_entries(&zips[0]);
+    let data: serde_json::Value = serde_json::from_slice(&entries["data.json"]).unwrap();
+    let layers = data["layers"].as_array().unwrap();
+    assert_eq!(layers[2]["file"], serde_json::Value::Null);
+    assert_eq!(layers[2]["skipped"], "empty");
+
+    let mut reassembled = RgbaImage::new(16, 16);
+    for layer in layers.iter().filter(|l| !l["file"].is_null()) {
+        let part = image::load_from_memory(&entries[layer["file"].as_str().unwrap()])
+            .unwrap()
+            .to_rgba8();
+        let crop = &layer["crop"];
+        assert_eq!(crop["width"], part.width());
+        assert_eq!(crop["height"], part.height());
+        let (x, y) = (crop["x"].as_i64().unwrap(), crop["y"].as_i64().unwrap());
+        image::imageops::overlay(&mut reassembled, &part, x, y);
+    }
+
+    let composite = composite_image(
+        app.canvas_size,
+        &app.layers,
+        app.export_scale,
+        egui::Color32::TRANSPARENT,
+        &app.output_effects,
+    );
+    assert_eq!(
+        normalize_transparent(reassembled),
+        normalize_transparent(composite)
+    );
+}
+
+#[test]
+fn export_estimate_lists_what_contributes() {
+    let mut hidden = solid_layer(1, 2, [0, 0, 0, 255]);
+    hidden.visible = false;
+    let mut outside = solid_layer(2, 2, [0, 0, 0, 255]);
+    outside.transform.offset = egui::vec2(50.0, 0.0);
+    let mut reference = solid_layer(3, 2, [0, 0, 0, 255]);
+    reference.is_reference = true;
+    let app = KitbashApp {
+        canvas_size: [8, 4],
+        export_scale: 3,
+        layers: vec![
+            solid_layer(0, 2, [0, 0, 0, 255]),
+            hidden,
+            outside,
+            reference,
+            solid_layer(4, 5, [0, 0, 0, 255]),
+        ],
+        ..Default::default()
+    };
+
+    let estimate = app.estimate_export();
+    assert_eq!(estimate.contributing, 2);
+    assert_eq!(estimate.total, 5);
+    assert_eq!(estimate.largest_source, Some([5, 5]));
+    assert_eq!(estimate.output_size, [24, 12]);
+    assert_eq!(estimate.png_bytes, None);
+    assert_eq!(estimate.html_bytes, None);
+    assert_eq!(
+        estimate.skipped,
+        [
+            "layer1: hidden",
+            "layer2: outside the canvas",
+            "layer3: reference"
+        ]
+    );
+
+    // The file sizes are heuristics; they have to land near what the exports really write
+    let gradient = RgbaImage::from_fn(48, 40, |x, y| Rgba([(x * 5) as u8, (y * 6) as u8, 90, 255]));
+    let mut sprite = solid_layer(1, 12, [250, 200, 20, 255]);
+    sprite.transform.offset = egui::vec2(20.0, 10.0);
+    for scale in [1, 2, 4] {
+        let app = KitbashApp {
+            canvas_size: [64, 48],
+            export_scale: scale,
+            layers: vec![
+                LayerImage::new(
+                    0,
+                    "gradient".to_owned(),
+                    image::DynamicImage::ImageRgba8(gradient.clone()),
+                ),
+                sprite.clone(),
+            ],
+            ..Default::default()
+        };
+        let snapshot = app.export_snapshot();
+        let estimated = futures::executor::block_on(snapshot.estimate_png_bytes());
+        let actual = encode_png(&snapshot.composite(), "").unwrap().len();
+        assert!(
+            estimated * 4 >= actual * 3 && estimated * 4 <= actual * 5,
+            "at {scale}x: estimated {estimated}, wrote {actual}"
+        );
+        let estimated = futures::executor::block_on(snapshot.estimate_html_viewer_bytes());
+        let actual = futures::executor::block_on(snapshot.build_html_viewer(&progress()))
+            .unwrap()
+            .len();
+        assert!(
+            estimated * 4 >= actual * 3 && estimated * 4 <= actual * 5,
+            "HTML at {scale}x: estimated {estimated}, wrote {actual}"
+        );
+    }
+}
+
+#[test]
+fn jpeg_composites_on_the_matte_and_records_it() {
+    let app = KitbashApp {
+        canvas_size: [8, 8],
+        bg_color: egui::Color32::TRANSPARENT,
+        composite_format: CompositeFormat::Jpeg,
+        jpeg_matte: egui::Color32::from_rgb(0, 0, 255),
+        ..Default::default()
+    };
+    let snapshot = app.composite_snapshot();
+    assert_eq!(snapshot.bg_color, app.jpeg_matte);
+    let stamp = snapshot.export_stamp();
+    assert_eq!(stamp["matte"], "#0000ffff");
+    assert_eq!(stamp["export_settings"]["composite_format"], "jpg");
+    assert_eq!(stamp["export_settings"]["palette_limit"]["enabled"], false);
+    assert_eq!(
+        stamp["export_settings"]["zip_max_entries"],
+        app.zip_max_entries
+    );
+    assert!(stamp.get("viewer_mode").is_none());
+    assert!(app.export_snapshot().export_stamp().get("matte").is_none());
+
+    let jpeg = encode_composite(
+        &snapshot.composite(),
+        CompositeFormat::Jpeg,
+        95,
+        PaletteLimit::default(),
+        app.jpeg_matte,
+        &stamp.to_string(),
+    )
+    .unwrap();
+    let decoded = image::load_from_memory(&jpeg).unwrap().to_rgb8();
+    for pixel in decoded.pixels() {
+        assert!(
+            pixel[0] < 8 && pixel[1] < 8 && pixel[2] > 247,
+            "{:?}",
+            pixel
+        );
+    }
+
+    // The stamp sits in a COM segment right after the JFIF header
+    let comment = stamp.to_string();
+    let at = jpeg
+        .windows(2)
+        .position(|w| w == [0xFF, 0xFE])
+        .expect("comment segment");
+    let len = u16::from_be_bytes([jpeg[at + 2], jpeg[at + 3]]) as usize;
+    assert_eq!(&jpeg[at + 4..at + 2 + len], comment.as_bytes());
+}
+
+#[test]
+fn export_preview_shows_the_limited_palette_and_saved_size() {
+    let gradient = RgbaImage::from_fn(16, 16, |x, y| Rgba([x as u8 * 16, y as u8 * 16, 60, 255]));
+    let app = KitbashApp {
+        canvas_size: [16, 16],
+        export_scale: 2,
+        palette_limit: PaletteLimit {
+            enabled: true,
+            colors: 4,
+            ..Default::default()
+        },
+        layers: vec![LayerImage::new(0, "gradient".to_owned(), gradient.into())],
+        ..Default::default()
+    };
+    let snapshot = app.composite_snapshot();
+    let (img, encoded_bytes) = futures::executor::block_on(snapshot.export_preview(app.jpeg_matte));
+    assert_eq!(img.dimensions(), (16, 16));
+    assert!(img.pixels().collect::<HashSet<_>>().len() <= 4);
+    let saved = encode_composite(
+        &snapshot.composite(),
+        CompositeFormat::Png,
+        app.jpeg_quality,
+        app.palette_limit,
+        app.jpeg_matte,
+        &snapshot.export_stamp().to_string(),
+    )
+    .unwrap();
+    assert_eq!(encoded_bytes, saved.len());
+}
+
+fn doc(layers: Vec<LayerImage>) -> DocSnapshot {
+    DocSnapshot {
+        layers,
+        canvas_size: [4, 4],
+        bg_color: egui::Color32::WHITE,
+        notes: ProjectNotes::default(),
+        guides: Vec::new(),
+        markers: Vec::new(),
+    }
+}
+
+fn undo_entry(label: &str, layers: Vec<LayerImage>) -> UndoEntry {
+    UndoEntry {
+        label: label.to_owned(),
+        state: doc(layers),
+    }
+}
+
+#[test]
+fn history_charges_shared_images_to_the_newest_holder() {
+    let a = solid_layer(0, 1, [255, 0, 0, 255]); // 4 bytes, also in the document
+    let b = solid_layer(1, 2, [0, 255, 0, 255]); // 16 bytes, in both undo entries
+    let c = solid_layer(2, 3, [0, 0, 255, 255]); // 36 bytes
+    let d = solid_layer(3, 4, [255, 255, 0, 255]); // 64 bytes, redo only
+    let history = History {
+        undo: vec![
+            undo_entry("old", vec![a.clone(), b.clone()]),
+            undo_entry("new", vec![b.clone(), c.clone()]),
+        ],
+        redo: vec![undo_entry("redo", vec![a.clone(), d.clone(), b.clone()])],
+        baseline: Some(doc(vec![a.clone()])),
+        transaction: None,
+    };
+    let (per_entry, total) = history.image_bytes(&[a]);
+    assert_eq!(per_entry, vec![0, 36]);
+    assert_eq!(total, 36 + 64 + 16);
+}
+
+#[test]
+fn budget_eviction_drops_oldest_and_keeps_the_rest_intact() {
+    let current = vec![solid_layer(0, 1, [1, 2, 3, 255])];
+    let layers: Vec<LayerImage> = (1..=4)
+        .map(|i| solid_layer(i, 4, [i as u8 * 40, 0, 0, 255])) // 64 bytes each
+        .collect();
+    // Each entry holds its own image plus the one the next entry also holds
+    let mut history = History {
+        undo: (0..4)
+            .map(|i| {
+                let mut held = vec![layers[i].clone()];
+                if i + 1 < 4 {
+                    held.push(layers[i + 1].clone());
+                }
+                undo_entry(&format!("step {i}"), held)
+            })
+            .collect(),
+        ..Default::default()
+    };
+    let (per_entry, total) = history.image_bytes(&current);
+    assert_eq!(per_entry, vec![64, 64, 64, 64]);
+    assert_eq!(total, 256);
+
+    let kept: Vec<_> = history.undo[2..]
+        .iter()
+        .map(|e| {
+            let layers = e
+                .state
+                .layers
+                .iter()
+                .map(|l| {
+                    (
+                        l.id,
+                        Arc::as_ptr(&l.source_image),
+                        l.source_image.as_bytes().to_vec(),
+                    )
+                })
+                .collect::<Vec<_>>();
+            (e.label.clone(), layers)
+        })
+        .collect();
+    history.enforce_budget(&current, 150);
+    assert_eq!(history.undo.len(), 2);
+    let (per_entry, total) = history.image_bytes(&current);
+    assert_eq!(per_entry, vec![64, 64]);
+    assert_eq!(total, 128);
+
+    // Eviction never corrupts remaining entries: same layers, same shared images, same pixels
+    for (entry, (label, layers)) in history.undo.iter().zip(&kept) {
+        assert_eq!(&entry.label, label);
+        assert_eq!(entry.state.layers.len(), layers.len());
+        for (l, (id, ptr, bytes)) in entry.state.layers.iter().zip(layers) {
+            assert_eq!(l.id, *id);
+            assert_eq!(Arc::as_ptr(&l.source_image), *ptr);
+            assert_eq!(l.source_image.as_bytes(), &bytes[..]);
+        }
+    }
+    // The survivors still share the image they held in common
+    assert!(Arc::ptr_eq(
+        &history.undo[0].state.layers[1].source_image,
+        &history.undo[1].state.layers[0].source_image
+    ));
+}
+
+#[test]
+fn budget_eviction_always_keeps_the_newest_step() {
+    let current = Vec::new();
+    let mut history = History {
+        undo: vec![
+            undo_entry("old", vec![solid_layer(0, 4, [9, 9, 9, 255])]),
+            undo_entry("new", vec![solid_layer(1, 4, [7, 7, 7, 255])]),
+        ],
+        ..Default::default()
+    };
+    history.enforce_budget(&current, 0);
+    assert_eq!(history.undo.len(), 1);
+    assert_eq!(history.undo[0].label, "new");
+    assert_eq!(history.image_bytes(&current), (vec![64], 64));
+}
+
+fn layer_ids(app: &KitbashApp) -> Vec<u64> {
+    app.layers.iter().map(|l| l.id).collect()
+}
+
+#[test]
+fn allocate_id_skips_past_loaded_layers() {
+    // Layers restored in bulk (undo, document switch, load) don't touch the counter
+    let mut app = KitbashApp::default();
+    app.restore(doc(vec![layer(5), layer(9), layer(2)]));
+    assert_eq!(app.next_id, 0);
+    assert_eq!(app.allocate_id(), 10);
+    assert_eq!(app.allocate_id(), 11);
+
+    // Loading older ids never moves the counter back
+    app.restore(doc(vec![layer(1)]));
+    app.fix_next_id();
+    assert_eq!(app.next_id, 12);
+}
+
+#[test]
+fn duplicating_and_pasting_layers_keeps_ids_unique() {
+    let mut app = KitbashApp {
+        layers: vec![layer(0), layer(1), layer(2)],
+        ..Default::default()
+    };
+    let copy = app.duplicate_layer(1).unwrap();
+    assert_eq!(copy, 3);
+    assert!(Arc::ptr_eq(
+        &app.layers[1].source_image,
+        &app.layers[2].source_image
+    ));
+
+    app.selection = [0, 1, copy].into_iter().collect();
+    app.copy_layers();
+    app.paste_layers();
+    app.paste_layers();
+    let ids = layer_ids(&app);
+    assert_eq!(ids.len(), 10);
+    assert_eq!(ids.iter().collect::<HashSet<_>>().len(), ids.len());
+    assert_eq!(duplicate_layer_id(&app.layers), None);
+    // The second paste is selected, with ids of its own
+    assert_eq!(app.selection, [7, 8, 9].into_iter().collect());
+}
+
+#[test]
+fn dedup_reassigns_later_duplicates() {
+    let mut app = KitbashApp {
+        layers: vec![layer(4), layer(1), layer(4), layer(1), layer(4)],
+        ..Default::default()
+    };
+    assert_eq!(duplicate_layer_id(&app.layers), Some(4));
+    app.dedup_layer_ids();
+    // The first holder keeps its id, later ones get fresh ids past the highest in use
+    assert_eq!(layer_ids(&app), vec![4, 1, 5, 6, 7]);
+    assert_eq!(app.next_id, 8);
+    assert_eq!(duplicate_layer_id(&app.layers), None);
+    assert_eq!(app.allocate_id(), 8);
+}
+
+fn undo_labels(app: &KitbashApp) -> Vec<&str> {
+    app.history.undo.iter().map(|e| e.label.as_str()).collect()
+}
+
+#[test]
+fn deleting_a_multi_selection_is_one_step() {
+    let mut app = KitbashApp {
+        layers: vec![layer(0), layer(1), layer(2)],
+        selection: [0, 2].into_iter().collect(),
+        ..Default::default()
+    };
+    app.record_pending_edits("Edit");
+    app.delete_layers(&app.selected_ids());
+    assert_eq!(layer_ids(&app), vec![1]);
+    assert_eq!(undo_labels(&app), ["Delete layer"]);
+    app.undo();
+    assert_eq!(layer_ids(&app), vec![0, 1, 2]);
+}
+
+#[test]
+fn moving_a_row_is_one_step() {
+    let mut app = KitbashApp {
+        layers: vec![layer(0), layer(1), layer(2)],
+        ..Default::default()
+    };
+    app.record_pending_edits("Edit");
+    app.move_layer_to_index(0, 2);
+    assert_eq!(layer_ids(&app), vec![1, 2, 0]);
+    assert_eq!(undo_labels(&app), ["Move layer"]);
+    // Dropping a row where it already is records nothing
+    app.move_layer_to_index(0, 2);
+    assert_eq!(app.history.undo.len(), 1);
+}
+
+#[test]
+fn nested_transactions_fold_into_the_outermost() {
+    let mut app = KitbashApp {
+        layers: vec![layer(0), layer(1)],
+        ..Default::default()
+    };
+    app.record_pending_edits("Edit");
+    app.begin_transaction("Outer");
+    app.layers[0].visible = false;
+    app.delete_layers(&[1]);
+    app.move_layer_to_index(0, 0);
+    app.layers[0].locked = true;
+    app.commit_transaction();
+    assert_eq!(undo_labels(&app), ["Outer"]);
+    app.undo();
+    assert_eq!(layer_ids(&app), vec![0, 1]);
+    assert!(app.layers[0].visible && !app.layers[0].locked);
+}
+
+/// Each layer's id, source size and source pixels
+fn source_pixels(app: &KitbashApp) -> Vec<(u64, [u32; 2], Vec<u8>)> {
+    app.layers
+        .iter()
+        .map(|l| {
+            let size = [l.source_image.width(), l.source_image.height()];
+            (l.id, size, l.source_image.as_bytes().to_vec())
+        })
+        .collect()
+}
+
+#[test]
+fn undo_and_redo_restore_source_pixels_exactly() {
+    let gradient = RgbaImage::from_fn(5, 3, |x, y| Rgba([x as u8 * 50, y as u8 * 80, 7, 255]));
+    let mut scaled = LayerImage::new(0, "scaled".to_owned(), gradient.into());
+    scaled.transform.scale_x = 2.0;
+    scaled.transform.scale_y = 3.0;
+    let mut sprite = solid_layer(1, 4, [10, 200, 30, 128]);
+    sprite.transform.offset = egui::vec2(3.0, 2.0);
+    let mut app = KitbashApp {
+        canvas_size: [16, 16],
+        layers: vec![scaled, sprite],
+        next_id: 2,
+        ..Default::default()
+    };
+    app.record_pending_edits("Edit");
+    let original = source_pixels(&app);
+
+    app.begin_transaction("Bake transform");
+    app.layers[0].bake_scale();
+    app.commit_transaction();
+    let baked = source_pixels(&app);
+    assert_eq!(baked[0].1, [10, 9]);
+
+    app.merge_layers(&[0, 1]).unwrap();
+    let merged = source_pixels(&app);
+    assert_eq!(merged.len(), 1);
+
+    app.undo();
+    assert_eq!(source_pixels(&app), baked);
+    app.undo();
+    assert_eq!(source_pixels(&app), original);
+    app.redo();
+    assert_eq!(source_pixels(&app), baked);
+    app.redo();
+    assert_eq!(source_pixels(&app), merged);
+}
+
+#[test]
+fn a_canvas_drag_over_many_frames_is_one_step() {
+    let app = KitbashApp {
+        canvas_size: [16, 16],
+        layers: vec![solid_layer(0, 16, [200, 40, 40, 255])],
+        next_id: 1,
+        ..Default::default()
+    };
+    let mut harness = ui_harness(egui::vec2(1200.0, 800.0), app);
+    harness.state_mut().record_pending_edits("Edit");
+    // The canvas fills the middle of the view left of the control panel
+    let (panel_x, _) = node_origin(&harness, "Kitbash Config");
+    let start = egui::pos2(panel_x as f32 / 2.0, 400.0);
+    let press = |pos, pressed| egui::Event::PointerButton {
+        pos,
+        button: egui::PointerButton::Primary,
+        pressed,
+        modifiers: egui::Modifiers::NONE,
+    };
+    harness
+        .input_mut()
+        .events
+        .push(egui::Event::PointerMoved(start));
+    harness.input_mut().events.push(press(start, true));
+    harness.step();
+    let mut pos = start;
+    for _ in 0..10 {
+        pos += egui::vec2(8.0, 4.0);
+        harness
+            .input_mut()
+            .events
+            .push(egui::Event::PointerMoved(pos));
+        harness.step();
+    }
+    harness.input_mut().events.push(press(pos, false));
+    harness.step();
+    harness.step();
+
+    let app = harness.state();
+    assert_ne!(app.layers[0].transform.offset, egui::Vec2::ZERO);
+    assert_eq!(undo_labels(app), ["Move layer"]);
+}
+
+#[test]
+fn edits_during_an_export_do_not_reach_its_output() {
+    use std::sync::atomic::AtomicUsize;
+    use std::time::{Duration, Instant};
+
+    let mut app = KitbashApp {
+        canvas_size: [8, 8],
+        layers: vec![
+            solid_layer(0, 8, [200, 40, 40, 255]),
+            solid_layer(1, 4, [40, 200, 40, 255]),
+            solid_layer(2, 2, [40, 40, 200, 128]),
+        ],
+        ..Default::default()
+    };
+    let snapshot = app.export_snapshot();
+    let expected_hash = snapshot.content_hash();
+    let expected_pixels = snapshot.composite().into_raw();
+
+    // A slow fake export that re-reads the snapshot every step until enough edits are in
+    let edits = Arc::new(AtomicUsize::new(0));
+    let seen_edits = edits.clone();
+    app.start_export("Stress", move |progress| async move {
+        let mut hashes = Vec::new();
+        for step in 0..10_000 {
+            progress.step(step, 10_000).await?;
+            hashes.push(snapshot.content_hash());
+            if seen_edits.load(Ordering::Relaxed) >= 200 {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        hashes.dedup();
+        Ok(vec![
+            ("hashes".to_owned(), hashes.join(",").into_bytes()),
+            ("pixels".to_owned(), snapshot.composite().into_raw()),
+        ])
+    });
+
+    let deadline = Instant::now() + Duration::from_secs(30);
+    let files = 'wait: loop {
+        assert!(Instant::now() < deadline, "export never finished");
+        for event in app.events.drain() {
+            match event {
+                AppEvent::Export(ExportEvent::Finished(files)) => break 'wait files,
+                AppEvent::Export(ExportEvent::Failed(err)) => panic!("{err}"),
+                _ => {}
+            }
+        }
+        // Edit pixels in place (copy-on-write against the snapshot) and swap sources
+        let n = edits.fetch_add(1, Ordering::Relaxed);
+        for layer in &mut app.layers {
+            let img = Arc::make_mut(&mut layer.source_image);
+            let pixels = img.as_mut_rgba8().unwrap();
+            let (w, h) = pixels.dimensions();
+            pixels.put_pixel(n as u32 % w, n as u32 / w % h, Rgba([n as u8, 0, 0, 255]));
+        }
+        if n.is_multiple_of(10) {
+            let img = RgbaImage::from_pixel(3, 3, Rgba([0, n as u8, 0, 255]));
+            app.layers[1].replace_source(image::DynamicImage::ImageRgba8(img), false);
+        }
+        std::thread::sleep(Duration::from_micros(200));
+    };
+
+    assert!(edits.load(Ordering::Relaxed) >= 200);
+    assert_ne!(app.export_snapshot().content_hash(), expected_hash);
+    assert_eq!(files[0].1, expected_hash.into_bytes());
+    assert_eq!(files[1].1, expected_pixels);
+}
+
+fn ui_harness(size: egui::Vec2, app: KitbashApp) -> egui_kittest::Harness<'static, KitbashApp> {
+    let mut harness = egui_kittest::Harness::builder()
+        .with_size(size)
+        .build_state(|ctx, app: &mut KitbashApp| app.ui(ctx), app);
+    harness.run();
+    harness
+}
+
+fn ui_test_app() -> KitbashApp {
+    KitbashApp {
+        canvas_size: [16, 16],
+        layers: vec![
+            solid_layer(0, 8, [200, 40, 40, 255]),
+            solid_layer(1, 4, [40, 200, 40, 255]),
+            solid_layer(2, 4, [40, 40, 200, 255]),
+        ],
+        next_id: 3,
+        ..Default::default()
+    }
+}
+
+/// Top-left corner of the one node labeled `label`
+fn node_origin(harness: &egui_kittest::Harness<'_, KitbashApp>, label: &str) -> (f64, f64) {
+    use egui_kittest::kittest::Queryable;
+    let bounds = harness.get_by_label(label).bounding_box().unwrap();
+    (bounds.x0, bounds.y0)
+}
+
+#[test]
+fn control_panel_sits_right_on_desktop() {
+    use egui_kittest::kittest::Queryable;
+    let harness = ui_harness(egui::vec2(1200.0, 800.0), ui_test_app());
+    for heading in ["Kitbash Config", "Layers", "Export (Scattered)"] {
+        let (x, _) = node_origin(&harness, heading);
+        assert!(x > 600.0, "{heading} at x = {x}");
+    }
+    // One row per layer, not one per frame or per pass
+    for name in ["layer0", "layer1", "layer2"] {
+        assert_eq!(harness.query_all_by_label(name).count(), 1, "{name}");
+    }
+}
+
+#[test]
+fn control_panel_sits_at_the_bottom_on_mobile() {
+    use egui_kittest::kittest::Queryable;
+    let harness = ui_harness(egui::vec2(400.0, 800.0), ui_test_app());
+    let (x, y) = node_origin(&harness, "Kitbash Config");
+    assert!(x < 50.0 && y > 400.0, "panel at ({x}, {y})");
+    // Scrolled out of view, but the export buttons are still there
+    for button in ["Download Individual PNGs", "Download ZIP"] {
+        assert!(harness.query_by_label(button).is_some(), "{button}");
+    }
+}
+
+#[test]
+fn properties_follow_the_selection() {
+    use egui_kittest::kittest::Queryable;
+    let mut harness = ui_harness(egui::vec2(1200.0, 800.0), ui_test_app());
+    assert!(harness.query_by_label("Select a layer to edit.").is_some());
+    assert!(harness.query_by_label_contains("Properties:").is_none());
+
+    harness.state_mut().select_only(1);
+    harness.run();
+    assert!(harness.query_by_label("Properties: layer1").is_some());
+    assert!(harness.query_by_label("Select a layer to edit.").is_none());
+
+    // Locked layers still show their properties, just not editable
+    harness.state_mut().layers[1].locked = true;
+    harness.run();
+    assert!(harness.query_by_label("Properties: layer1").is_some());
+}
+
+#[test]
+fn export_section_offers_every_download() {
+    use egui_kittest::kittest::Queryable;
+    let mut harness = ui_harness(egui::vec2(1200.0, 800.0), ui_test_app());
+    for button in [
+        "Download Individual PNGs",
+        "Download GIF Preview",
+        "Download ZIP",
+    ] {
+        assert!(harness.query_by_label(button).is_some(), "{button}");
+    }
+    assert!(harness
+        .query_by_label_contains("Will composite 3 of 3 layers")
+        .is_some());
+
+    // Viewers may still export
+    harness.state_mut().viewer_mode = true;
+    harness.run();
+    assert!(harness.query_by_label("Download ZIP").is_some());
+}
+
+/// eframe storage kept in memory, standing in for the one on disk between sessions
+#[derive(Default)]
+struct MemoryStorage(BTreeMap<String, String>);
+
+impl eframe::Storage for MemoryStorage {
+    fn get_string(&self, key: &str) -> Option<String> {
+        self.0.get(key).cloned()
+    }
+
+    fn set_string(&mut self, key: &str, value: String) {
+        self.0.insert(key.to_owned(), value);
+    }
+
+    fn flush(&mut self) {}
+}
+
+#[test]
+fn viewer_mode_is_kept_between_sessions() {
+    use eframe::App;
+    let mut storage = MemoryStorage::default();
+    assert!(!KitbashApp::restored(Some(&storage)).viewer_mode);
+
+    let mut app = KitbashApp {
+        viewer_mode: true,
+        ..Default::default()
+    };
+    app.save(&mut storage);
+    assert!(KitbashApp::restored(Some(&storage)).viewer_mode);
+}
+
+/// The same 5×3 pattern in several color types
+fn varied_sources() -> Vec<image::DynamicImage> {
+    let rgba = RgbaImage::from_fn(5, 3, |x, y| {
+        Rgba([
+            (x * 50) as u8,
+            (y * 90) as u8,
+            (x * y * 17) as u8,
+            (255 - x * 40) as u8,
+        ])
+    });
+    let rgba = image::DynamicImage::ImageRgba8(rgba);
+    vec![
+        image::DynamicImage::ImageRgb8(rgba.to_rgb8()),
+        image::DynamicImage::ImageLumaA8(rgba.to_luma_alpha8()),
+        image::DynamicImage::ImageRgba16(rgba.to_rgba16()),
+        image::DynamicImage::ImageRgba32F(rgba.to_rgba32f()),
+        rgba,
+    ]
+}
+
+/// `layer` with its source replaced by what the resampling pass makes of it at 1×
+fn presampled(layer: &LayerImage) -> LayerImage {
+    let (w, h) = (layer.source_image.width(), layer.source_image.height());
+    let resized = layer
+        .source_image
+        .resize_exact(w, h, FilterType::Nearest)
+        .to_rgba8();
+    LayerImage {
+        source_image: Arc::new(image::DynamicImage::ImageRgba8(resized)),
+        ..layer.clone()
+    }
+}
+
+#[test]
+fn unscaled_fast_path_matches_resampling() {
+    let mut layers = Vec::new();
+    for (i, source) in varied_sources().into_iter().enumerate() {
+        let mut layer = LayerImage::new(i as u64, format!("layer{i}"), source);
+        layer.tint = egui::Color32::from_rgb(250, 200, 150);
+        layer.transform.flip_h = i % 2 == 0;
+        layer.set_top_left(egui::vec2(i as f32, i as f32 * 2.0));
+        // The fast path is taken: the target size is the source size
+        let src = [layer.source_image.width(), layer.source_image.height()];
+        let rendered = render_layer_pixels(&layer, 1).unwrap();
+        let resampled = render_layer_pixels(&presampled(&layer), 1).unwrap();
+        assert_eq!(rendered.dimensions(), (src[0], src[1]));
+        assert_eq!(rendered, resampled, "source {i}");
+        layers.push(layer);
+    }
+
+    // Whole scenes, over backgrounds that take the fill and the no-fill path
+    let slow_layers: Vec<LayerImage> = layers.iter().map(presampled).collect();
+    let effects = OutputEffects::default();
+    for bg in [
+        egui::Color32::TRANSPARENT,
+        egui::Color32::from_rgba_unmultiplied(255, 0, 0, 0),
+        egui::Color32::from_rgba_premultiplied(30, 60, 90, 0), // Additive: no alpha, has color
+        egui::Color32::from_rgba_unmultiplied(10, 20, 30, 40),
+    ] {
+        let fast = composite_image([16, 16], &layers, 2, bg, &effects);
+        let slow = composite_image([16, 16], &slow_layers, 2, bg, &effects);
+        assert_eq!(fast, slow, "{bg:?}");
+        // Whether or not the fill is skipped, the buffer starts out as the fill loop's
+        let filled = RgbaImage::from_pixel(32, 32, Rgba(bg.to_srgba_unmultiplied()));
+        assert_eq!(
+            composite_image([16, 16], &[], 2, bg, &effects),
+            filled,
+            "{bg:?}"
+        );
+    }
+}
+
+#[test]
+fn project_name_belongs_to_its_document() {
+    let mut app = KitbashApp {
+        export_filename_template: "{project}".to_owned(),
+        ..Default::default()
+    };
+    assert_eq!(app.export_stem(), "Untitled 1");
+    app.project_name = "hero".to_owned();
+    assert_eq!(app.export_stem(), "hero");
+
+    // A new tab starts out named after itself
+    app.new_document();
+    assert_eq!(app.project_name, "");
+    assert_eq!(app.export_stem(), "Untitled 2");
+    app.project_name = "villain".to_owned();
+
+    app.switch_document(0);
+    assert_eq!(app.project_label(), "hero");
+    app.switch_document(1);
+    assert_eq!(app.project_label(), "villain");
+}
+
+#[test]
+fn zip_export_is_byte_for_byte_reproducible() {
+    let mut layers = vec![
+        solid_layer(0, 8, [200, 40, 40, 255]),
+        solid_layer(1, 3, [40, 200, 40, 128]),
+        solid_layer(2, 5, [40, 40, 200, 255]),
+    ];
+    layers[1].transform.offset = egui::vec2(1.3333, -0.6667);
+    layers[1].transform.scale_x = 1.5;
+    layers[2].source_image = layers[0].source_image.clone(); // Shared source
+    layers[2].user_data = [
+        ("b".to_owned(), "2".to_owned()),
+        ("a".to_owned(), "1".to_owned()),
+    ]
+    .into_iter()
+    .collect();
+    let app = KitbashApp {
+        canvas_size: [16, 16],
+        layers,
+        markers: vec![Marker {
+            name: "hand".to_owned(),
+            position: egui::vec2(2.5, 3.25),
+            layer: Some(1),
+        }],
+        notes: ProjectNotes {
+            text: "export at 2x".to_owned(),
+            show_on_open: true,
+        },
+        export_sources: true,
+        export_scale: 2,
+        zip_max_entries: 2, // Split into several packs
+        ..Default::default()
+    };
+
+    let snapshot = app.export_snapshot();
+    let first = layer_zips(&snapshot);
+    let second = layer_zips(&snapshot);
+    assert!(first.len() > 1);
+    assert_eq!(first, second);
+    // A fresh snapshot of the unchanged document exports the same bytes too
+    std::thread::sleep(std::time::Duration::from_millis(1100));
+    let later = layer_zips(&app.export_snapshot());
+    assert_eq!(first, later);
+}
+
+#[test]
+fn a_panicking_export_reports_failure() {
+    let mut app = KitbashApp::default();
+    app.start_export("Broken", |progress| async move {
+        progress.step(0, 1).await?;
+        panic!("layer {} vanished", 7);
+    });
+    let deadline = std::time::Instant::now() + std::time::Duration::from_secs(10);
+    let message = 'wait: loop {
+        assert!(
+            std::time::Instant::now() < deadline,
+            "no result from the export"
+        );
+        for event in app.events.drain() {
+            match event {
+                AppEvent::Export(ExportEvent::Failed(message)) => break 'wait message,
+                AppEvent::Export(ExportEvent::Progress(_)) => {}
+                _ => panic!("unexpected export result"),
+            }
+        }
+        std::thread::sleep(std::time::Duration::from_millis(5));
+    };
+    assert_eq!(message, "Export crashed: layer 7 vanished");
+}
+
+#[test]
+fn sources_are_shared_only_when_pixels_match() {
+    let red = [255, 0, 0, 255];
+    let mut touched = RgbaImage::from_pixel(4, 4, Rgba(red));
+    touched.put_pixel(3, 3, Rgba([255, 0, 0, 254]));
+    let rgb = image::RgbImage::from_pixel(4, 4, image::Rgb([255, 0, 0]));
+    let base = solid_layer(0, 4, red);
+    let layers = vec![
+        base.clone(),
+        LayerImage::new(
+            1,
+            "same pixels".to_owned(),
+            image::DynamicImage::ImageRgb8(rgb),
+        ),
+        LayerImage::new(
+            2,
+            "same bytes".to_owned(),
+            image::DynamicImage::ImageRgba8(RgbaImage::from_pixel(2, 8, Rgba(red))),
+        ),
+        LayerImage::new(
+            3,
+            "touched".to_owned(),
+            image::DynamicImage::ImageRgba8(touched),
+        ),
+        LayerImage {
+            id: 4,
+            ..base // Shares the source Arc
+        },
+    ];
+    let app = KitbashApp {
+        layers,
+        next_id: 5,
+        ..Default::default()
+    };
+    let snapshot = app.export_snapshot();
+    let order = draw_order(&snapshot.layers);
+    let (files, entries) = snapshot.source_files(&order);
+    assert_eq!(
+        files,
+        [
+            "sources/layer0.png",
+            "sources/layer0.png",
+            "sources/same bytes.png",
+            "sources/touched.png",
+            "sources/layer0.png",
+        ]
+    );
+    let firsts: Vec<usize> = entries.iter().map(|(_, idx)| *idx).collect();
+    assert_eq!(firsts, [0, 2, 3]);
+}
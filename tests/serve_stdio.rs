@@ -0,0 +1,97 @@
+// Drives `kitbash --serve-stdio` end to end: requests in on stdin, one JSON line per
+// answer on stdout, files on disk.
+
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, Write};
+use std::process::{Command, Stdio};
+
+/// Run the server on `requests` and collect every response line
+fn serve(requests: &[Value]) -> Vec<Value> {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_kitbash"))
+        .arg("--serve-stdio")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("failed to start kitbash");
+
+    let mut stdin = child.stdin.take().unwrap();
+    for request in requests {
+        writeln!(stdin, "{}", request).unwrap();
+    }
+    drop(stdin);
+
+    let responses = BufReader::new(child.stdout.take().unwrap())
+        .lines()
+        .map(|line| serde_json::from_str(&line.unwrap()).unwrap())
+        .collect();
+    assert!(child.wait().unwrap().success());
+    responses
+}
+
+fn scratch_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("kitbash-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn composites_a_layer_at_its_offset() {
+    let dir = scratch_dir("serve-stdio");
+    let input = dir.join("dot.png");
+    let output = dir.join("out.png");
+    image::RgbaImage::from_pixel(2, 2, image::Rgba([255, 0, 0, 255]))
+        .save(&input)
+        .unwrap();
+
+    let responses = serve(&[
+        json!({ "id": 1, "method": "set_canvas", "params": { "width": 16, "height": 16 } }),
+        json!({ "id": 2, "method": "add_layer", "params": { "path": input, "name": "dot" } }),
+        json!({ "id": 3, "method": "set_offset", "params": { "name": "dot", "x": 2, "y": 1 } }),
+        json!({ "id": 4, "method": "list_layers" }),
+        json!({ "id": 5, "method": "composite", "params": { "path": output, "scale": 2 } }),
+        json!({ "id": 6, "method": "quit" }),
+        json!({ "id": 7, "method": "list_layers" }),
+    ]);
+
+    assert_eq!(responses.len(), 6, "nothing is answered after quit");
+    for (i, response) in responses.iter().enumerate() {
+        assert_eq!(response["id"], i + 1);
+        assert_eq!(response["ok"], true, "{}", response);
+    }
+    assert_eq!(
+        responses[3]["result"],
+        json!([{ "id": 0, "name": "dot", "visible": true }])
+    );
+    assert_eq!(responses[4]["result"]["width"], 32);
+    assert_eq!(responses[4]["result"]["height"], 32);
+
+    let img = image::open(&output).unwrap().to_rgba8();
+    assert_eq!(img.dimensions(), (32, 32));
+    for (x, y, pixel) in img.enumerate_pixels() {
+        let covered = (4..8).contains(&x) && (2..6).contains(&y);
+        assert_eq!(pixel[3] == 255, covered, "pixel {},{}", x, y);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn reports_bad_requests_and_keeps_serving() {
+    let responses = serve(&[
+        json!("not a request"),
+        json!({ "id": "a", "method": "explode" }),
+        json!({ "id": "b", "method": "set_visible", "params": { "name": "missing", "visible": false } }),
+        json!({ "id": "c", "method": "set_canvas", "params": { "width": 0, "height": 64 } }),
+        json!({ "id": "d", "method": "set_canvas", "params": { "width": 64, "height": 4097 } }),
+        json!({ "id": "e", "method": "list_layers" }),
+    ]);
+
+    let errors: Vec<_> = responses[..5].iter().map(|r| r["error"].clone()).collect();
+    assert!(responses[..5].iter().all(|r| r["ok"] == false));
+    assert_eq!(responses[0]["id"], Value::Null);
+    assert_eq!(errors[1], "Unknown method: explode");
+    assert_eq!(errors[2], "No layer named 'missing'");
+    assert_eq!(errors[3], "Canvas size must be 16 to 4096 pixels");
+    assert_eq!(errors[4], "Canvas size must be 16 to 4096 pixels");
+    assert_eq!(responses[5]["result"], json!([]));
+}